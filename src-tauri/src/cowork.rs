@@ -1,8 +1,128 @@
-use crate::database::Database;
+use crate::cowork_approvals::{self, ApprovalRequest, PermissionRule};
+use crate::cowork_attachments::{self, Attachment, AttachmentStore};
+use crate::cowork_context::{self, ContextPlan, SummaryMetadata};
+use crate::cowork_documents;
+use crate::cowork_images::{self, ImageInput};
+use crate::cowork_export::{ExportFormat, SessionBundle};
+use crate::cowork_memory;
+use crate::cowork_memory_sync::{self, MemorySyncConflict, SyncAction, SyncState};
+use crate::cowork_prompt::{self, ChatImage, ChatMessage, ChatRequest};
+use crate::cowork_snapshots::{self, FileSnapshot, RevertedFile, SnapshotTurn};
+use crate::cowork_tools::{self, ToolCall, ToolEvent};
+use crate::cowork_usage::{self, PriceTable, UsageGroup, UsageSummary};
+use crate::cowork_workspaces::{self, WorkspaceInstructions};
+use crate::database::{Database, MemoryScope, MessageSearchFilter, SessionListFilter};
 use crate::goclaw::GoClawManager;
+use crate::skills::SkillsManager;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{mpsc, Mutex};
+
+/// GoClaw 推送增量回复时使用的通知方法名
+const STREAM_DELTA_METHOD: &str = "chat.delta";
+/// 控制每轮回复后是否自动提取记忆的配置项
+const MEMORY_AUTO_EXTRACT_KEY: &str = "memory_auto_extract";
+/// 控制是否把相关记忆注入提示词的配置项
+const MEMORY_RETRIEVAL_KEY: &str = "memory_retrieval";
+const MEMORY_TOP_K_KEY: &str = "memory_top_k";
+const MEMORY_TOKEN_BUDGET_KEY: &str = "memory_token_budget";
+const MEMORY_DUPLICATE_THRESHOLD_KEY: &str = "memory_duplicate_threshold";
+/// 控制是否在后台把记忆与数据目录下的 memories.md 双向同步的配置项
+const MEMORY_FILE_SYNC_KEY: &str = "memory_file_sync";
+/// 控制是否在第一轮回复后自动生成会话标题的配置项
+const AUTO_TITLE_KEY: &str = "auto_title";
+/// 图片附件长边的最大像素，超过时缩小后再保存
+const IMAGE_MAX_DIMENSION_KEY: &str = "image_max_dimension";
+/// 文档附件提取出的文字注入请求时单个文档的最大字符数
+const DOCUMENT_MAX_CHARS_KEY: &str = "document_max_chars";
+/// 用量费用计算使用的价格表（JSON，见 PriceTable）
+const USAGE_PRICE_TABLE_KEY: &str = "usage_price_table";
+/// 每个会话保留文件快照的回复轮数，0 表示不保存快照
+const SNAPSHOT_RETENTION_KEY: &str = "snapshot_retention";
+/// 控制是否把工作区根目录的说明文件（如 AGENTS.md）注入系统提示词的配置项
+const WORKSPACE_INSTRUCTIONS_KEY: &str = "workspace_instructions";
+/// 依次查找的说明文件名，逗号分隔
+const WORKSPACE_INSTRUCTION_FILES_KEY: &str = "workspace_instruction_files";
+const WORKSPACE_INSTRUCTIONS_MAX_CHARS_KEY: &str = "workspace_instructions_max_chars";
+/// 后台检查 memories.md 是否被修改的间隔
+const MEMORY_FILE_POLL_INTERVAL: Duration = Duration::from_secs(3);
+/// 后台检查工作区说明文件是否被修改的间隔
+const INSTRUCTIONS_POLL_INTERVAL: Duration = Duration::from_secs(3);
+/// 流式回复过程中部分内容落盘的最小间隔
+const STREAM_PERSIST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 回复过程中 GoClaw 推送给会话的事件
+enum StreamEvent {
+    Delta(String),
+    Tool(ToolEvent),
+    Approval(ApprovalRequest),
+    /// 用户已答复审批，重新开始计算空闲超时
+    ApprovalAnswered,
+}
+
+/// 正在生成回复的会话
+struct ActiveStream {
+    events: mpsc::UnboundedSender<StreamEvent>,
+    /// GoClaw 请求 id，请求发出前为 None
+    request_id: Option<String>,
+    cancelled: bool,
+}
+
+type StreamRegistry = Arc<std::sync::Mutex<HashMap<String, ActiveStream>>>;
+
+/// 会话的一次回复从开始处理到结束期间占用的登记项，drop 时自动注销
+struct ReplyGuard {
+    streams: StreamRegistry,
+    session_id: String,
+    events: mpsc::UnboundedReceiver<StreamEvent>,
+    active: bool,
+}
+
+impl ReplyGuard {
+    /// 注销登记并返回回复是否被取消，之后到达的事件不再投递
+    fn finish(&mut self) -> bool {
+        if !self.active {
+            return false;
+        }
+        self.active = false;
+        self.streams
+            .lock()
+            .unwrap()
+            .remove(&self.session_id)
+            .map(|stream| stream.cancelled)
+            .unwrap_or(false)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.streams
+            .lock()
+            .unwrap()
+            .get(&self.session_id)
+            .map(|stream| stream.cancelled)
+            .unwrap_or(false)
+    }
+}
+
+impl Drop for ReplyGuard {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+/// 等待用户决定的工具审批请求
+struct PendingApproval {
+    request: ApprovalRequest,
+    /// 发起请求的助手回复消息 id，批准时用于记录文件快照
+    turn_id: String,
+}
+
+/// 等待中的审批请求，按审批 id 索引
+type ApprovalRegistry = Arc<std::sync::Mutex<HashMap<String, PendingApproval>>>;
+/// 已读取过的工作区说明文件，按工作区路径索引，None 表示该工作区没有说明文件
+type InstructionsCache = Arc<std::sync::Mutex<HashMap<String, Option<WorkspaceInstructions>>>>;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CoworkSession {
@@ -16,6 +136,75 @@ pub struct CoworkSession {
     pub active_skill_ids: Option<String>,
     pub created_at: i64,
     pub updated_at: i64,
+    #[serde(default)]
+    pub context_budget: Option<i64>,
+    /// 分支来源会话，非分支会话为 None
+    #[serde(default)]
+    pub parent_session_id: Option<String>,
+    /// 分支点在来源会话中的消息序号
+    #[serde(default)]
+    pub fork_sequence: Option<i32>,
+    /// auto 为自动生成的标题，manual 为用户修改过的标题，None 为创建时的默认标题
+    #[serde(default)]
+    pub title_source: Option<String>,
+    #[serde(default)]
+    pub folder_id: Option<String>,
+    /// 归档时间，未归档为 None
+    #[serde(default)]
+    pub archived_at: Option<i64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// 可复用的会话配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoworkTemplate {
+    pub id: String,
+    pub name: String,
+    pub cwd: Option<String>,
+    pub system_prompt: Option<String>,
+    pub execution_mode: Option<String>,
+    pub active_skill_ids: Option<String>,
+    pub context_budget: Option<i64>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// 分页列出会话的结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoworkSessionPage {
+    pub sessions: Vec<CoworkSession>,
+    /// 满足过滤条件的会话总数
+    pub total: i64,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoworkFolder {
+    pub id: String,
+    pub name: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub session_count: i64,
+}
+
+/// 以目录为键的工作区，由会话的 cwd 汇总而来
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoworkWorkspace {
+    pub path: String,
+    pub name: String,
+    pub session_count: i64,
+    /// 未归档的会话数
+    pub active_session_count: i64,
+    pub updated_at: i64,
+    /// 工作区根目录中会被注入系统提示词的说明文件
+    pub instructions_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoworkTag {
+    pub tag: String,
+    pub session_count: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,11 +228,98 @@ pub struct UserMemory {
     pub created_at: i64,
     pub updated_at: i64,
     pub last_used_at: Option<i64>,
+    /// preference / fact / habit，手动创建的记忆为 None
+    #[serde(default)]
+    pub kind: Option<String>,
+    /// global / workspace / session
+    #[serde(default = "default_memory_scope")]
+    pub scope: String,
+    /// workspace 范围为工作目录路径，session 范围为会话 id
+    #[serde(default)]
+    pub scope_value: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+}
+
+fn default_memory_scope() -> String {
+    cowork_memory::SCOPE_GLOBAL.to_string()
+}
+
+/// 一次记忆文件同步的结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MemorySyncReport {
+    pub path: String,
+    pub created: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub conflicts: Vec<MemorySyncConflict>,
+    /// 是否重新写出了 memories.md
+    pub exported: bool,
+}
+
+impl MemorySyncReport {
+    fn has_changes(&self) -> bool {
+        self.created + self.updated + self.deleted > 0 || !self.conflicts.is_empty() || self.exported
+    }
+}
+
+/// 按 sequence 游标分页读取的消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoworkMessagePage {
+    /// 按 sequence 升序排列
+    pub messages: Vec<CoworkMessage>,
+    /// 翻页方向上是否还有更多消息
+    pub has_more: bool,
+}
+
+/// 全文检索命中的消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoworkSearchHit {
+    pub message_id: String,
+    pub session_id: String,
+    pub session_title: String,
+    pub r#type: String,
+    pub sequence: Option<i32>,
+    pub timestamp: i64,
+    /// 命中片段，关键词用 <mark></mark> 包裹
+    pub snippet: String,
+    /// bm25 相关度，越小越相关
+    pub rank: f64,
+}
+
+/// 解析消息 metadata，无效或为空时返回空对象
+fn parse_metadata(metadata: Option<&str>) -> serde_json::Value {
+    metadata
+        .and_then(|m| serde_json::from_str::<serde_json::Value>(m).ok())
+        .filter(|m| m.is_object())
+        .unwrap_or_else(|| serde_json::json!({}))
 }
 
+/// 把 `patch` 中的字段覆盖到 `base` 上，返回新的 metadata 对象
+fn merge_metadata(base: &serde_json::Value, patch: serde_json::Value) -> serde_json::Value {
+    let mut merged = if base.is_object() {
+        base.clone()
+    } else {
+        serde_json::json!({})
+    };
+    if let (Some(merged), serde_json::Value::Object(patch)) = (merged.as_object_mut(), patch) {
+        merged.extend(patch);
+    }
+    merged
+}
+
+#[derive(Clone)]
 pub struct CoworkManager {
     database: Arc<Mutex<Database>>,
     goclaw_manager: Option<Arc<Mutex<GoClawManager>>>,
+    skills_manager: Option<Arc<Mutex<SkillsManager>>>,
+    app_handle: Option<AppHandle>,
+    streams: StreamRegistry,
+    approvals: ApprovalRegistry,
+    instructions: InstructionsCache,
+    data_dir: Option<PathBuf>,
+    /// 防止后台轮询和手动触发的记忆文件同步同时进行
+    memory_sync_lock: Arc<Mutex<()>>,
 }
 
 impl CoworkManager {
@@ -51,6 +327,13 @@ impl CoworkManager {
         CoworkManager {
             database,
             goclaw_manager: None,
+            skills_manager: None,
+            app_handle: None,
+            streams: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            approvals: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            instructions: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            data_dir: None,
+            memory_sync_lock: Arc::new(Mutex::new(())),
         }
     }
 
@@ -58,79 +341,1127 @@ impl CoworkManager {
         self.goclaw_manager = Some(goclaw_manager);
     }
 
+    pub fn set_skills_manager(&mut self, skills_manager: Arc<Mutex<SkillsManager>>) {
+        self.skills_manager = Some(skills_manager);
+    }
+
+    pub fn set_app_handle(&mut self, app_handle: AppHandle) {
+        self.app_handle = Some(app_handle);
+    }
+
+    pub fn set_data_dir(&mut self, data_dir: PathBuf) {
+        self.data_dir = Some(data_dir);
+    }
+
+    fn data_dir(&self) -> anyhow::Result<&PathBuf> {
+        self.data_dir
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Data directory not configured"))
+    }
+
+    /// 返回需要注册到 GoClawManager 的通知回调，把增量回复、工具事件和审批请求分发给对应会话
+    pub fn notification_handler(&self) -> impl Fn(String, serde_json::Value) + Send + Sync + 'static {
+        let streams = self.streams.clone();
+        move |method, params| {
+            let (session_id, event) = if method == STREAM_DELTA_METHOD {
+                let session_id = params.get("session_id").and_then(|v| v.as_str());
+                let delta = params.get("delta").and_then(|v| v.as_str());
+                match (session_id, delta) {
+                    (Some(session_id), Some(delta)) => {
+                        (session_id.to_string(), StreamEvent::Delta(delta.to_string()))
+                    }
+                    _ => return,
+                }
+            } else if method == cowork_approvals::APPROVAL_REQUEST_METHOD {
+                match cowork_approvals::parse_request(&params, chrono::Utc::now().timestamp_millis()) {
+                    Some(request) => (request.session_id.clone(), StreamEvent::Approval(request)),
+                    None => return,
+                }
+            } else {
+                match cowork_tools::parse_notification(&method, &params) {
+                    Some((session_id, event)) => (session_id, StreamEvent::Tool(event)),
+                    None => return,
+                }
+            };
+            if let Some(stream) = streams.lock().unwrap().get(&session_id) {
+                let _ = stream.events.send(event);
+            }
+        }
+    }
+
+    fn emit(&self, event: &str, payload: serde_json::Value) {
+        if let Some(app_handle) = &self.app_handle {
+            let _ = app_handle.emit(event, payload);
+        }
+    }
+
     pub async fn send_message(
         &self,
         session_id: String,
         content: String,
+        attachments: Vec<Attachment>,
     ) -> anyhow::Result<CoworkMessage> {
-        let _user_msg =
+        Self::into_reply(self.send_message_with_error(session_id, content, attachments).await?)
+    }
+
+    fn into_reply((message, error): (CoworkMessage, Option<String>)) -> anyhow::Result<CoworkMessage> {
+        match error {
+            Some(e) => Err(anyhow::anyhow!("AI request failed: {}", e)),
+            None => Ok(message),
+        }
+    }
+
+    /// 保存用户消息（附件须已通过 store_attachment 存入，记录在消息 metadata.attachments 中）并生成回复
+    pub async fn send_message_with_error(
+        &self,
+        session_id: String,
+        content: String,
+        attachments: Vec<Attachment>,
+    ) -> anyhow::Result<(CoworkMessage, Option<String>)> {
+        let guard = self.begin_reply(&session_id)?;
+        if !attachments.is_empty() {
+            let store = self.attachment_store()?;
+            if let Some(missing) = attachments.iter().find(|a| !store.exists(&a.sha256)) {
+                return Err(anyhow::anyhow!("Attachment not found: {}", missing.sha256));
+            }
+        }
+        let user_msg =
             self.add_message(session_id.clone(), "user".to_string(), content.clone()).await?;
+        if !attachments.is_empty() {
+            let metadata = serde_json::json!({ "attachments": attachments }).to_string();
+            let db = self.database.lock().await;
+            db.cowork_update_message(&user_msg.id, &session_id, None, Some(&metadata))?;
+        }
+        self.generate_reply(guard, session_id, serde_json::json!({}), None).await
+    }
 
-        if let Some(goclaw_manager) = &self.goclaw_manager {
-            let goclaw = goclaw_manager.lock().await;
-            let params = serde_json::json!({ "content": content });
-            match goclaw.request("chat".to_string(), params).await {
-                Ok(response) => {
-                    let response_text = response.as_str().unwrap_or("消息已接收").to_string();
-                    let assistant_msg =
-                        self.add_message(session_id, "assistant".to_string(), response_text).await?;
-                    return Ok(assistant_msg);
+    /// 登记会话正在生成回复，使压缩历史等准备阶段也能被取消。
+    /// 同一会话同时只允许一条回复在处理中
+    fn begin_reply(&self, session_id: &str) -> anyhow::Result<ReplyGuard> {
+        let mut streams = self.streams.lock().unwrap();
+        if streams.contains_key(session_id) {
+            return Err(anyhow::anyhow!(
+                "A reply is already being generated for session {}",
+                session_id
+            ));
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        streams.insert(
+            session_id.to_string(),
+            ActiveStream {
+                events: tx,
+                request_id: None,
+                cancelled: false,
+            },
+        );
+        Ok(ReplyGuard {
+            streams: self.streams.clone(),
+            session_id: session_id.to_string(),
+            events: rx,
+            active: true,
+        })
+    }
+
+    /// 基于当前已存储的历史生成一条新的助手回复，`base_metadata` 会合并进回复的 metadata。
+    /// `reply` 为已预先插入的空回复（如重新生成时），它不会进入本轮请求的历史
+    async fn generate_reply(
+        &self,
+        mut guard: ReplyGuard,
+        session_id: String,
+        base_metadata: serde_json::Value,
+        reply: Option<CoworkMessage>,
+    ) -> anyhow::Result<(CoworkMessage, Option<String>)> {
+        let goclaw_manager = match &self.goclaw_manager {
+            Some(goclaw_manager) => goclaw_manager.clone(),
+            None => {
+                let mut fallback_msg = match reply {
+                    Some(reply) => reply,
+                    None => {
+                        self.add_message(session_id.clone(), "assistant".to_string(), String::new())
+                            .await?
+                    }
+                };
+                fallback_msg.content = "消息已接收".to_string();
+                let error = "GoClaw manager not available".to_string();
+                let metadata = merge_metadata(
+                    &base_metadata,
+                    serde_json::json!({ "status": "error", "error": error }),
+                )
+                .to_string();
+                let db = self.database.lock().await;
+                db.cowork_update_message(
+                    &fallback_msg.id,
+                    &session_id,
+                    Some(&fallback_msg.content),
+                    Some(&metadata),
+                )?;
+                fallback_msg.metadata = Some(metadata);
+                return Ok((fallback_msg, Some(error)));
+            }
+        };
+
+        let request = self
+            .build_chat_request(&session_id, reply.as_ref().map(|r| r.id.as_str()))
+            .await?;
+        let params = serde_json::to_value(&request)?;
+        let mut assistant_msg = match reply {
+            Some(reply) => reply,
+            None => {
+                self.add_message(session_id.clone(), "assistant".to_string(), String::new())
+                    .await?
+            }
+        };
+
+        // 准备请求（如压缩历史）期间已被取消时不再发出请求
+        let sent = !guard.is_cancelled();
+        let (partial, result, cancelled, used_tools) = if !sent {
+            guard.finish();
+            (String::new(), Err(anyhow::anyhow!("Cancelled")), true, false)
+        } else {
+            self.stream_reply(&goclaw_manager, &mut guard, &session_id, &assistant_msg.id, &base_metadata, params)
+                .await
+        };
+
+        let (final_content, metadata, error, usage) = match result {
+            _ if cancelled => {
+                // 取消前已生成的部分同样计费，按估算记录；请求未发出时没有用量
+                let usage = sent.then(|| cowork_usage::estimate_usage(&request, &partial, None));
+                (
+                    partial.clone(),
+                    merge_metadata(
+                        &base_metadata,
+                        serde_json::json!({
+                            "status": "cancelled",
+                            "cancelled_at": chrono::Utc::now().timestamp_millis(),
+                            "usage": usage,
+                        }),
+                    ),
+                    None,
+                    usage,
+                )
+            }
+            // 工具消息按流式正文中的位置穿插显示，用过工具时保留流式正文使位置保持有效
+            Ok(response) if used_tools && !partial.is_empty() => {
+                let usage = cowork_usage::usage_from_response(&response)
+                    .unwrap_or_else(|| cowork_usage::estimate_usage(&request, &partial, None));
+                (
+                    partial.clone(),
+                    merge_metadata(
+                        &base_metadata,
+                        serde_json::json!({ "status": "done", "usage": usage }),
+                    ),
+                    None,
+                    Some(usage),
+                )
+            }
+            Ok(response) => {
+                let text = Self::response_text(&response)
+                    .filter(|t| !t.is_empty())
+                    .unwrap_or_else(|| {
+                        if partial.is_empty() {
+                            "消息已接收".to_string()
+                        } else {
+                            partial.clone()
+                        }
+                    });
+                let usage = cowork_usage::usage_from_response(&response)
+                    .unwrap_or_else(|| cowork_usage::estimate_usage(&request, &text, None));
+                (
+                    text,
+                    merge_metadata(
+                        &base_metadata,
+                        serde_json::json!({ "status": "done", "usage": usage }),
+                    ),
+                    None,
+                    Some(usage),
+                )
+            }
+            Err(e) => {
+                let error_msg = format!("AI 请求失败: {}", e);
+                let text = if partial.is_empty() {
+                    error_msg.clone()
+                } else {
+                    partial.clone()
+                };
+                (
+                    text,
+                    merge_metadata(
+                        &base_metadata,
+                        serde_json::json!({ "status": "error", "error": error_msg }),
+                    ),
+                    Some(error_msg),
+                    None,
+                )
+            }
+        };
+
+        let metadata = metadata.to_string();
+        {
+            let db = self.database.lock().await;
+            db.cowork_update_message(
+                &assistant_msg.id,
+                &session_id,
+                Some(&final_content),
+                Some(&metadata),
+            )?;
+            if cancelled {
+                db.cowork_update_session(&session_id, None, None, Some("idle"), None, None, None, None)?;
+            }
+            if let Some(usage) = &usage {
+                if let Err(e) = db.cowork_usage_record(
+                    &assistant_msg.id,
+                    &session_id,
+                    usage.model.as_deref(),
+                    usage.prompt_tokens,
+                    usage.completion_tokens,
+                    usage.estimated,
+                ) {
+                    println!("[Cowork] Failed to record usage of {}: {}", assistant_msg.id, e);
                 }
+            }
+        }
+
+        self.emit(
+            "cowork:messageDone",
+            serde_json::json!({
+                "session_id": session_id,
+                "message_id": assistant_msg.id,
+                "content": final_content,
+                "error": error,
+                "cancelled": cancelled,
+            }),
+        );
+
+        if error.is_none() && !cancelled {
+            self.spawn_memory_extraction(session_id.clone(), assistant_msg.id.clone());
+            self.spawn_title_generation(session_id.clone());
+        }
+
+        assistant_msg.content = final_content;
+        assistant_msg.metadata = Some(metadata);
+        Ok((assistant_msg, error))
+    }
+
+    /// 修改一条用户消息并从该处重新请求回复。其后的消息会被删除，
+    /// `archive` 为 true 时先把原对话完整分支为一个新会话保留下来
+    pub async fn edit_and_resend(
+        &self,
+        session_id: String,
+        message_id: String,
+        content: String,
+        archive: bool,
+    ) -> anyhow::Result<CoworkMessage> {
+        let guard = self.begin_reply(&session_id)?;
+        let history = self.list_messages(session_id.clone()).await?;
+        let message = history
+            .iter()
+            .find(|m| m.id == message_id)
+            .ok_or_else(|| anyhow::anyhow!("Message not found: {}", message_id))?;
+        if message.r#type != "user" {
+            return Err(anyhow::anyhow!("Only user messages can be edited"));
+        }
+        let sequence = message
+            .sequence
+            .ok_or_else(|| anyhow::anyhow!("Message {} has no sequence", message_id))?;
+
+        let mut patch = serde_json::json!({ "edited_at": chrono::Utc::now().timestamp_millis() });
+        if archive {
+            let last_sequence = history.iter().filter_map(|m| m.sequence).max().unwrap_or(sequence);
+            let branch = self.fork_session(session_id.clone(), last_sequence, None).await?;
+            patch["archived_session_id"] = serde_json::Value::String(branch.id);
+        }
+        let metadata = merge_metadata(&parse_metadata(message.metadata.as_deref()), patch).to_string();
+
+        {
+            let db = self.database.lock().await;
+            let removed =
+                db.cowork_edit_message_truncating(&message_id, &session_id, sequence, &content, &metadata)?;
+            println!(
+                "[Cowork] Edited message {} of session {}, discarded {} later messages",
+                message_id, session_id, removed
+            );
+        }
+
+        Self::into_reply(
+            self.generate_reply(guard, session_id, serde_json::json!({}), None)
+                .await?,
+        )
+    }
+
+    /// 重新生成最后一条回复，之前的候选回复保存在新回复 metadata 的 alternates 中
+    pub async fn regenerate_reply(&self, session_id: String) -> anyhow::Result<CoworkMessage> {
+        let guard = self.begin_reply(&session_id)?;
+        let history = self.list_messages(session_id.clone()).await?;
+        let last_user = history
+            .iter()
+            .rposition(|m| m.r#type == "user")
+            .ok_or_else(|| anyhow::anyhow!("No user message to reply to"))?;
+        let previous = history[last_user..]
+            .iter()
+            .rev()
+            .find(|m| m.r#type == "assistant")
+            .ok_or_else(|| anyhow::anyhow!("No reply to regenerate"))?;
+
+        let previous_metadata = parse_metadata(previous.metadata.as_deref());
+        let mut alternates = previous_metadata
+            .get("alternates")
+            .and_then(|a| a.as_array())
+            .cloned()
+            .unwrap_or_default();
+        alternates.push(serde_json::json!({
+            "content": previous.content,
+            "status": previous_metadata.get("status").cloned().unwrap_or(serde_json::Value::Null),
+            "error": previous_metadata.get("error").cloned().unwrap_or(serde_json::Value::Null),
+            "timestamp": previous.timestamp,
+        }));
+
+        // 上一次回复产生的工具消息随之删除，新回复会重新执行
+        let delete_ids: Vec<&str> = history[last_user..]
+            .iter()
+            .filter(|m| m.id == previous.id || cowork_tools::is_tool_message(&m.r#type))
+            .map(|m| m.id.as_str())
+            .collect();
+        let base_metadata = serde_json::json!({ "alternates": alternates });
+        let metadata = merge_metadata(&base_metadata, serde_json::json!({ "status": "streaming" })).to_string();
+        let reply_id = format!("msg_{}", uuid::Uuid::new_v4());
+        let sequence = {
+            let db = self.database.lock().await;
+            db.cowork_replace_reply(&session_id, &delete_ids, &reply_id, &metadata)?
+        };
+        let reply = CoworkMessage {
+            id: reply_id,
+            session_id: session_id.clone(),
+            r#type: "assistant".to_string(),
+            content: String::new(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            metadata: Some(metadata),
+            sequence: Some(sequence),
+        };
+
+        Self::into_reply(
+            self.generate_reply(guard, session_id, base_metadata, Some(reply))
+                .await?,
+        )
+    }
+
+    /// 把回复切换为第 `index` 个候选，当前内容放回候选列表的同一位置
+    pub async fn select_alternate(
+        &self,
+        session_id: String,
+        message_id: String,
+        index: usize,
+    ) -> anyhow::Result<CoworkMessage> {
+        let mut message = self
+            .list_messages(session_id.clone())
+            .await?
+            .into_iter()
+            .find(|m| m.id == message_id)
+            .ok_or_else(|| anyhow::anyhow!("Message not found: {}", message_id))?;
+        let mut metadata = parse_metadata(message.metadata.as_deref());
+        let selected = metadata
+            .get("alternates")
+            .and_then(|a| a.get(index))
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Alternate {} not found", index))?;
+
+        metadata["alternates"][index] = serde_json::json!({
+            "content": message.content,
+            "status": metadata.get("status").cloned().unwrap_or(serde_json::Value::Null),
+            "error": metadata.get("error").cloned().unwrap_or(serde_json::Value::Null),
+            "timestamp": message.timestamp,
+        });
+        metadata["status"] = selected.get("status").cloned().unwrap_or(serde_json::Value::Null);
+        metadata["error"] = selected.get("error").cloned().unwrap_or(serde_json::Value::Null);
+        let content = selected
+            .get("content")
+            .and_then(|c| c.as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        let metadata = metadata.to_string();
+        {
+            let db = self.database.lock().await;
+            db.cowork_update_message(&message_id, &session_id, Some(&content), Some(&metadata))?;
+        }
+        message.content = content;
+        message.metadata = Some(metadata);
+        Ok(message)
+    }
+
+    /// 根据会话历史、系统提示词、技能和工作目录组装本轮 chat 请求，
+    /// 超出上下文预算时先把较早的历史压缩为摘要。`reply_id` 为本轮回复的消息，不计入历史
+    async fn build_chat_request(&self, session_id: &str, reply_id: Option<&str>) -> anyhow::Result<ChatRequest> {
+        let session = self.get_session(session_id.to_string()).await?;
+        let history: Vec<CoworkMessage> = self
+            .list_messages(session_id.to_string())
+            .await?
+            .into_iter()
+            .filter(|m| Some(m.id.as_str()) != reply_id)
+            .collect();
+        // 文档文字先并入消息正文，和正文一起计入上下文预算
+        let history = self.append_document_text(history).await;
+
+        let skills_prompt = match &self.skills_manager {
+            Some(skills_manager) => {
+                let skills_manager = skills_manager.lock().await.clone();
+                let skill_ids = cowork_prompt::parse_skill_ids(session.active_skill_ids.as_deref());
+                skills_manager
+                    .build_auto_routing_prompt_for(skill_ids.as_deref())
+                    .await
+                    .unwrap_or_else(|e| {
+                        println!("[Cowork] Failed to build skills prompt: {}", e);
+                        String::new()
+                    })
+            }
+            None => String::new(),
+        };
+
+        let memory_prompt = if self.config_enabled(MEMORY_RETRIEVAL_KEY, true).await {
+            let query = history
+                .iter()
+                .rev()
+                .find(|m| m.r#type == "user")
+                .map(|m| m.content.as_str())
+                .unwrap_or_default();
+            self.retrieve_memories(&session, query).await.unwrap_or_else(|e| {
+                println!("[Cowork] Failed to retrieve memories: {}", e);
+                String::new()
+            })
+        } else {
+            String::new()
+        };
+
+        let instructions_prompt = match session.cwd.as_deref().map(str::trim).filter(|c| !c.is_empty()) {
+            Some(cwd) if self.config_enabled(WORKSPACE_INSTRUCTIONS_KEY, true).await => self
+                .workspace_instructions(cwd.to_string())
+                .await
+                .map(|i| i.prompt_section())
+                .unwrap_or_default(),
+            _ => String::new(),
+        };
+
+        let sections = vec![instructions_prompt, memory_prompt, skills_prompt];
+        let system_prompt_tokens = cowork_prompt::build_system_prompt(&session, &sections)
+            .map(|p| cowork_context::estimate_tokens(&p))
+            .unwrap_or(0);
+        let budget = self.context_budget(&session).await;
+        let plan = cowork_context::plan_context(
+            &history,
+            (budget.max(0) as usize).saturating_sub(system_prompt_tokens),
+        );
+
+        let previous_summary = plan.summary.as_ref().map(|m| m.content.clone());
+        let summary = if plan.needs_summary() {
+            match self.compact_history(session_id, &plan).await {
+                Ok(summary) => Some(summary),
                 Err(e) => {
-                    let _error_msg = self.add_message(
-                        session_id.clone(),
-                        "assistant".to_string(),
-                        format!("请求失败: {}", e),
-                    ).await?;
-                    return Err(anyhow::anyhow!("AI request failed: {}", e));
+                    println!("[Cowork] Failed to compact history for {}: {}", session_id, e);
+                    previous_summary
+                }
+            }
+        } else {
+            previous_summary
+        };
+
+        let mut request = cowork_prompt::build_chat_request(
+            &session,
+            &plan.verbatim,
+            summary.as_deref(),
+            &sections,
+        );
+        // 附件以本地文件路径的形式交给 GoClaw 读取，图片另外以 data URL 内联供视觉模型使用
+        if let Ok(store) = self.attachment_store() {
+            for message in request.messages.iter_mut() {
+                for attachment in message.attachments.iter_mut() {
+                    let sha256 = &attachment.attachment.sha256;
+                    if !store.exists(sha256) {
+                        continue;
+                    }
+                    let path = store.path_for(sha256);
+                    attachment.path = Some(path.to_string_lossy().to_string());
+                    if attachment.attachment.is_image() {
+                        match std::fs::read(&path) {
+                            Ok(bytes) => message.images.push(ChatImage {
+                                name: attachment.attachment.name.clone(),
+                                mime_type: attachment.attachment.mime_type.clone(),
+                                data_url: cowork_images::data_url(&attachment.attachment.mime_type, &bytes),
+                            }),
+                            Err(e) => println!("[Cowork] Failed to read image attachment {}: {}", sha256, e),
+                        }
+                    }
                 }
             }
         }
+        Ok(request)
+    }
+
+    /// 检索对当前会话生效且与本轮输入相关的记忆并生成提示词段落，被选中的记忆会更新 last_used_at
+    async fn retrieve_memories(&self, session: &CoworkSession, query: &str) -> anyhow::Result<String> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let memories: Vec<UserMemory> = self
+            .list_user_memories()
+            .await?
+            .into_iter()
+            .filter(|m| cowork_memory::applies_to(m, session, now))
+            .collect();
+        let ranked = cowork_memory::rank_memories(query, &memories, now);
+        if ranked.is_empty() {
+            return Ok(String::new());
+        }
+
+        let top_k = self
+            .config_value(MEMORY_TOP_K_KEY)
+            .await
+            .unwrap_or(cowork_memory::DEFAULT_MEMORY_TOP_K);
+        let token_budget = self
+            .config_value(MEMORY_TOKEN_BUDGET_KEY)
+            .await
+            .unwrap_or(cowork_memory::DEFAULT_MEMORY_TOKEN_BUDGET);
+        let selected = cowork_memory::select_memories(&ranked, top_k, token_budget);
+        if selected.is_empty() {
+            return Ok(String::new());
+        }
+
+        let ids: Vec<&str> = selected.iter().map(|m| m.id.as_str()).collect();
+        {
+            let db = self.database.lock().await;
+            db.user_memories_mark_used(&ids)?;
+        }
+        Ok(cowork_memory::build_memory_prompt(&selected))
+    }
+
+    async fn context_budget(&self, session: &CoworkSession) -> i64 {
+        if let Some(budget) = session.context_budget {
+            return budget;
+        }
+        self.config_value("context_budget")
+            .await
+            .unwrap_or(cowork_context::DEFAULT_CONTEXT_BUDGET)
+    }
+
+    /// 读取并解析 cowork 配置，未设置或格式不正确时返回 None
+    async fn config_value<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        let db = self.database.lock().await;
+        db.cowork_config_get(key)
+            .ok()
+            .flatten()
+            .and_then(|v| v.trim().parse().ok())
+    }
+
+    /// 读取布尔型 cowork 配置，未设置时使用默认值
+    async fn config_enabled(&self, key: &str, default: bool) -> bool {
+        let db = self.database.lock().await;
+        db.cowork_config_get(key)
+            .ok()
+            .flatten()
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(default)
+    }
+
+    /// 发送一次不带会话上下文、非流式的 chat 请求，用于摘要、记忆提取等后台任务，
+    /// 返回去掉首尾空白后的回复，回复为空时返回 None
+    async fn complete(&self, request_session_id: String, prompt: String) -> anyhow::Result<Option<String>> {
+        let goclaw_manager = self
+            .goclaw_manager
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("GoClaw manager not available"))?;
+        let request = ChatRequest {
+            session_id: request_session_id,
+            content: prompt.clone(),
+            messages: vec![ChatMessage {
+                role: "user".to_string(),
+                content: prompt,
+                attachments: Vec::new(),
+                images: Vec::new(),
+            }],
+            system_prompt: None,
+            cwd: None,
+            execution_mode: None,
+            stream: false,
+        };
+
+        let pending = {
+            let goclaw = goclaw_manager.lock().await;
+            goclaw
+                .start_request("chat".to_string(), serde_json::to_value(&request)?)
+                .await?
+        };
+        let response = pending.wait().await?;
+        Ok(Self::response_text(&response)
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty()))
+    }
+
+    /// 请求模型把旧消息（连同上一份摘要）压缩为新摘要，并作为 summary 消息追加到会话末尾。
+    /// 消息序号保持不变（分页游标和分支点依赖它），摘要替代哪些消息由 metadata.covers_through 决定
+    async fn compact_history(&self, session_id: &str, plan: &ContextPlan) -> anyhow::Result<String> {
+        let covers_through = plan
+            .covers_through()
+            .ok_or_else(|| anyhow::anyhow!("Nothing to summarize"))?;
+
+        let prompt = cowork_context::build_summary_prompt(
+            plan.summary.as_ref().map(|m| m.content.as_str()),
+            &plan.to_summarize,
+        );
+        let summary = self
+            .complete(format!("{}:summary", session_id), prompt)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Empty summary response"))?;
+
+        let summary_msg = self
+            .add_message(
+                session_id.to_string(),
+                cowork_context::SUMMARY_MESSAGE_TYPE.to_string(),
+                summary.clone(),
+            )
+            .await?;
+        let metadata = serde_json::to_string(&SummaryMetadata::new(covers_through, &summary))?;
+        let db = self.database.lock().await;
+        db.cowork_update_message(&summary_msg.id, session_id, None, Some(&metadata))?;
 
-        let fallback_msg = self.add_message(
+        println!(
+            "[Cowork] Compacted {} messages of session {} into summary {}",
+            plan.to_summarize.len(),
             session_id,
-            "assistant".to_string(),
-            "消息已接收".to_string(),
-        ).await?;
-        Ok(fallback_msg)
+            summary_msg.id
+        );
+        Ok(summary)
     }
 
-    pub async fn send_message_with_error(
+    /// 发起 chat 请求并消费 GoClaw 推送的增量内容和工具事件，
+    /// 返回已收到的部分内容、最终结果、是否被取消以及是否记录了工具消息。
+    /// 工具消息的 metadata.reply_offset 记录事件发生时已收到的正文字符数，用于把工具调用穿插回正文中
+    /// 回复可能持续很久，因此只在超过请求超时仍没有任何推送时放弃请求，等待用户审批的时间不计入
+    async fn stream_reply(
         &self,
-        session_id: String,
-        content: String,
-    ) -> anyhow::Result<(CoworkMessage, Option<String>)> {
-        let _user_msg =
-            self.add_message(session_id.clone(), "user".to_string(), content.clone()).await?;
+        goclaw_manager: &Arc<Mutex<GoClawManager>>,
+        guard: &mut ReplyGuard,
+        session_id: &str,
+        message_id: &str,
+        base_metadata: &serde_json::Value,
+        params: serde_json::Value,
+    ) -> (String, anyhow::Result<serde_json::Value>, bool, bool) {
+        let mut partial = String::new();
+        let mut last_persist = Instant::now();
+        // call_id -> (工具调用消息 id, 调用, 正文位置)，收到结果后移除
+        let mut running_tools: HashMap<String, (String, ToolCall, usize)> = HashMap::new();
+        let mut used_tools = false;
 
-        if let Some(goclaw_manager) = &self.goclaw_manager {
+        let pending = {
             let goclaw = goclaw_manager.lock().await;
-            let params = serde_json::json!({ "content": content });
-            match goclaw.request("chat".to_string(), params).await {
-                Ok(response) => {
-                    let response_text = response.as_str().unwrap_or("消息已接收").to_string();
-                    let assistant_msg =
-                        self.add_message(session_id, "assistant".to_string(), response_text).await?;
-                    return Ok((assistant_msg, None));
+            goclaw.start_request("chat".to_string(), params).await
+        };
+
+        let result = match pending {
+            Err(e) => Err(e),
+            Ok(mut pending) => {
+                let request_id = pending.id().to_string();
+                let cancelled_early = match self.streams.lock().unwrap().get_mut(session_id) {
+                    Some(stream) => {
+                        stream.request_id = Some(request_id.clone());
+                        stream.cancelled
+                    }
+                    None => false,
+                };
+                // 请求发出前已被取消
+                if cancelled_early {
+                    let goclaw = goclaw_manager.lock().await;
+                    if let Err(e) = goclaw.cancel_request(&request_id).await {
+                        println!("[Cowork] Failed to cancel request {}: {}", request_id, e);
+                    }
                 }
-                Err(e) => {
-                    let error_msg = format!("AI 请求失败: {}", e);
-                    let assistant_msg =
-                        self.add_message(session_id, "assistant".to_string(), error_msg.clone()).await?;
-                    return Ok((assistant_msg, Some(error_msg)));
+
+                let idle_timeout = pending.timeout();
+                let idle = tokio::time::sleep(idle_timeout);
+                tokio::pin!(idle);
+                let outcome = loop {
+                    tokio::select! {
+                        result = pending.response() => break Some(result),
+                        _ = &mut idle => {
+                            // 等待用户审批期间 GoClaw 不会推送任何内容，不计入空闲超时
+                            if self.approval_pending(session_id) {
+                                idle.as_mut().reset(tokio::time::Instant::now() + idle_timeout);
+                                continue;
+                            }
+                            break None;
+                        }
+                        Some(event) = guard.events.recv() => {
+                            idle.as_mut().reset(tokio::time::Instant::now() + idle_timeout);
+                            match event {
+                                StreamEvent::Delta(delta) => {
+                                    self.on_delta(session_id, message_id, &delta, &mut partial);
+                                    if last_persist.elapsed() >= STREAM_PERSIST_INTERVAL {
+                                        self.persist_partial(session_id, message_id, base_metadata, &partial).await;
+                                        last_persist = Instant::now();
+                                    }
+                                }
+                                StreamEvent::Tool(event) => {
+                                    let offset = partial.chars().count();
+                                    used_tools |= self
+                                        .on_tool_event(session_id, message_id, event, offset, &mut running_tools)
+                                        .await;
+                                }
+                                StreamEvent::Approval(request) => self.on_approval_request(request, message_id).await,
+                                StreamEvent::ApprovalAnswered => {}
+                            }
+                        }
+                    }
+                };
+                match outcome {
+                    Some(result) => result,
+                    None => {
+                        println!(
+                            "[Cowork] Reply of session {} received nothing for {} ms, giving up",
+                            session_id,
+                            idle_timeout.as_millis()
+                        );
+                        pending.abandon().await;
+                        Err(anyhow::anyhow!("Request timeout"))
+                    }
                 }
             }
+        };
+
+        let cancelled = guard.finish();
+        while let Ok(event) = guard.events.try_recv() {
+            match event {
+                StreamEvent::Delta(delta) => self.on_delta(session_id, message_id, &delta, &mut partial),
+                StreamEvent::Tool(event) => {
+                    let offset = partial.chars().count();
+                    used_tools |= self
+                        .on_tool_event(session_id, message_id, event, offset, &mut running_tools)
+                        .await;
+                }
+                // 请求已结束，不再需要审批
+                StreamEvent::Approval(_) | StreamEvent::ApprovalAnswered => {}
+            }
+        }
+        let unanswered: Vec<ApprovalRequest> = {
+            let mut approvals = self.approvals.lock().unwrap();
+            let ids: Vec<String> = approvals
+                .values()
+                .filter(|a| a.request.session_id == session_id)
+                .map(|a| a.request.id.clone())
+                .collect();
+            ids.iter().filter_map(|id| approvals.remove(id)).map(|a| a.request).collect()
+        };
+        for request in unanswered {
+            self.resolve_approval(&request, false, cowork_approvals::SOURCE_CANCELLED, None).await;
         }
+        // 回复已结束但仍未收到结果的调用
+        let now = chrono::Utc::now().timestamp_millis();
+        for (_, (tool_msg_id, mut call, offset)) in running_tools {
+            call.finish(cowork_tools::STATUS_INTERRUPTED, now);
+            self.update_tool_call(session_id, message_id, &tool_msg_id, &call, offset).await;
+        }
+        self.record_missing_snapshots(session_id, message_id).await;
+        self.record_snapshot_results(session_id, message_id).await;
+        self.prune_snapshots(session_id).await;
 
-        let fallback_msg = self.add_message(
-            session_id,
-            "assistant".to_string(),
-            "消息已接收".to_string(),
-        ).await?;
-        Ok((
-            fallback_msg,
-            Some("GoClaw manager not available".to_string()),
-        ))
+        (partial, result, cancelled, used_tools)
+    }
+
+    /// 按已保存的规则自动处理审批请求，没有匹配的规则时等待用户通过 respond_approval 决定
+    async fn on_approval_request(&self, request: ApprovalRequest, turn_id: &str) {
+        let cwd = self
+            .get_session(request.session_id.clone())
+            .await
+            .ok()
+            .and_then(|s| s.cwd);
+        let rules = match self.list_permission_rules(Some(request.session_id.clone())).await {
+            Ok(rules) => rules,
+            Err(e) => {
+                println!("[Cowork] Failed to load permission rules: {}", e);
+                Vec::new()
+            }
+        };
+        if let Some(rule) = cowork_approvals::evaluate(&rules, &request, cwd.as_deref()) {
+            if rule.allows() {
+                self.snapshot_before_tool(&request.session_id, turn_id, &request.tool, &request.arguments)
+                    .await;
+            }
+            self.resolve_approval(&request, rule.allows(), cowork_approvals::SOURCE_RULE, Some(&rule.id))
+                .await;
+            return;
+        }
+
+        self.approvals.lock().unwrap().insert(
+            request.id.clone(),
+            PendingApproval {
+                request: request.clone(),
+                turn_id: turn_id.to_string(),
+            },
+        );
+        self.emit(
+            "cowork:approvalRequest",
+            serde_json::to_value(&request).unwrap_or(serde_json::Value::Null),
+        );
+    }
+
+    /// 会话是否有等待用户决定的审批
+    fn approval_pending(&self, session_id: &str) -> bool {
+        self.approvals
+            .lock()
+            .unwrap()
+            .values()
+            .any(|a| a.request.session_id == session_id)
+    }
+
+    /// 把审批结果发回 GoClaw（回复已结束时除外），写入审计记录并通知前端
+    async fn resolve_approval(&self, request: &ApprovalRequest, allow: bool, source: &str, rule_id: Option<&str>) {
+        if source != cowork_approvals::SOURCE_CANCELLED {
+            if let Some(goclaw_manager) = &self.goclaw_manager {
+                let goclaw = goclaw_manager.lock().await;
+                if let Err(e) = goclaw
+                    .send_notification(
+                        cowork_approvals::APPROVAL_RESPONSE_METHOD.to_string(),
+                        serde_json::json!({
+                            "id": request.id,
+                            "session_id": request.session_id,
+                            "approved": allow,
+                        }),
+                    )
+                    .await
+                {
+                    println!("[Cowork] Failed to send approval {}: {}", request.id, e);
+                }
+            }
+        }
+
+        let decision = if allow {
+            cowork_approvals::DECISION_ALLOW
+        } else {
+            cowork_approvals::DECISION_DENY
+        };
+        {
+            let db = self.database.lock().await;
+            if let Err(e) = db.cowork_record_approval(
+                &request.id,
+                &request.session_id,
+                &request.tool,
+                request.command.as_deref(),
+                request.path.as_deref(),
+                &request.arguments.to_string(),
+                decision,
+                source,
+                rule_id,
+                request.requested_at,
+            ) {
+                println!("[Cowork] Failed to record approval {}: {}", request.id, e);
+            }
+        }
+        println!(
+            "[Cowork] Approval {} for {} in session {}: {} ({})",
+            request.id, request.tool, request.session_id, decision, source
+        );
+        self.emit(
+            "cowork:approvalResolved",
+            serde_json::json!({
+                "id": request.id,
+                "session_id": request.session_id,
+                "decision": decision,
+                "source": source,
+                "rule_id": rule_id,
+            }),
+        );
+    }
+
+    /// 把工具事件保存为 tool_call / tool_result 消息并通知前端，返回是否写入了消息。
+    /// `offset` 为事件发生时回复正文已收到的字符数
+    async fn on_tool_event(
+        &self,
+        session_id: &str,
+        reply_id: &str,
+        event: ToolEvent,
+        offset: usize,
+        running_tools: &mut HashMap<String, (String, ToolCall, usize)>,
+    ) -> bool {
+        match event {
+            ToolEvent::Call { call_id, name, arguments } => {
+                let call = ToolCall {
+                    call_id: call_id.clone(),
+                    name,
+                    arguments,
+                    status: cowork_tools::STATUS_RUNNING.to_string(),
+                    started_at: chrono::Utc::now().timestamp_millis(),
+                    finished_at: None,
+                    duration_ms: None,
+                };
+                let metadata = serde_json::json!({ "tool_call": call, "reply_id": reply_id, "reply_offset": offset });
+                let tool_msg = match self
+                    .add_tool_message(session_id, cowork_tools::TOOL_CALL_MESSAGE_TYPE, call.summary(), metadata)
+                    .await
+                {
+                    Ok(tool_msg) => tool_msg,
+                    Err(e) => {
+                        println!("[Cowork] Failed to record tool call {}: {}", call_id, e);
+                        return false;
+                    }
+                };
+                self.emit(
+                    "cowork:toolCall",
+                    serde_json::json!({
+                        "session_id": session_id,
+                        "message_id": tool_msg.id,
+                        "reply_id": reply_id,
+                        "reply_offset": offset,
+                        "tool_call": call,
+                    }),
+                );
+                running_tools.insert(call_id, (tool_msg.id, call, offset));
+                true
+            }
+            ToolEvent::Result(mut result) => {
+                let call_msg_id = match running_tools.remove(&result.call_id) {
+                    Some((call_msg_id, mut call, call_offset)) => {
+                        let status = if result.is_error() {
+                            cowork_tools::STATUS_ERROR
+                        } else {
+                            cowork_tools::STATUS_DONE
+                        };
+                        call.finish(status, chrono::Utc::now().timestamp_millis());
+                        self.update_tool_call(session_id, reply_id, &call_msg_id, &call, call_offset).await;
+                        if result.name.is_empty() {
+                            result.name = call.name;
+                        }
+                        Some(call_msg_id)
+                    }
+                    None => None,
+                };
+                let metadata = serde_json::json!({
+                    "tool_result": result,
+                    "tool_call_message_id": call_msg_id,
+                    "reply_id": reply_id,
+                    "reply_offset": offset,
+                });
+                let tool_msg = match self
+                    .add_tool_message(session_id, cowork_tools::TOOL_RESULT_MESSAGE_TYPE, result.content(), metadata)
+                    .await
+                {
+                    Ok(tool_msg) => tool_msg,
+                    Err(e) => {
+                        println!("[Cowork] Failed to record tool result {}: {}", result.call_id, e);
+                        return call_msg_id.is_some();
+                    }
+                };
+                self.emit(
+                    "cowork:toolResult",
+                    serde_json::json!({
+                        "session_id": session_id,
+                        "message_id": tool_msg.id,
+                        "reply_id": reply_id,
+                        "reply_offset": offset,
+                        "tool_call_message_id": call_msg_id,
+                        "tool_result": result,
+                    }),
+                );
+                true
+            }
+        }
+    }
+
+    async fn add_tool_message(
+        &self,
+        session_id: &str,
+        msg_type: &str,
+        content: String,
+        metadata: serde_json::Value,
+    ) -> anyhow::Result<CoworkMessage> {
+        let mut tool_msg = self
+            .add_message(session_id.to_string(), msg_type.to_string(), content)
+            .await?;
+        let metadata = metadata.to_string();
+        let db = self.database.lock().await;
+        db.cowork_update_message(&tool_msg.id, session_id, None, Some(&metadata))?;
+        tool_msg.metadata = Some(metadata);
+        Ok(tool_msg)
+    }
+
+    /// 更新工具调用消息中的状态和耗时
+    async fn update_tool_call(
+        &self,
+        session_id: &str,
+        reply_id: &str,
+        tool_msg_id: &str,
+        call: &ToolCall,
+        offset: usize,
+    ) {
+        let metadata =
+            serde_json::json!({ "tool_call": call, "reply_id": reply_id, "reply_offset": offset }).to_string();
+        {
+            let db = self.database.lock().await;
+            if let Err(e) = db.cowork_update_message(tool_msg_id, session_id, None, Some(&metadata)) {
+                println!("[Cowork] Failed to update tool call {}: {}", tool_msg_id, e);
+            }
+        }
+        self.emit(
+            "cowork:toolCall",
+            serde_json::json!({
+                "session_id": session_id,
+                "message_id": tool_msg_id,
+                "reply_id": reply_id,
+                "reply_offset": offset,
+                "tool_call": call,
+            }),
+        );
+    }
+
+    /// 取消会话中正在生成的回复，没有进行中的回复时返回 false
+    pub async fn cancel(&self, session_id: String) -> anyhow::Result<bool> {
+        let request_id = match self.streams.lock().unwrap().get_mut(&session_id) {
+            Some(stream) => {
+                stream.cancelled = true;
+                stream.request_id.clone()
+            }
+            None => return Ok(false),
+        };
+
+        if let (Some(request_id), Some(goclaw_manager)) = (request_id, &self.goclaw_manager) {
+            let goclaw = goclaw_manager.lock().await;
+            goclaw.cancel_request(&request_id).await?;
+        }
+        println!("[Cowork] Cancelling reply of session {}", session_id);
+        Ok(true)
+    }
+
+    fn on_delta(&self, session_id: &str, message_id: &str, delta: &str, partial: &mut String) {
+        partial.push_str(delta);
+        self.emit(
+            "cowork:messageDelta",
+            serde_json::json!({
+                "session_id": session_id,
+                "message_id": message_id,
+                "delta": delta,
+            }),
+        );
+    }
+
+    async fn persist_partial(
+        &self,
+        session_id: &str,
+        message_id: &str,
+        base_metadata: &serde_json::Value,
+        partial: &str,
+    ) {
+        let metadata =
+            merge_metadata(base_metadata, serde_json::json!({ "status": "streaming" })).to_string();
+        let db = self.database.lock().await;
+        if let Err(e) =
+            db.cowork_update_message(message_id, session_id, Some(partial), Some(&metadata))
+        {
+            println!("[Cowork] Failed to persist partial reply {}: {}", message_id, e);
+        }
+    }
+
+    fn response_text(response: &serde_json::Value) -> Option<String> {
+        match response {
+            serde_json::Value::String(text) => Some(text.clone()),
+            serde_json::Value::Object(obj) => obj
+                .get("content")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+            _ => None,
+        }
     }
 
     pub async fn list_sessions(&self) -> anyhow::Result<Vec<CoworkSession>> {
@@ -143,6 +1474,161 @@ impl CoworkManager {
         Ok(sessions)
     }
 
+    /// 按标签、文件夹、状态、归档、工作目录和更新时间过滤并分页列出会话，
+    /// 未指定 archived 时默认不包含已归档会话
+    pub async fn list_sessions_page(
+        &self,
+        tag: Option<String>,
+        folder_id: Option<String>,
+        status: Option<String>,
+        archived: Option<bool>,
+        cwd: Option<String>,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> anyhow::Result<CoworkSessionPage> {
+        let filter = SessionListFilter {
+            tag: tag.as_deref(),
+            folder_id: folder_id.as_deref(),
+            status: status.as_deref(),
+            archived: Some(archived.unwrap_or(false)),
+            cwd: cwd.as_deref(),
+            workspace: None,
+            start_time,
+            end_time,
+            limit: limit.unwrap_or(50).min(200),
+            offset: offset.unwrap_or(0),
+        };
+        self.sessions_page(&filter).await
+    }
+
+    /// 分页列出工作区中的会话，工作区为归一化后的 cwd
+    pub async fn list_workspace_sessions(
+        &self,
+        workspace: String,
+        archived: Option<bool>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> anyhow::Result<CoworkSessionPage> {
+        let filter = SessionListFilter {
+            archived: Some(archived.unwrap_or(false)),
+            workspace: Some(cowork_workspaces::normalize_workspace(&workspace)),
+            limit: limit.unwrap_or(50).min(200),
+            offset: offset.unwrap_or(0),
+            ..Default::default()
+        };
+        self.sessions_page(&filter).await
+    }
+
+    async fn sessions_page(&self, filter: &SessionListFilter<'_>) -> anyhow::Result<CoworkSessionPage> {
+        let db = self.database.lock().await;
+        let (sessions_json, total) = db.cowork_list_sessions_page(filter)?;
+        let sessions: Vec<CoworkSession> = sessions_json
+            .into_iter()
+            .filter_map(|s| serde_json::from_value(s).ok())
+            .collect();
+        let has_more = i64::from(filter.offset) + (sessions.len() as i64) < total;
+        Ok(CoworkSessionPage {
+            sessions,
+            total,
+            has_more,
+        })
+    }
+
+    /// 列出所有工作区，最近有会话更新的在前
+    pub async fn list_workspaces(&self) -> anyhow::Result<Vec<CoworkWorkspace>> {
+        let rows = {
+            let db = self.database.lock().await;
+            db.cowork_list_workspaces()?
+        };
+        let file_names = self.instruction_file_names().await;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let path = row.get("path")?.as_str()?.to_string();
+                Some(CoworkWorkspace {
+                    name: cowork_workspaces::workspace_name(&path),
+                    session_count: row.get("session_count")?.as_i64()?,
+                    active_session_count: row.get("active_session_count")?.as_i64()?,
+                    updated_at: row.get("updated_at")?.as_i64()?,
+                    instructions_path: cowork_workspaces::find_instructions(&path, &file_names),
+                    path,
+                })
+            })
+            .collect())
+    }
+
+    async fn instruction_file_names(&self) -> Vec<String> {
+        let names = self
+            .config_value::<String>(WORKSPACE_INSTRUCTION_FILES_KEY)
+            .await
+            .map(|raw| cowork_workspaces::parse_file_names(&raw))
+            .unwrap_or_default();
+        if names.is_empty() {
+            cowork_workspaces::parse_file_names(cowork_workspaces::DEFAULT_INSTRUCTION_FILES)
+        } else {
+            names
+        }
+    }
+
+    /// 读取工作区的说明文件，文件未变化时使用缓存；内容与上次读取时不同则通知前端
+    pub async fn workspace_instructions(&self, workspace: String) -> Option<WorkspaceInstructions> {
+        let workspace = cowork_workspaces::normalize_workspace(&workspace).to_string();
+        let file_names = self.instruction_file_names().await;
+        let signature = cowork_workspaces::current_signature(&workspace, &file_names);
+        if let Some(cached) = self.instructions.lock().unwrap().get(&workspace) {
+            if cached.as_ref().map(|i| i.signature()) == signature {
+                return cached.clone();
+            }
+        }
+
+        let max_chars = self
+            .config_value::<usize>(WORKSPACE_INSTRUCTIONS_MAX_CHARS_KEY)
+            .await
+            .unwrap_or(cowork_workspaces::DEFAULT_INSTRUCTIONS_MAX_CHARS);
+        let loaded = cowork_workspaces::load_instructions(&workspace, &file_names, max_chars);
+        let previous = self
+            .instructions
+            .lock()
+            .unwrap()
+            .insert(workspace.clone(), loaded.clone());
+        if let Some(previous) = previous {
+            if previous.as_ref().map(|i| &i.content) != loaded.as_ref().map(|i| &i.content) {
+                println!("[Cowork] Reloaded instructions of workspace {}", workspace);
+                self.emit(
+                    "cowork:workspaceInstructionsChanged",
+                    serde_json::json!({
+                        "workspace": workspace,
+                        "path": loaded.as_ref().map(|i| i.path.clone()),
+                        "removed": loaded.is_none(),
+                    }),
+                );
+            }
+        }
+        loaded
+    }
+
+    /// 后台轮询已读取过的工作区说明文件，文件被修改、创建或删除时重新读取
+    pub async fn run_workspace_instructions_watcher(self) {
+        let mut interval = tokio::time::interval(INSTRUCTIONS_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let workspaces: Vec<String> = self.instructions.lock().unwrap().keys().cloned().collect();
+            for workspace in workspaces {
+                self.workspace_instructions(workspace).await;
+            }
+        }
+    }
+
+    pub async fn get_session(&self, id: String) -> anyhow::Result<CoworkSession> {
+        let db = self.database.lock().await;
+        let session_json = db
+            .cowork_get_session(&id)?
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", id))?;
+        Ok(serde_json::from_value(session_json)?)
+    }
+
     pub async fn create_session(
         &self,
         title: String,
@@ -151,70 +1637,908 @@ impl CoworkManager {
         execution_mode: Option<String>,
     ) -> anyhow::Result<CoworkSession> {
         let id = format!("session_{}", uuid::Uuid::new_v4());
-        let now = chrono::Utc::now().timestamp_millis();
+        {
+            let db = self.database.lock().await;
+            db.cowork_create_session(
+                &id,
+                &title,
+                cwd.as_deref(),
+                system_prompt.as_deref(),
+                execution_mode.as_deref(),
+            )?;
+        }
+        self.get_session(id).await
+    }
+
+    pub async fn list_templates(&self) -> anyhow::Result<Vec<CoworkTemplate>> {
+        let db = self.database.lock().await;
+        let templates_json = db.cowork_list_templates(None)?;
+        Ok(templates_json
+            .into_iter()
+            .filter_map(|t| serde_json::from_value(t).ok())
+            .collect())
+    }
+
+    pub async fn get_template(&self, id: String) -> anyhow::Result<CoworkTemplate> {
+        let db = self.database.lock().await;
+        let template_json = db
+            .cowork_list_templates(Some(&id))?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("Template not found: {}", id))?;
+        Ok(serde_json::from_value(template_json)?)
+    }
+
+    pub async fn create_template(
+        &self,
+        name: String,
+        cwd: Option<String>,
+        system_prompt: Option<String>,
+        execution_mode: Option<String>,
+        active_skill_ids: Option<String>,
+        context_budget: Option<i64>,
+    ) -> anyhow::Result<CoworkTemplate> {
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Template name cannot be empty"));
+        }
+        let id = format!("template_{}", uuid::Uuid::new_v4());
+        {
+            let db = self.database.lock().await;
+            db.cowork_create_template(
+                &id,
+                &name,
+                cwd.as_deref().filter(|c| !c.is_empty()),
+                system_prompt.as_deref().filter(|p| !p.is_empty()),
+                execution_mode.as_deref().filter(|m| !m.is_empty()),
+                active_skill_ids.as_deref().filter(|s| !s.is_empty()),
+                context_budget.filter(|b| *b > 0),
+            )?;
+        }
+        self.get_template(id).await
+    }
+
+    pub async fn update_template(
+        &self,
+        id: String,
+        name: Option<String>,
+        cwd: Option<String>,
+        system_prompt: Option<String>,
+        execution_mode: Option<String>,
+        active_skill_ids: Option<String>,
+        context_budget: Option<i64>,
+    ) -> anyhow::Result<CoworkTemplate> {
+        let name = name.map(|n| n.trim().to_string());
+        if name.as_deref() == Some("") {
+            return Err(anyhow::anyhow!("Template name cannot be empty"));
+        }
+        {
+            let db = self.database.lock().await;
+            if !db.cowork_update_template(
+                &id,
+                name.as_deref(),
+                cwd.as_deref(),
+                system_prompt.as_deref(),
+                execution_mode.as_deref(),
+                active_skill_ids.as_deref(),
+                context_budget,
+            )? {
+                return Err(anyhow::anyhow!("Template not found: {}", id));
+            }
+        }
+        self.get_template(id).await
+    }
+
+    pub async fn delete_template(&self, id: String) -> anyhow::Result<()> {
+        let db = self.database.lock().await;
+        db.cowork_delete_template(&id)?;
+        Ok(())
+    }
+
+    /// 把已有会话的系统提示词、技能、工作目录、执行模式和上下文预算保存为新模板
+    pub async fn save_session_as_template(&self, session_id: String, name: String) -> anyhow::Result<CoworkTemplate> {
+        let session = self.get_session(session_id).await?;
+        self.create_template(
+            name,
+            session.cwd,
+            session.system_prompt,
+            session.execution_mode,
+            session.active_skill_ids,
+            session.context_budget,
+        )
+        .await
+    }
+
+    /// 按模板创建会话，`title` 为空时使用模板名称，`cwd` 可覆盖模板中的工作目录
+    pub async fn create_session_from_template(
+        &self,
+        template_id: String,
+        title: Option<String>,
+        cwd: Option<String>,
+    ) -> anyhow::Result<CoworkSession> {
+        let id = format!("session_{}", uuid::Uuid::new_v4());
+        {
+            let db = self.database.lock().await;
+            if !db.cowork_create_session_from_template(
+                &id,
+                &template_id,
+                title.as_deref().filter(|t| !t.trim().is_empty()),
+                cwd.as_deref().filter(|c| !c.is_empty()),
+            )? {
+                return Err(anyhow::anyhow!("Template not found: {}", template_id));
+            }
+        }
+        self.get_session(id).await
+    }
+
+    pub async fn delete_session(&self, id: String) -> anyhow::Result<()> {
+        {
+            let db = self.database.lock().await;
+            db.cowork_delete_session(&id)?;
+        }
+        if self.data_dir.is_some() {
+            if let Err(e) = self.gc_attachments().await {
+                println!("[Cowork] Failed to collect attachments: {}", e);
+            }
+            if let Err(e) = self.gc_snapshots().await {
+                println!("[Cowork] Failed to collect file snapshots: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn attachment_store(&self) -> anyhow::Result<AttachmentStore> {
+        Ok(AttachmentStore::new(self.data_dir()?.join(cowork_attachments::ATTACHMENTS_DIR)))
+    }
+
+    /// 把附件内容存入按 SHA-256 寻址的存储，相同内容在所有会话间只保存一份。
+    /// 图片会先按 image_max_dimension 缩小、去掉 EXIF 并生成缩略图
+    pub async fn store_attachment(
+        &self,
+        name: String,
+        data: Vec<u8>,
+        mime_type: Option<String>,
+    ) -> anyhow::Result<Attachment> {
+        if data.len() > cowork_attachments::MAX_ATTACHMENT_BYTES {
+            return Err(anyhow::anyhow!(
+                "Attachment is too large: {} bytes (limit {} bytes)",
+                data.len(),
+                cowork_attachments::MAX_ATTACHMENT_BYTES
+            ));
+        }
+        let mime_type = mime_type
+            .filter(|m| !m.trim().is_empty())
+            .unwrap_or_else(|| cowork_attachments::guess_mime_type(&name).to_string());
+        let store = self.attachment_store()?;
+
+        if cowork_images::is_processable(&mime_type) {
+            let max_dimension = self
+                .config_value(IMAGE_MAX_DIMENSION_KEY)
+                .await
+                .unwrap_or(cowork_images::DEFAULT_IMAGE_MAX_DIMENSION);
+            let image = tokio::task::spawn_blocking(move || cowork_images::process_image(&data, max_dimension))
+                .await??;
+            let sha256 = store.put(&image.data)?;
+            let thumbnail_sha256 = store.put(&image.thumbnail)?;
+            let db = self.database.lock().await;
+            db.cowork_attachment_upsert(&sha256, image.mime_type, image.data.len() as u64)?;
+            db.cowork_attachment_upsert(&thumbnail_sha256, image.thumbnail_mime_type, image.thumbnail.len() as u64)?;
+            return Ok(Attachment {
+                sha256,
+                name,
+                mime_type: image.mime_type.to_string(),
+                size: image.data.len() as u64,
+                width: Some(image.width),
+                height: Some(image.height),
+                thumbnail_sha256: Some(thumbnail_sha256),
+            });
+        }
+
+        let sha256 = store.put(&data)?;
+        let db = self.database.lock().await;
+        db.cowork_attachment_upsert(&sha256, &mime_type, data.len() as u64)?;
+        Ok(Attachment {
+            sha256,
+            name,
+            mime_type,
+            size: data.len() as u64,
+            width: None,
+            height: None,
+            thumbnail_sha256: None,
+        })
+    }
+
+    /// 把消息中 DOCX/XLSX/PPTX/PDF 等文档附件的文字内容追加到消息正文末尾
+    async fn append_document_text(&self, mut messages: Vec<CoworkMessage>) -> Vec<CoworkMessage> {
+        let store = match self.attachment_store() {
+            Ok(store) => store,
+            Err(_) => return messages,
+        };
+        let max_chars = self
+            .config_value(DOCUMENT_MAX_CHARS_KEY)
+            .await
+            .unwrap_or(cowork_documents::DEFAULT_DOCUMENT_MAX_CHARS);
+        for message in messages.iter_mut() {
+            let mut sections = Vec::new();
+            for attachment in cowork_attachments::attachments_from_metadata(message.metadata.as_deref()) {
+                match self.document_text(&store, &attachment).await {
+                    Ok(Some(text)) if !text.is_empty() => sections.push(cowork_documents::attachment_section(
+                        &attachment.name,
+                        &cowork_documents::truncate(&text, max_chars),
+                    )),
+                    Ok(_) => {}
+                    Err(e) => println!("[Cowork] Failed to extract text from {}: {}", attachment.name, e),
+                }
+            }
+            if !sections.is_empty() {
+                message.content = format!("{}\n\n{}", message.content, sections.join("\n\n"))
+                    .trim()
+                    .to_string();
+            }
+        }
+        messages
+    }
+
+    /// 读取文档附件的文字内容，首次提取后缓存在附件存储中；不是文档类型时返回 None
+    async fn document_text(&self, store: &AttachmentStore, attachment: &Attachment) -> anyhow::Result<Option<String>> {
+        let kind = match cowork_documents::document_kind(&attachment.mime_type, &attachment.name) {
+            Some(kind) => kind,
+            None => return Ok(None),
+        };
+        if let Some(text) = store.read_text(&attachment.sha256) {
+            return Ok(Some(text));
+        }
+        if !store.exists(&attachment.sha256) {
+            return Ok(None);
+        }
+        let bytes = std::fs::read(store.path_for(&attachment.sha256))?;
+        let text = tokio::task::spawn_blocking(move || cowork_documents::extract_text(kind, &bytes)).await??;
+        if let Err(e) = store.put_text(&attachment.sha256, &text) {
+            println!("[Cowork] Failed to cache text of {}: {}", attachment.sha256, e);
+        }
+        Ok(Some(text))
+    }
+
+    /// 保存前端粘贴的图片，返回可随消息发送的附件
+    pub async fn store_images(&self, images: Vec<ImageInput>) -> anyhow::Result<Vec<Attachment>> {
+        let mut attachments = Vec::new();
+        for (index, image) in images.into_iter().enumerate() {
+            let (mime_type, data) = image.decode()?;
+            let name = image
+                .name
+                .filter(|n| !n.trim().is_empty())
+                .unwrap_or_else(|| format!("image-{}", index + 1));
+            let mime_type = mime_type.or_else(|| Some(cowork_attachments::guess_mime_type(&name).to_string()));
+            if !mime_type.as_deref().unwrap_or_default().starts_with("image/") {
+                return Err(anyhow::anyhow!("Not an image: {}", name));
+            }
+            attachments.push(self.store_attachment(name, data, mime_type).await?);
+        }
+        Ok(attachments)
+    }
+
+    /// 以 data URL 读取附件或缩略图内容
+    pub async fn read_attachment(&self, sha256: String) -> anyhow::Result<String> {
+        let store = self.attachment_store()?;
+        if !store.exists(&sha256) {
+            return Err(anyhow::anyhow!("Attachment not found: {}", sha256));
+        }
+        let mime_type = {
+            let db = self.database.lock().await;
+            db.cowork_attachment_mime_type(&sha256)?
+        }
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+        let bytes = std::fs::read(store.path_for(&sha256))?;
+        Ok(cowork_images::data_url(&mime_type, &bytes))
+    }
+
+    /// 会话 id 到该会话最近一张图片缩略图哈希的映射
+    pub async fn session_thumbnails(&self) -> anyhow::Result<HashMap<String, String>> {
+        let db = self.database.lock().await;
+        Ok(db.cowork_session_thumbnails()?.into_iter().collect())
+    }
+
+    /// 删除不再被任何消息引用的附件，刚上传尚未发送的附件在宽限期内保留，返回删除个数
+    pub async fn gc_attachments(&self) -> anyhow::Result<usize> {
+        let store = self.attachment_store()?;
+        let stored_before = chrono::Utc::now().timestamp_millis() - cowork_attachments::UNREFERENCED_GRACE_MS;
+        let db = self.database.lock().await;
+        let unreferenced = db.cowork_attachments_unreferenced(stored_before)?;
+        for sha256 in &unreferenced {
+            store.remove(sha256)?;
+            db.cowork_attachment_delete(sha256)?;
+        }
+        if !unreferenced.is_empty() {
+            println!("[Cowork] Removed {} unreferenced attachments", unreferenced.len());
+        }
+        Ok(unreferenced.len())
+    }
+
+    fn snapshot_store(&self) -> anyhow::Result<AttachmentStore> {
+        Ok(AttachmentStore::new(self.data_dir()?.join(cowork_snapshots::SNAPSHOTS_DIR)))
+    }
+
+    /// 需要保存快照的会话 cwd。沙箱模式、未设置 cwd 或 snapshot_retention 为 0 时返回 None
+    async fn snapshot_cwd(&self, session_id: &str) -> Option<String> {
+        let retention = self
+            .config_value::<usize>(SNAPSHOT_RETENTION_KEY)
+            .await
+            .unwrap_or(cowork_snapshots::DEFAULT_SNAPSHOT_RETENTION);
+        if retention == 0 {
+            return None;
+        }
+        let session = self.get_session(session_id.to_string()).await.ok()?;
+        if session.execution_mode.as_deref() == Some("sandbox") {
+            return None;
+        }
+        session.cwd.filter(|c| !c.trim().is_empty())
+    }
+
+    /// 工具修改会话 cwd 中的文件前保存其当前内容，同一轮中每个文件只保存第一次修改前的版本。
+    /// GoClaw 没有工具执行前的钩子，只能在审批通过时调用：此时工具还在等待结果，文件尚未被改动。
+    /// 无法保存的文件记为缺失，未经审批执行的工具由 record_missing_snapshots 在回复结束时补记
+    async fn snapshot_before_tool(&self, session_id: &str, turn_id: &str, tool: &str, arguments: &serde_json::Value) {
+        let cwd = match self.snapshot_cwd(session_id).await {
+            Some(cwd) => cwd,
+            None => return,
+        };
+        let store = match self.snapshot_store() {
+            Ok(store) => store,
+            Err(_) => return,
+        };
+
+        for path in cowork_snapshots::modified_paths(tool, arguments, &cwd) {
+            let path_str = path.to_string_lossy().to_string();
+            // 从保存内容到写入记录一直持有数据库锁，
+            // 避免 gc_snapshots 在两者之间把刚保存的内容当作未引用删除
+            let db = self.database.lock().await;
+            if db.cowork_has_file_snapshot(turn_id, &path_str).unwrap_or(false) {
+                continue;
+            }
+            let store = store.clone();
+            let captured = tokio::task::spawn_blocking(move || cowork_snapshots::capture(&store, &path)).await;
+            let captured = match captured {
+                Ok(Ok(captured)) => Some(captured),
+                Ok(Err(e)) => {
+                    println!("[Cowork] Skipped snapshot of {}: {}", path_str, e);
+                    None
+                }
+                Err(e) => {
+                    println!("[Cowork] Failed to snapshot {}: {}", path_str, e);
+                    None
+                }
+            };
+            let recorded = match captured {
+                Some((existed, sha256, size)) => {
+                    db.cowork_add_file_snapshot(session_id, turn_id, &path_str, existed, sha256.as_deref(), size)
+                }
+                None => db.cowork_add_missing_file_snapshot(session_id, turn_id, &path_str),
+            };
+            if let Err(e) = recorded {
+                println!("[Cowork] Failed to record snapshot of {}: {}", path_str, e);
+            }
+        }
+    }
+
+    /// 回复结束时检查本轮每个会修改文件的工具调用：文件没有快照说明工具未经审批就执行了，
+    /// 修改前的内容已无法取得，记为缺失，这一轮因此不能撤销，避免只撤销其中一部分文件
+    async fn record_missing_snapshots(&self, session_id: &str, turn_id: &str) {
+        let cwd = match self.snapshot_cwd(session_id).await {
+            Some(cwd) => cwd,
+            None => return,
+        };
+        let messages = match self.list_messages(session_id.to_string()).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                println!("[Cowork] Failed to list tool calls of turn {}: {}", turn_id, e);
+                return;
+            }
+        };
+        let paths: Vec<PathBuf> = messages
+            .iter()
+            .filter(|m| m.r#type == cowork_tools::TOOL_CALL_MESSAGE_TYPE)
+            .map(|m| parse_metadata(m.metadata.as_deref()))
+            .filter(|metadata| metadata["reply_id"] == turn_id)
+            .filter_map(|metadata| serde_json::from_value::<ToolCall>(metadata["tool_call"].clone()).ok())
+            .flat_map(|call| cowork_snapshots::modified_paths(&call.name, &call.arguments, &cwd))
+            .collect();
+        let db = self.database.lock().await;
+        for path in paths {
+            let path_str = path.to_string_lossy().to_string();
+            match db.cowork_add_missing_file_snapshot(session_id, turn_id, &path_str) {
+                Ok(true) => println!(
+                    "[Cowork] {} was modified without a snapshot, turn {} cannot be reverted",
+                    path_str, turn_id
+                ),
+                Ok(false) => {}
+                Err(e) => println!("[Cowork] Failed to record missing snapshot of {}: {}", path_str, e),
+            }
+        }
+    }
+
+    /// 回复结束时记录本轮快照文件的当前状态，撤销前据此检查文件之后是否又被改动
+    async fn record_snapshot_results(&self, session_id: &str, turn_id: &str) {
+        let snapshots: Vec<FileSnapshot> = {
+            let db = self.database.lock().await;
+            match db.cowork_list_file_snapshots(session_id, Some(turn_id)) {
+                Ok(snapshots) => snapshots.into_iter().filter_map(|s| serde_json::from_value(s).ok()).collect(),
+                Err(e) => {
+                    println!("[Cowork] Failed to list snapshots of turn {}: {}", turn_id, e);
+                    return;
+                }
+            }
+        };
+        for snapshot in snapshots.into_iter().filter(|s| !s.missing && s.after_existed.is_none()) {
+            let path = PathBuf::from(&snapshot.path);
+            let state = tokio::task::spawn_blocking(move || cowork_snapshots::file_state(&path))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|state| state);
+            let (existed, sha256) = match state {
+                Ok(state) => state,
+                Err(e) => {
+                    println!("[Cowork] Failed to read {} after turn {}: {}", snapshot.path, turn_id, e);
+                    continue;
+                }
+            };
+            let db = self.database.lock().await;
+            if let Err(e) = db.cowork_set_file_snapshot_after(snapshot.id, existed, sha256.as_deref()) {
+                println!("[Cowork] Failed to record state of {}: {}", snapshot.path, e);
+            }
+        }
+    }
+
+    /// 按 snapshot_retention 删除会话中较早轮次的快照
+    async fn prune_snapshots(&self, session_id: &str) {
+        if self.data_dir.is_none() {
+            return;
+        }
+        let retention = self
+            .config_value::<usize>(SNAPSHOT_RETENTION_KEY)
+            .await
+            .unwrap_or(cowork_snapshots::DEFAULT_SNAPSHOT_RETENTION);
+        let pruned = {
+            let db = self.database.lock().await;
+            db.cowork_prune_file_snapshots(session_id, retention)
+        };
+        match pruned {
+            Ok(0) => {}
+            Ok(_) => {
+                if let Err(e) = self.gc_snapshots().await {
+                    println!("[Cowork] Failed to collect file snapshots: {}", e);
+                }
+            }
+            Err(e) => println!("[Cowork] Failed to prune snapshots of {}: {}", session_id, e),
+        }
+    }
+
+    /// 删除快照存储中不再被任何快照记录引用的内容，返回删除个数。
+    /// 扫描期间持有数据库锁，与 snapshot_before_tool 的保存和记录互斥
+    pub async fn gc_snapshots(&self) -> anyhow::Result<usize> {
+        let store = self.snapshot_store()?;
+        let db = self.database.lock().await;
+        let referenced: std::collections::HashSet<String> = db.cowork_snapshot_hashes()?.into_iter().collect();
+        let mut removed = 0;
+        for sha256 in cowork_snapshots::stored_blobs(&self.data_dir()?.join(cowork_snapshots::SNAPSHOTS_DIR)) {
+            if !referenced.contains(&sha256) && store.remove(&sha256)? {
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            println!("[Cowork] Removed {} unreferenced file snapshots", removed);
+        }
+        Ok(removed)
+    }
+
+    /// 列出会话中保存了文件快照的回复轮次，最近的一轮在前
+    pub async fn list_snapshot_turns(&self, session_id: String) -> anyhow::Result<Vec<SnapshotTurn>> {
+        let db = self.database.lock().await;
+        let snapshots: Vec<FileSnapshot> = db
+            .cowork_list_file_snapshots(&session_id, None)?
+            .into_iter()
+            .filter_map(|s| serde_json::from_value(s).ok())
+            .collect();
+        Ok(cowork_snapshots::group_turns(snapshots))
+    }
+
+    /// 撤销一轮回复对文件的修改：改动过的文件恢复原内容，这一轮新建的文件被删除。
+    /// 任一文件在这一轮之后又被改动过（包括之后的轮次）时拒绝撤销，避免覆盖之后的修改
+    pub async fn revert_turn(&self, session_id: String, turn_id: String) -> anyhow::Result<Vec<RevertedFile>> {
+        if self.streams.lock().unwrap().contains_key(&session_id) {
+            return Err(anyhow::anyhow!("Cannot revert while a reply is being generated"));
+        }
+        let snapshots: Vec<FileSnapshot> = {
+            let db = self.database.lock().await;
+            db.cowork_list_file_snapshots(&session_id, Some(&turn_id))?
+                .into_iter()
+                .filter_map(|s| serde_json::from_value(s).ok())
+                .collect()
+        };
+        if snapshots.is_empty() {
+            return Err(anyhow::anyhow!("No file changes recorded for turn: {}", turn_id));
+        }
+        if snapshots.iter().any(|s| s.reverted_at.is_some()) {
+            return Err(anyhow::anyhow!("Turn already reverted: {}", turn_id));
+        }
+        let missing: Vec<&str> = snapshots.iter().filter(|s| s.missing).map(|s| s.path.as_str()).collect();
+        if !missing.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Turn {} cannot be reverted, no snapshot of: {}",
+                turn_id,
+                missing.join(", ")
+            ));
+        }
+
+        let store = self.snapshot_store()?;
+        let results = tokio::task::spawn_blocking(move || {
+            let conflicts: Vec<String> = snapshots
+                .iter()
+                .filter(|s| cowork_snapshots::changed_since_turn(s))
+                .map(|s| s.path.clone())
+                .collect();
+            if !conflicts.is_empty() {
+                return Err(conflicts);
+            }
+            Ok(snapshots
+                .iter()
+                .rev()
+                .map(|snapshot| match cowork_snapshots::restore(&store, snapshot) {
+                    Ok(action) => RevertedFile {
+                        path: snapshot.path.clone(),
+                        action: action.to_string(),
+                        error: None,
+                    },
+                    Err(e) => RevertedFile {
+                        path: snapshot.path.clone(),
+                        action: "failed".to_string(),
+                        error: Some(e.to_string()),
+                    },
+                })
+                .collect::<Vec<_>>())
+        })
+        .await?
+        .map_err(|conflicts| {
+            anyhow::anyhow!("Files changed after turn {}: {}", turn_id, conflicts.join(", "))
+        })?;
+
+        {
+            let db = self.database.lock().await;
+            db.cowork_mark_turn_reverted(&session_id, &turn_id)?;
+        }
+        println!(
+            "[Cowork] Reverted turn {} of session {}: {} files, {} failed",
+            turn_id,
+            session_id,
+            results.len(),
+            results.iter().filter(|r| r.error.is_some()).count()
+        );
+        self.emit(
+            "cowork:turnReverted",
+            serde_json::json!({
+                "session_id": session_id,
+                "turn_id": turn_id,
+                "files": results,
+            }),
+        );
+        Ok(results)
+    }
+
+    pub async fn set_session_tags(&self, id: String, tags: Vec<String>) -> anyhow::Result<CoworkSession> {
+        {
+            let db = self.database.lock().await;
+            if !db.cowork_set_session_tags(&id, &tags)? {
+                return Err(anyhow::anyhow!("Session not found: {}", id));
+            }
+        }
+        self.get_session(id).await
+    }
+
+    pub async fn list_tags(&self) -> anyhow::Result<Vec<CoworkTag>> {
+        let db = self.database.lock().await;
+        Ok(db
+            .cowork_list_tags()?
+            .into_iter()
+            .map(|(tag, session_count)| CoworkTag { tag, session_count })
+            .collect())
+    }
+
+    pub async fn set_session_archived(&self, id: String, archived: bool) -> anyhow::Result<()> {
+        let db = self.database.lock().await;
+        if !db.cowork_set_session_archived(&id, archived)? {
+            return Err(anyhow::anyhow!("Session not found: {}", id));
+        }
+        Ok(())
+    }
+
+    pub async fn set_session_folder(&self, id: String, folder_id: Option<String>) -> anyhow::Result<()> {
+        let folder_id = folder_id.filter(|f| !f.is_empty());
+        if let Some(folder_id) = &folder_id {
+            if !self.list_folders().await?.iter().any(|f| &f.id == folder_id) {
+                return Err(anyhow::anyhow!("Folder not found: {}", folder_id));
+            }
+        }
+        let db = self.database.lock().await;
+        db.cowork_set_session_folder(&id, folder_id.as_deref())?;
+        Ok(())
+    }
+
+    pub async fn list_folders(&self) -> anyhow::Result<Vec<CoworkFolder>> {
+        let db = self.database.lock().await;
+        let folders_json = db.cowork_list_folders()?;
+        Ok(folders_json
+            .into_iter()
+            .filter_map(|f| serde_json::from_value(f).ok())
+            .collect())
+    }
+
+    pub async fn create_folder(&self, name: String) -> anyhow::Result<CoworkFolder> {
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Folder name cannot be empty"));
+        }
+        let id = format!("folder_{}", uuid::Uuid::new_v4());
+        {
+            let db = self.database.lock().await;
+            db.cowork_create_folder(&id, &name)?;
+        }
+        self.list_folders()
+            .await?
+            .into_iter()
+            .find(|f| f.id == id)
+            .ok_or_else(|| anyhow::anyhow!("Folder not found: {}", id))
+    }
+
+    pub async fn rename_folder(&self, id: String, name: String) -> anyhow::Result<()> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Folder name cannot be empty"));
+        }
+        let db = self.database.lock().await;
+        if !db.cowork_rename_folder(&id, name)? {
+            return Err(anyhow::anyhow!("Folder not found: {}", id));
+        }
+        Ok(())
+    }
+
+    /// 删除文件夹，其中的会话保留并移出文件夹
+    pub async fn delete_folder(&self, id: String) -> anyhow::Result<()> {
         let db = self.database.lock().await;
+        db.cowork_delete_folder(&id)?;
+        Ok(())
+    }
 
-        db.cowork_create_session(
+    pub async fn update_session(
+        &self,
+        id: String,
+        title: Option<String>,
+        pinned: Option<bool>,
+        status: Option<String>,
+        cwd: Option<String>,
+        system_prompt: Option<String>,
+        execution_mode: Option<String>,
+        active_skill_ids: Option<String>,
+    ) -> anyhow::Result<()> {
+        let db = self.database.lock().await;
+        db.cowork_update_session(
             &id,
-            &title,
+            title.as_deref(),
+            pinned,
+            status.as_deref(),
             cwd.as_deref(),
             system_prompt.as_deref(),
             execution_mode.as_deref(),
+            active_skill_ids.as_deref(),
         )?;
+        Ok(())
+    }
+
+    pub async fn set_context_budget(&self, session_id: String, budget: Option<i64>) -> anyhow::Result<()> {
+        let db = self.database.lock().await;
+        db.cowork_set_session_context_budget(&session_id, budget)?;
+        Ok(())
+    }
+
+    pub async fn update_message(
+        &self,
+        session_id: String,
+        id: String,
+        content: Option<String>,
+        metadata: Option<String>,
+    ) -> anyhow::Result<()> {
+        let db = self.database.lock().await;
+        db.cowork_update_message(&id, &session_id, content.as_deref(), metadata.as_deref())?;
+        Ok(())
+    }
+
+    /// 按会话（group_by = "session"）或按天（group_by = "day"）汇总 token 用量并按价格表计算费用
+    pub async fn usage_summary(
+        &self,
+        group_by: String,
+        session_id: Option<String>,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+    ) -> anyhow::Result<UsageSummary> {
+        let by_day = match group_by.as_str() {
+            "session" => false,
+            "day" => true,
+            other => return Err(anyhow::anyhow!("Unsupported usage grouping: {}", other)),
+        };
+        let prices = self.price_table().await;
+        let groups: Vec<UsageGroup> = {
+            let db = self.database.lock().await;
+            db.cowork_usage_groups(by_day, session_id.as_deref(), start_time, end_time)?
+        }
+        .into_iter()
+        .filter_map(|g| serde_json::from_value(g).ok())
+        .collect();
+        Ok(cowork_usage::summarize(&group_by, &groups, &prices))
+    }
+
+    pub async fn price_table(&self) -> PriceTable {
+        self.config_value(USAGE_PRICE_TABLE_KEY).await.unwrap_or_default()
+    }
+
+    pub async fn set_price_table(&self, prices: PriceTable) -> anyhow::Result<()> {
+        if let Some((model, _)) = prices
+            .models
+            .iter()
+            .find(|(_, p)| p.prompt < 0.0 || p.completion < 0.0 || !p.prompt.is_finite() || !p.completion.is_finite())
+        {
+            return Err(anyhow::anyhow!("Invalid price for model {}", model));
+        }
+        self.set_config(USAGE_PRICE_TABLE_KEY.to_string(), serde_json::to_string(&prices)?)
+            .await
+    }
+
+    pub fn list_pending_approvals(&self, session_id: Option<String>) -> Vec<ApprovalRequest> {
+        let mut pending: Vec<ApprovalRequest> = self
+            .approvals
+            .lock()
+            .unwrap()
+            .values()
+            .map(|a| &a.request)
+            .filter(|a| session_id.as_ref().map(|id| &a.session_id == id).unwrap_or(true))
+            .cloned()
+            .collect();
+        pending.sort_by_key(|a| a.requested_at);
+        pending
+    }
+
+    /// 回复等待中的审批请求。`scope` 为 "session" 或 "global" 时同时保存为规则（总是允许/拒绝），
+    /// 规则的命令模式和路径前缀默认取自请求本身，全局规则的相对路径按会话 cwd 转为绝对路径，返回新建的规则
+    pub async fn respond_approval(
+        &self,
+        approval_id: String,
+        allow: bool,
+        scope: Option<String>,
+        command_pattern: Option<String>,
+        path_prefix: Option<String>,
+    ) -> anyhow::Result<Option<PermissionRule>> {
+        let (request, turn_id) = self
+            .approvals
+            .lock()
+            .unwrap()
+            .get(&approval_id)
+            .map(|a| (a.request.clone(), a.turn_id.clone()))
+            .ok_or_else(|| anyhow::anyhow!("Approval request not found: {}", approval_id))?;
+
+        let rule = match scope {
+            Some(scope) => {
+                let cwd = self
+                    .get_session(request.session_id.clone())
+                    .await
+                    .ok()
+                    .and_then(|s| s.cwd);
+                let rule = cowork_approvals::rule_from_request(
+                    format!("rule_{}", uuid::Uuid::new_v4()),
+                    &request,
+                    allow,
+                    &scope,
+                    command_pattern.filter(|p| !p.trim().is_empty()),
+                    path_prefix.filter(|p| !p.trim().is_empty()),
+                    chrono::Utc::now().timestamp_millis(),
+                )?;
+                Some(rule.anchor_path(cwd.as_deref()))
+            }
+            None => None,
+        };
+        if let Some(rule) = &rule {
+            self.save_permission_rule(rule).await?;
+        }
 
-        Ok(CoworkSession {
-            id: id.clone(),
-            title,
-            status: "idle".to_string(),
-            pinned: false,
-            cwd,
-            system_prompt,
-            execution_mode,
-            active_skill_ids: None,
-            created_at: now,
-            updated_at: now,
-        })
+        // 回复结束时请求已被取消
+        if self.approvals.lock().unwrap().remove(&approval_id).is_none() {
+            return Err(anyhow::anyhow!("Approval request not found: {}", approval_id));
+        }
+        if let Some(stream) = self.streams.lock().unwrap().get(&request.session_id) {
+            let _ = stream.events.send(StreamEvent::ApprovalAnswered);
+        }
+        if allow {
+            self.snapshot_before_tool(&request.session_id, &turn_id, &request.tool, &request.arguments)
+                .await;
+        }
+        self.resolve_approval(
+            &request,
+            allow,
+            cowork_approvals::SOURCE_USER,
+            rule.as_ref().map(|r| r.id.as_str()),
+        )
+        .await;
+        Ok(rule)
     }
 
-    pub async fn delete_session(&self, id: String) -> anyhow::Result<()> {
+    /// 列出审批规则；指定会话时只返回全局规则和该会话的规则
+    pub async fn list_permission_rules(&self, session_id: Option<String>) -> anyhow::Result<Vec<PermissionRule>> {
         let db = self.database.lock().await;
-        db.cowork_delete_session(&id)?;
-        Ok(())
+        let rules_json = db.cowork_list_permission_rules(session_id.as_deref())?;
+        Ok(rules_json
+            .into_iter()
+            .filter_map(|r| serde_json::from_value(r).ok())
+            .collect())
     }
 
-    pub async fn update_session(
+    pub async fn add_permission_rule(
         &self,
-        id: String,
-        title: Option<String>,
-        pinned: Option<bool>,
-        status: Option<String>,
-        cwd: Option<String>,
-        system_prompt: Option<String>,
-        execution_mode: Option<String>,
-    ) -> anyhow::Result<()> {
+        tool: String,
+        command_pattern: Option<String>,
+        path_prefix: Option<String>,
+        session_id: Option<String>,
+        allow: bool,
+    ) -> anyhow::Result<PermissionRule> {
+        let tool = tool.trim().to_string();
+        if tool.is_empty() {
+            return Err(anyhow::anyhow!("Tool name is required"));
+        }
+        if let Some(session_id) = &session_id {
+            self.get_session(session_id.clone()).await?;
+        }
+        let rule = PermissionRule {
+            id: format!("rule_{}", uuid::Uuid::new_v4()),
+            tool,
+            command_pattern: command_pattern.filter(|p| !p.trim().is_empty()),
+            path_prefix: path_prefix.filter(|p| !p.trim().is_empty()),
+            session_id,
+            decision: if allow {
+                cowork_approvals::DECISION_ALLOW
+            } else {
+                cowork_approvals::DECISION_DENY
+            }
+            .to_string(),
+            created_at: chrono::Utc::now().timestamp_millis(),
+        };
+        self.save_permission_rule(&rule).await?;
+        Ok(rule)
+    }
+
+    async fn save_permission_rule(&self, rule: &PermissionRule) -> anyhow::Result<()> {
         let db = self.database.lock().await;
-        db.cowork_update_session(
-            &id,
-            title.as_deref(),
-            pinned,
-            status.as_deref(),
-            cwd.as_deref(),
-            system_prompt.as_deref(),
-            execution_mode.as_deref(),
+        db.cowork_add_permission_rule(
+            &rule.id,
+            &rule.tool,
+            rule.command_pattern.as_deref(),
+            rule.path_prefix.as_deref(),
+            rule.session_id.as_deref(),
+            &rule.decision,
         )?;
         Ok(())
     }
 
-    pub async fn update_message(
+    pub async fn delete_permission_rule(&self, id: String) -> anyhow::Result<bool> {
+        let db = self.database.lock().await;
+        Ok(db.cowork_delete_permission_rule(&id)?)
+    }
+
+    pub async fn list_approval_audit(
         &self,
-        session_id: String,
-        id: String,
-        content: Option<String>,
-        metadata: Option<String>,
-    ) -> anyhow::Result<()> {
+        session_id: Option<String>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> anyhow::Result<Vec<serde_json::Value>> {
         let db = self.database.lock().await;
-        db.cowork_update_message(&id, &session_id, content.as_deref(), metadata.as_deref())?;
-        Ok(())
+        Ok(db.cowork_list_approval_audit(
+            session_id.as_deref(),
+            limit.unwrap_or(100).clamp(1, 1000),
+            offset.unwrap_or(0).max(0),
+        )?)
     }
 
     pub async fn get_config(&self) -> anyhow::Result<serde_json::Value> {
@@ -237,6 +2561,8 @@ impl CoworkManager {
         Ok(())
     }
 
+    /// 更新记忆。scope / scope_value / expires_at 任一有值时同时更新适用范围，
+    /// expires_at 小于等于 0 表示取消过期时间
     pub async fn update_user_memory(
         &self,
         id: String,
@@ -244,14 +2570,48 @@ impl CoworkManager {
         confidence: Option<f64>,
         status: Option<String>,
         is_explicit: Option<bool>,
+        scope: Option<String>,
+        scope_value: Option<String>,
+        expires_at: Option<i64>,
     ) -> anyhow::Result<()> {
+        let scope_update = if scope.is_some() || scope_value.is_some() || expires_at.is_some() {
+            let current = self
+                .list_user_memories()
+                .await?
+                .into_iter()
+                .find(|m| m.id == id)
+                .ok_or_else(|| anyhow::anyhow!("User memory not found: {}", id))?;
+            let scope_value = match &scope {
+                Some(_) => scope_value,
+                None => scope_value.or(current.scope_value),
+            };
+            let (scope, scope_value) = cowork_memory::validate_scope(
+                scope.as_deref().unwrap_or(&current.scope),
+                scope_value.as_deref(),
+            )?;
+            let expires_at = match expires_at {
+                Some(expires_at) if expires_at <= 0 => None,
+                Some(expires_at) => Some(expires_at),
+                None => current.expires_at,
+            };
+            Some((scope, scope_value, expires_at))
+        } else {
+            None
+        };
+
+        let scope_update = scope_update.as_ref().map(|(scope, scope_value, expires_at)| MemoryScope {
+            scope,
+            scope_value: scope_value.as_deref(),
+            expires_at: *expires_at,
+        });
         let db = self.database.lock().await;
-        db.user_memory_update(
+        db.user_memory_update_with_scope(
             &id,
             text.as_deref(),
             confidence,
             status.as_deref(),
             is_explicit,
+            scope_update.as_ref(),
         )?;
         Ok(())
     }
@@ -276,6 +2636,153 @@ impl CoworkManager {
         Ok(messages)
     }
 
+    /// 按 sequence 游标分页读取消息，供界面按需加载长会话的历史。
+    /// `before` 向更早的消息翻页，`after` 向更新的消息翻页，都不指定时返回最新一页
+    pub async fn list_messages_page(
+        &self,
+        session_id: String,
+        before: Option<i32>,
+        after: Option<i32>,
+        limit: Option<u32>,
+    ) -> anyhow::Result<CoworkMessagePage> {
+        let limit = limit.unwrap_or(50).clamp(1, 500);
+        let db = self.database.lock().await;
+        let (messages_json, has_more) = db.cowork_list_messages_page(&session_id, before, after, limit)?;
+        let messages = messages_json
+            .into_iter()
+            .filter_map(|m| serde_json::from_value(m).ok())
+            .collect();
+        Ok(CoworkMessagePage { messages, has_more })
+    }
+
+    /// 在指定消息处分支出新会话：复制会话设置和序号不超过 `sequence` 的消息，
+    /// 消息保留原序号以便摘要的 covers_through 继续有效
+    pub async fn fork_session(
+        &self,
+        session_id: String,
+        sequence: i32,
+        title: Option<String>,
+    ) -> anyhow::Result<CoworkSession> {
+        let parent = self.get_session(session_id.clone()).await?;
+        let history = self.list_messages(session_id.clone()).await?;
+        if !history.iter().any(|m| m.sequence == Some(sequence)) {
+            return Err(anyhow::anyhow!(
+                "Message with sequence {} not found in session {}",
+                sequence,
+                session_id
+            ));
+        }
+
+        let now = chrono::Utc::now().timestamp_millis();
+        let fork_id = format!("session_{}", uuid::Uuid::new_v4());
+        let fork = CoworkSession {
+            id: fork_id.clone(),
+            title: title
+                .filter(|t| !t.trim().is_empty())
+                .unwrap_or_else(|| format!("{} (fork)", parent.title)),
+            status: "idle".to_string(),
+            pinned: false,
+            created_at: now,
+            updated_at: now,
+            parent_session_id: Some(parent.id.clone()),
+            fork_sequence: Some(sequence),
+            archived_at: None,
+            ..parent
+        };
+        let history: Vec<CoworkMessage> = history
+            .into_iter()
+            .filter(|m| matches!(m.sequence, Some(seq) if seq <= sequence))
+            .collect();
+        // 工具消息通过 metadata 引用所属回复和调用消息，需随 id 一起替换
+        let ids: HashMap<String, String> = history
+            .iter()
+            .map(|m| (m.id.clone(), format!("msg_{}", uuid::Uuid::new_v4())))
+            .collect();
+        let messages = history
+            .into_iter()
+            .map(|mut m| {
+                m.id = ids[&m.id].clone();
+                m.session_id = fork_id.clone();
+                m.metadata = cowork_tools::remap_message_refs(m.metadata, &ids);
+                serde_json::to_value(m)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        {
+            let db = self.database.lock().await;
+            db.cowork_insert_session_with_messages(&serde_json::to_value(&fork)?, &messages, &[])?;
+        }
+        println!(
+            "[Cowork] Forked session {} at sequence {} into {}",
+            session_id, sequence, fork_id
+        );
+        self.get_session(fork_id).await
+    }
+
+    /// 导出会话为 markdown / html / json 文本
+    pub async fn export_session(&self, session_id: String, format: String) -> anyhow::Result<String> {
+        let format = ExportFormat::parse(&format)?;
+        let session = self.get_session(session_id.clone()).await?;
+        let messages = self.list_messages(session_id).await?;
+        let mut bundle = SessionBundle::new(session, messages);
+        // 只有 JSON 导出包需要能完整导入，附件内容随包导出
+        if format == ExportFormat::Json {
+            if let Ok(store) = self.attachment_store() {
+                bundle.embed_attachments(&store)?;
+            }
+        }
+        bundle.render(format)
+    }
+
+    /// 导入 json 导出包，总是创建新的会话，不会覆盖已有会话
+    pub async fn import_session(&self, data: String) -> anyhow::Result<CoworkSession> {
+        let bundle = SessionBundle::parse(&data)?;
+        let attachments = if bundle.attachments.is_empty() {
+            Vec::new()
+        } else {
+            let restored = bundle.restore_attachments(&self.attachment_store()?)?;
+            println!("[Cowork] Restored {} attachments from session bundle", restored.len());
+            restored
+        };
+        let (session, messages) = bundle.remap_for_import();
+        let messages_json = messages
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<Vec<_>, _>>()?;
+        {
+            let db = self.database.lock().await;
+            db.cowork_insert_session_with_messages(&serde_json::to_value(&session)?, &messages_json, &attachments)?;
+        }
+        self.get_session(session.id).await
+    }
+
+    pub async fn search(
+        &self,
+        query: String,
+        session_id: Option<String>,
+        message_types: Vec<String>,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> anyhow::Result<Vec<CoworkSearchHit>> {
+        let filter = MessageSearchFilter {
+            session_id: session_id.as_deref(),
+            message_types: &message_types,
+            start_time,
+            end_time,
+            limit: limit.unwrap_or(50).min(200),
+            offset: offset.unwrap_or(0),
+        };
+        let db = self.database.lock().await;
+        let hits_json = db.cowork_search_messages(&query, &filter)?;
+        let hits: Vec<CoworkSearchHit> = hits_json
+            .into_iter()
+            .filter_map(|h| serde_json::from_value(h).ok())
+            .collect();
+        Ok(hits)
+    }
+
     pub async fn add_message(
         &self,
         session_id: String,
@@ -309,17 +2816,61 @@ impl CoworkManager {
         Ok(memories)
     }
 
+    /// 按范围筛选记忆；指定 session_id 时只返回对该会话生效的记忆
+    pub async fn list_user_memories_filtered(
+        &self,
+        scope: Option<String>,
+        session_id: Option<String>,
+    ) -> anyhow::Result<Vec<UserMemory>> {
+        let session = match session_id {
+            Some(session_id) => Some(self.get_session(session_id).await?),
+            None => None,
+        };
+        let now = chrono::Utc::now().timestamp_millis();
+        Ok(self
+            .list_user_memories()
+            .await?
+            .into_iter()
+            .filter(|m| scope.as_ref().map(|s| &m.scope == s).unwrap_or(true))
+            .filter(|m| {
+                session
+                    .as_ref()
+                    .map(|s| cowork_memory::applies_to(m, s, now))
+                    .unwrap_or(true)
+            })
+            .collect())
+    }
+
     pub async fn create_user_memory(
         &self,
         text: String,
         confidence: f64,
         is_explicit: bool,
+        scope: Option<String>,
+        scope_value: Option<String>,
+        expires_at: Option<i64>,
     ) -> anyhow::Result<UserMemory> {
+        let (scope, scope_value) = cowork_memory::validate_scope(
+            scope.as_deref().unwrap_or(cowork_memory::SCOPE_GLOBAL),
+            scope_value.as_deref(),
+        )?;
+        let expires_at = expires_at.filter(|e| *e > 0);
         let id = format!("memory_{}", uuid::Uuid::new_v4());
         let now = chrono::Utc::now().timestamp_millis();
         let db = self.database.lock().await;
 
-        db.user_memory_create(&id, &text, confidence, is_explicit)?;
+        db.user_memory_create_with_scope(
+            &id,
+            &text,
+            confidence,
+            is_explicit,
+            "created",
+            &MemoryScope {
+                scope: &scope,
+                scope_value: scope_value.as_deref(),
+                expires_at,
+            },
+        )?;
 
         Ok(UserMemory {
             id: id.clone(),
@@ -330,6 +2881,1160 @@ impl CoworkManager {
             created_at: now,
             updated_at: now,
             last_used_at: None,
+            kind: None,
+            scope,
+            scope_value,
+            expires_at,
         })
     }
+
+    /// 根据第一轮问答请求模型生成会话标题。标题已被自动生成或被用户修改过、
+    /// 或会话已不止一轮回复时不做任何事，返回写入的新标题
+    pub async fn generate_title(&self, session_id: String) -> anyhow::Result<Option<String>> {
+        let session = self.get_session(session_id.clone()).await?;
+        if session.title_source.is_some() {
+            return Ok(None);
+        }
+        let messages = self.list_messages(session_id.clone()).await?;
+        let replies: Vec<&CoworkMessage> = messages.iter().filter(|m| m.r#type == "assistant").collect();
+        let user_message = messages.iter().find(|m| m.r#type == "user");
+        let (user_message, reply) = match (user_message, replies.as_slice()) {
+            (Some(user_message), [reply]) => (user_message, reply),
+            _ => return Ok(None),
+        };
+
+        let prompt = cowork_prompt::build_title_prompt(&user_message.content, &reply.content);
+        let title = match self.complete(format!("{}:title", session_id), prompt).await? {
+            Some(response) => match cowork_prompt::clean_title(&response) {
+                Some(title) => title,
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+
+        // 生成期间用户可能已经改了标题，写入时再判断一次
+        let db = self.database.lock().await;
+        if !db.cowork_set_auto_title(&session_id, &title)? {
+            return Ok(None);
+        }
+        println!("[Cowork] Generated title for {}: {}", session_id, title);
+        Ok(Some(title))
+    }
+
+    fn spawn_title_generation(&self, session_id: String) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            if !manager.config_enabled(AUTO_TITLE_KEY, true).await {
+                return;
+            }
+            match manager.generate_title(session_id.clone()).await {
+                Ok(Some(title)) => manager.emit(
+                    "cowork:sessionTitleUpdated",
+                    serde_json::json!({ "session_id": session_id, "title": title }),
+                ),
+                Ok(None) => {}
+                Err(e) => println!("[Cowork] Failed to generate title for {}: {}", session_id, e),
+            }
+        });
+    }
+
+    fn spawn_memory_extraction(&self, session_id: String, assistant_message_id: String) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            if !manager.config_enabled(MEMORY_AUTO_EXTRACT_KEY, true).await {
+                return;
+            }
+            match manager
+                .extract_memories(session_id.clone(), Some(assistant_message_id))
+                .await
+            {
+                Ok(memories) if !memories.is_empty() => {
+                    if let Err(e) = manager.consolidate_memories().await {
+                        println!("[Cowork] Failed to consolidate memories: {}", e);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => println!("[Cowork] Failed to extract memories for {}: {}", session_id, e),
+            }
+        });
+    }
+
+    /// 合并文本近似重复的记忆，返回本次合并的汇总
+    pub async fn consolidate_memories(&self) -> anyhow::Result<serde_json::Value> {
+        let threshold = self
+            .config_value(MEMORY_DUPLICATE_THRESHOLD_KEY)
+            .await
+            .unwrap_or(cowork_memory::DUPLICATE_SIMILARITY_THRESHOLD);
+        let memories = self.list_user_memories().await?;
+        let plans = cowork_memory::plan_merges(&memories, threshold);
+
+        let mut merged = 0;
+        let mut sources_moved = 0;
+        {
+            let db = self.database.lock().await;
+            for plan in &plans {
+                let merged_with_similarity: Vec<(String, f64)> = plan
+                    .merged_ids
+                    .iter()
+                    .cloned()
+                    .zip(plan.similarities.iter().copied())
+                    .collect();
+                sources_moved += db.user_memory_merge(
+                    &plan.survivor_id,
+                    &merged_with_similarity,
+                    plan.confidence,
+                    &plan.status,
+                    plan.is_explicit,
+                )?;
+                merged += plan.merged_ids.len();
+            }
+        }
+
+        if merged > 0 {
+            println!(
+                "[Cowork] Consolidated {} duplicate memories into {} records",
+                merged,
+                plans.len()
+            );
+        }
+        Ok(serde_json::json!({
+            "groups": plans.len(),
+            "merged": merged,
+            "sources_moved": sources_moved,
+            "merges": plans,
+        }))
+    }
+
+    /// 从一轮对话中提取候选记忆并保存，`assistant_message_id` 为空时使用会话中最后一条回复
+    pub async fn extract_memories(
+        &self,
+        session_id: String,
+        assistant_message_id: Option<String>,
+    ) -> anyhow::Result<Vec<UserMemory>> {
+        let history = self.list_messages(session_id.clone()).await?;
+        let assistant_index = history
+            .iter()
+            .rposition(|m| match &assistant_message_id {
+                Some(id) => &m.id == id,
+                None => m.r#type == "assistant",
+            })
+            .ok_or_else(|| anyhow::anyhow!("Assistant message not found"))?;
+        let assistant_msg = &history[assistant_index];
+        let user_msg = history[..assistant_index]
+            .iter()
+            .rev()
+            .find(|m| m.r#type == "user")
+            .ok_or_else(|| anyhow::anyhow!("No user message before {}", assistant_msg.id))?;
+
+        let session = self.get_session(session_id.clone()).await?;
+        let now = chrono::Utc::now().timestamp_millis();
+        let existing: Vec<UserMemory> = self
+            .list_user_memories()
+            .await?
+            .into_iter()
+            .filter(|m| m.status != "deleted" && cowork_memory::applies_to(m, &session, now))
+            .collect();
+        let prompt = cowork_memory::build_extraction_prompt(user_msg, assistant_msg, &existing);
+        let response = match self.complete(format!("{}:memory", session_id), prompt).await? {
+            Some(response) => response,
+            None => return Ok(Vec::new()),
+        };
+        let candidates = cowork_memory::parse_candidates(&response, &existing);
+        if candidates.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut memories = Vec::new();
+        {
+            let db = self.database.lock().await;
+            for candidate in candidates {
+                let id = format!("memory_{}", uuid::Uuid::new_v4());
+                let (scope, scope_value) = cowork_memory::candidate_scope(&candidate, &session);
+                db.user_memory_create_with_sources(
+                    &id,
+                    &candidate.text,
+                    &candidate.kind,
+                    candidate.confidence,
+                    candidate.status(),
+                    &MemoryScope {
+                        scope: &scope,
+                        scope_value: scope_value.as_deref(),
+                        expires_at: None,
+                    },
+                    &session_id,
+                    &[(&user_msg.id, "user"), (&assistant_msg.id, "assistant")],
+                )?;
+                memories.push(UserMemory {
+                    id,
+                    text: candidate.text.clone(),
+                    confidence: candidate.confidence,
+                    is_explicit: false,
+                    status: candidate.status().to_string(),
+                    created_at: now,
+                    updated_at: now,
+                    last_used_at: None,
+                    kind: Some(candidate.kind),
+                    scope,
+                    scope_value,
+                    expires_at: None,
+                });
+            }
+        }
+
+        println!(
+            "[Cowork] Extracted {} memories from session {}",
+            memories.len(),
+            session_id
+        );
+        self.emit(
+            "cowork:memoriesExtracted",
+            serde_json::json!({
+                "session_id": session_id,
+                "message_id": assistant_msg.id,
+                "memories": memories,
+            }),
+        );
+        Ok(memories)
+    }
+
+    /// 与数据目录下的 memories.md 双向同步：先把文件中的增删改合并进数据库
+    /// （以上次同步的快照为基准判断冲突），再用数据库中生效的记忆重写文件
+    pub async fn sync_memory_file(&self) -> anyhow::Result<MemorySyncReport> {
+        let _guard = self.memory_sync_lock.lock().await;
+        let data_dir = self.data_dir()?;
+        let path = data_dir.join(cowork_memory_sync::MEMORY_FILE_NAME);
+        let state_path = data_dir.join(cowork_memory_sync::SYNC_STATE_FILE_NAME);
+        let state = cowork_memory_sync::load_state(&state_path).unwrap_or_default();
+        let mut report = MemorySyncReport {
+            path: path.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+
+        // 文件不存在或与上次写出的内容相同时，只需要导出
+        let file_content = std::fs::read_to_string(&path).ok();
+        let file_changed = file_content
+            .as_ref()
+            .map(|content| state.file_hash.as_deref() != Some(&cowork_memory_sync::content_hash(content)))
+            .unwrap_or(false);
+        let mut conflict_ids = state.conflict_ids.clone();
+        if let (true, Some(content)) = (file_changed, file_content.as_ref()) {
+            let now = chrono::Utc::now().timestamp_millis();
+            let memories = self.list_user_memories().await?;
+            let active = cowork_memory_sync::synced_memories(&memories, &state.conflict_ids, now);
+            let plan = cowork_memory_sync::plan_sync(&active, &cowork_memory_sync::parse(content), &state);
+
+            let db = self.database.lock().await;
+            for action in plan.actions {
+                match action {
+                    SyncAction::Create { entry, status } => {
+                        let id = format!("memory_{}", uuid::Uuid::new_v4());
+                        db.user_memory_create_with_scope(
+                            &id,
+                            &entry.text,
+                            1.0,
+                            true,
+                            status,
+                            &MemoryScope {
+                                scope: &entry.scope,
+                                scope_value: entry.scope_value.as_deref(),
+                                expires_at: None,
+                            },
+                        )?;
+                        if status == cowork_memory::STATUS_PENDING {
+                            conflict_ids.push(id);
+                        }
+                        report.created += 1;
+                    }
+                    SyncAction::Update { id, entry } => {
+                        let current = match active.iter().find(|m| m.id == id) {
+                            Some(current) => current,
+                            None => continue,
+                        };
+                        let text_changed = cowork_memory_sync::single_line(&current.text) != entry.text;
+                        let scope_changed =
+                            current.scope != entry.scope || current.scope_value != entry.scope_value;
+                        let scope = MemoryScope {
+                            scope: &entry.scope,
+                            scope_value: entry.scope_value.as_deref(),
+                            expires_at: current.expires_at,
+                        };
+                        db.user_memory_update_with_scope(
+                            &id,
+                            text_changed.then_some(entry.text.as_str()),
+                            None,
+                            None,
+                            text_changed.then_some(true),
+                            scope_changed.then_some(&scope),
+                        )?;
+                        report.updated += 1;
+                    }
+                    SyncAction::Delete { id } => {
+                        if db.user_memory_delete(&id)? {
+                            report.deleted += 1;
+                        }
+                    }
+                }
+            }
+            report.conflicts = plan.conflicts;
+        }
+
+        let now = chrono::Utc::now().timestamp_millis();
+        let memories = self.list_user_memories().await?;
+        let active = cowork_memory_sync::synced_memories(&memories, &conflict_ids, now);
+        let rendered = cowork_memory_sync::render(&active);
+        if file_content.as_deref() != Some(rendered.as_str()) {
+            std::fs::write(&path, &rendered)?;
+            report.exported = true;
+        }
+
+        let new_state = SyncState {
+            file_hash: Some(cowork_memory_sync::content_hash(&rendered)),
+            db_fingerprint: self.database.lock().await.user_memories_fingerprint().ok(),
+            synced_at: now,
+            entries: active
+                .iter()
+                .map(|m| (m.id.clone(), cowork_memory_sync::SyncEntry::from_memory(m)))
+                .collect(),
+            // 已确认或丢弃的冲突副本不再保留
+            conflict_ids: active
+                .iter()
+                .filter(|m| m.status == cowork_memory::STATUS_PENDING)
+                .map(|m| m.id.clone())
+                .collect(),
+        };
+        std::fs::write(&state_path, serde_json::to_string_pretty(&new_state)?)?;
+
+        if report.has_changes() {
+            println!(
+                "[Cowork] Synced memories file: created {}, updated {}, deleted {}, conflicts {}",
+                report.created,
+                report.updated,
+                report.deleted,
+                report.conflicts.len()
+            );
+        }
+        Ok(report)
+    }
+
+    /// 记忆文件或数据库中的记忆自上次同步后是否有变化
+    async fn memory_file_outdated(&self) -> anyhow::Result<bool> {
+        let data_dir = self.data_dir()?;
+        let state = match cowork_memory_sync::load_state(&data_dir.join(cowork_memory_sync::SYNC_STATE_FILE_NAME)) {
+            Some(state) => state,
+            None => return Ok(true),
+        };
+        let file_hash = std::fs::read_to_string(data_dir.join(cowork_memory_sync::MEMORY_FILE_NAME))
+            .ok()
+            .map(|content| cowork_memory_sync::content_hash(&content));
+        if file_hash.is_none() || file_hash != state.file_hash {
+            return Ok(true);
+        }
+        let fingerprint = self.database.lock().await.user_memories_fingerprint()?;
+        Ok(state.db_fingerprint.as_deref() != Some(fingerprint.as_str()))
+    }
+
+    /// 后台轮询 memories.md，开启 memory_file_sync 配置后在文件或记忆变化时自动同步
+    pub async fn run_memory_file_watcher(self) {
+        let mut interval = tokio::time::interval(MEMORY_FILE_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !self.config_enabled(MEMORY_FILE_SYNC_KEY, false).await {
+                continue;
+            }
+            match self.memory_file_outdated().await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    println!("[Cowork] Failed to check memories file: {}", e);
+                    continue;
+                }
+            }
+            match self.sync_memory_file().await {
+                Ok(report) if report.has_changes() => {
+                    self.emit("cowork:memoriesSynced", serde_json::json!(report));
+                }
+                Ok(_) => {}
+                Err(e) => println!("[Cowork] Failed to sync memories file: {}", e),
+            }
+        }
+    }
+
+    pub async fn list_user_memory_sources(&self, memory_id: String) -> anyhow::Result<Vec<serde_json::Value>> {
+        let db = self.database.lock().await;
+        Ok(db.user_memory_list_sources(&memory_id)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn manager(dir: &std::path::Path) -> CoworkManager {
+        let db = Database::new(dir.join("test.db")).unwrap();
+        CoworkManager::new(Arc::new(Mutex::new(db)))
+    }
+
+    #[tokio::test]
+    async fn test_fork_session() {
+        let temp_dir = tempdir().unwrap();
+        let manager = manager(temp_dir.path());
+        let session = manager
+            .create_session(
+                "Original".to_string(),
+                Some("/tmp/project".to_string()),
+                Some("Be brief.".to_string()),
+                None,
+            )
+            .await
+            .unwrap();
+        for (msg_type, content) in [("user", "q1"), ("assistant", "a1"), ("user", "q2"), ("assistant", "a2")] {
+            manager
+                .add_message(session.id.clone(), msg_type.to_string(), content.to_string())
+                .await
+                .unwrap();
+        }
+
+        let fork = manager.fork_session(session.id.clone(), 2, None).await.unwrap();
+        assert_ne!(fork.id, session.id);
+        assert_eq!(fork.title, "Original (fork)");
+        assert_eq!(fork.system_prompt.as_deref(), Some("Be brief."));
+        assert_eq!(fork.parent_session_id.as_deref(), Some(session.id.as_str()));
+        assert_eq!(fork.fork_sequence, Some(2));
+
+        let messages = manager.list_messages(fork.id.clone()).await.unwrap();
+        let contents: Vec<_> = messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, vec!["q1", "a1"]);
+        assert_eq!(manager.list_messages(session.id.clone()).await.unwrap().len(), 4);
+
+        assert!(manager.fork_session(session.id, 99, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fork_session_remaps_tool_messages() {
+        let temp_dir = tempdir().unwrap();
+        let manager = manager(temp_dir.path());
+        let session = manager
+            .create_session("Tools".to_string(), None, None, None)
+            .await
+            .unwrap();
+        manager
+            .add_message(session.id.clone(), "user".to_string(), "list files".to_string())
+            .await
+            .unwrap();
+        let reply = manager
+            .add_message(session.id.clone(), "assistant".to_string(), "Found a.txt".to_string())
+            .await
+            .unwrap();
+        let mut running_tools = HashMap::new();
+        let call = ToolEvent::Call {
+            call_id: "call_1".to_string(),
+            name: "shell".to_string(),
+            arguments: serde_json::json!({ "command": "ls" }),
+        };
+        assert!(manager.on_tool_event(&session.id, &reply.id, call, 0, &mut running_tools).await);
+        let result = ToolEvent::Result(cowork_tools::ToolResult {
+            call_id: "call_1".to_string(),
+            stdout: "a.txt".to_string(),
+            ..Default::default()
+        });
+        assert!(manager.on_tool_event(&session.id, &reply.id, result, 0, &mut running_tools).await);
+
+        let fork = manager.fork_session(session.id.clone(), 4, None).await.unwrap();
+        let messages = manager.list_messages(fork.id.clone()).await.unwrap();
+        let types: Vec<&str> = messages.iter().map(|m| m.r#type.as_str()).collect();
+        assert_eq!(types, vec!["user", "assistant", "tool_call", "tool_result"]);
+        assert_ne!(messages[1].id, reply.id);
+        // 工具消息引用分支中的回复和调用消息，而不是原会话中的
+        let call_metadata = parse_metadata(messages[2].metadata.as_deref());
+        assert_eq!(call_metadata["reply_id"], messages[1].id.as_str());
+        let result_metadata = parse_metadata(messages[3].metadata.as_deref());
+        assert_eq!(result_metadata["reply_id"], messages[1].id.as_str());
+        assert_eq!(result_metadata["tool_call_message_id"], messages[2].id.as_str());
+
+        // 只复制到调用消息时，结果消息不在分支中
+        let partial = manager.fork_session(session.id, 3, None).await.unwrap();
+        let messages = manager.list_messages(partial.id).await.unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(parse_metadata(messages[2].metadata.as_deref())["reply_id"], messages[1].id.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_session_tags_and_archive() {
+        let temp_dir = tempdir().unwrap();
+        let manager = manager(temp_dir.path());
+        let session = manager
+            .create_session("Original".to_string(), None, None, None)
+            .await
+            .unwrap();
+        manager
+            .add_message(session.id.clone(), "user".to_string(), "q1".to_string())
+            .await
+            .unwrap();
+
+        let tagged = manager
+            .set_session_tags(session.id.clone(), vec!["report".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(tagged.tags, vec!["report".to_string()]);
+        manager.set_session_archived(session.id.clone(), true).await.unwrap();
+
+        // 分支沿用标签，但不继承归档状态；默认列表不包含已归档的会话
+        let fork = manager.fork_session(session.id.clone(), 1, None).await.unwrap();
+        assert_eq!(fork.tags, vec!["report".to_string()]);
+        assert!(fork.archived_at.is_none());
+        let page = manager
+            .list_sessions_page(Some("report".to_string()), None, None, None, None, None, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!((page.total, page.sessions[0].id.as_str(), page.has_more), (1, fork.id.as_str(), false));
+
+        // 会话不存在时报错
+        let missing = "session_missing".to_string();
+        assert!(manager.set_session_tags(missing.clone(), vec!["ghost".to_string()]).await.is_err());
+        assert!(manager.set_session_archived(missing, true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_session_templates() {
+        let temp_dir = tempdir().unwrap();
+        let manager = manager(temp_dir.path());
+        let session = manager
+            .create_session(
+                "Original".to_string(),
+                Some("/tmp/project".to_string()),
+                Some("Review carefully.".to_string()),
+                Some("sandbox".to_string()),
+            )
+            .await
+            .unwrap();
+        manager
+            .update_session(session.id.clone(), None, None, None, None, None, None, Some(r#"["git"]"#.to_string()))
+            .await
+            .unwrap();
+        manager.set_context_budget(session.id.clone(), Some(12000)).await.unwrap();
+
+        let template = manager
+            .save_session_as_template(session.id.clone(), " Review ".to_string())
+            .await
+            .unwrap();
+        assert_eq!(template.name, "Review");
+        assert_eq!(template.active_skill_ids.as_deref(), Some(r#"["git"]"#));
+        assert!(manager.save_session_as_template(session.id, "  ".to_string()).await.is_err());
+
+        let created = manager
+            .create_session_from_template(template.id.clone(), None, Some("/tmp/other".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(created.title, "Review");
+        assert_eq!(created.cwd.as_deref(), Some("/tmp/other"));
+        assert_eq!(created.system_prompt.as_deref(), Some("Review carefully."));
+        assert_eq!(created.execution_mode.as_deref(), Some("sandbox"));
+        assert_eq!(created.active_skill_ids.as_deref(), Some(r#"["git"]"#));
+        assert_eq!(created.context_budget, Some(12000));
+        // 模板名称作为默认标题，仍允许被自动标题替换
+        assert!(created.title_source.is_none());
+
+        // 传入空字符串清除工作目录和系统提示词
+        let cleared = manager
+            .update_template(template.id.clone(), None, Some(String::new()), Some(String::new()), None, None, None)
+            .await
+            .unwrap();
+        assert!(cleared.cwd.is_none() && cleared.system_prompt.is_none());
+        assert_eq!(cleared.execution_mode.as_deref(), Some("sandbox"));
+
+        manager.delete_template(template.id.clone()).await.unwrap();
+        assert!(manager.create_session_from_template(template.id, None, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_usage_summary() {
+        let temp_dir = tempdir().unwrap();
+        let manager = manager(temp_dir.path());
+        let session = manager
+            .create_session("Usage".to_string(), None, None, None)
+            .await
+            .unwrap();
+        {
+            let db = manager.database.lock().await;
+            db.cowork_usage_record("msg_1", &session.id, Some("gpt-4o"), 1_000_000, 0, false).unwrap();
+            db.cowork_usage_record("msg_2", &session.id, None, 1000, 500, true).unwrap();
+        }
+        assert!(manager.price_table().await.models.is_empty());
+        let mut prices = PriceTable::default();
+        prices.models.insert("gpt-4o".to_string(), cowork_usage::ModelPrice { prompt: 2.5, completion: 10.0 });
+        manager.set_price_table(prices.clone()).await.unwrap();
+        assert_eq!(manager.price_table().await, prices);
+
+        let summary = manager
+            .usage_summary("session".to_string(), None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(summary.rows.len(), 1);
+        assert_eq!(summary.rows[0].title.as_deref(), Some("Usage"));
+        assert_eq!((summary.rows[0].replies, summary.rows[0].estimated_replies), (2, 1));
+        assert!((summary.total.cost - 2.5).abs() < 1e-9);
+        assert_eq!(summary.total.unpriced_tokens, 1500);
+        assert_eq!(manager.usage_summary("day".to_string(), None, None, None).await.unwrap().rows.len(), 1);
+        assert!(manager.usage_summary("model".to_string(), None, None, None).await.is_err());
+
+        prices.models.insert("bad".to_string(), cowork_usage::ModelPrice { prompt: -1.0, completion: 0.0 });
+        assert!(manager.set_price_table(prices).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_edit_and_regenerate() {
+        let temp_dir = tempdir().unwrap();
+        let manager = manager(temp_dir.path());
+        let session = manager
+            .create_session("Original".to_string(), None, None, None)
+            .await
+            .unwrap();
+        let mut ids = Vec::new();
+        for (msg_type, content) in [("user", "q1"), ("assistant", "a1"), ("user", "q2"), ("assistant", "a2")] {
+            let msg = manager
+                .add_message(session.id.clone(), msg_type.to_string(), content.to_string())
+                .await
+                .unwrap();
+            ids.push(msg.id);
+        }
+
+        // 未连接 GoClaw 时回复失败，但历史已按编辑结果改写
+        let result = manager
+            .edit_and_resend(session.id.clone(), ids[0].clone(), "q1 edited".to_string(), true)
+            .await;
+        assert!(result.is_err());
+        let messages = manager.list_messages(session.id.clone()).await.unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].content, "q1 edited");
+        let metadata = parse_metadata(messages[0].metadata.as_deref());
+        let archived_id = metadata["archived_session_id"].as_str().unwrap().to_string();
+        assert_eq!(manager.list_messages(archived_id).await.unwrap().len(), 4);
+
+        // 新回复使用新的消息 id，原回复的用量记录不受影响
+        let previous_id = messages[1].id.clone();
+        assert!(manager.regenerate_reply(session.id.clone()).await.is_err());
+        let messages = manager.list_messages(session.id.clone()).await.unwrap();
+        assert_eq!(messages.len(), 2);
+        let reply = &messages[1];
+        assert_ne!(reply.id, previous_id);
+        let metadata = parse_metadata(reply.metadata.as_deref());
+        assert_eq!(metadata["alternates"].as_array().unwrap().len(), 1);
+
+        let selected = manager
+            .select_alternate(session.id.clone(), reply.id.clone(), 0)
+            .await
+            .unwrap();
+        assert_eq!(selected.content, "消息已接收");
+        assert_eq!(parse_metadata(selected.metadata.as_deref())["alternates"][0]["content"], reply.content);
+
+        assert!(manager
+            .edit_and_resend(session.id.clone(), reply.id.clone(), "x".to_string(), false)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reply_in_flight() {
+        let temp_dir = tempdir().unwrap();
+        let manager = manager(temp_dir.path());
+        let session = manager
+            .create_session("Busy".to_string(), None, None, None)
+            .await
+            .unwrap();
+
+        // 回复处理中时拒绝同一会话的新请求，取消在请求发出前也能生效
+        let guard = manager.begin_reply(&session.id).unwrap();
+        assert!(manager
+            .send_message_with_error(session.id.clone(), "q".to_string(), Vec::new())
+            .await
+            .is_err());
+        assert!(manager.list_messages(session.id.clone()).await.unwrap().is_empty());
+        assert!(manager.cancel(session.id.clone()).await.unwrap());
+        assert!(guard.is_cancelled());
+
+        drop(guard);
+        assert!(!manager.cancel(session.id.clone()).await.unwrap());
+        manager
+            .send_message_with_error(session.id.clone(), "q".to_string(), Vec::new())
+            .await
+            .unwrap();
+        assert!(manager.streams.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tool_messages() {
+        let temp_dir = tempdir().unwrap();
+        let manager = manager(temp_dir.path());
+        let session = manager
+            .create_session("Tools".to_string(), None, None, None)
+            .await
+            .unwrap();
+        manager
+            .add_message(session.id.clone(), "user".to_string(), "list files".to_string())
+            .await
+            .unwrap();
+        let reply = manager
+            .add_message(session.id.clone(), "assistant".to_string(), String::new())
+            .await
+            .unwrap();
+
+        let mut running_tools = HashMap::new();
+        let call = ToolEvent::Call {
+            call_id: "call_1".to_string(),
+            name: "shell".to_string(),
+            arguments: serde_json::json!({ "command": "ls" }),
+        };
+        assert!(manager.on_tool_event(&session.id, &reply.id, call, 12, &mut running_tools).await);
+        let result = ToolEvent::Result(cowork_tools::ToolResult {
+            call_id: "call_1".to_string(),
+            stdout: "a.txt".to_string(),
+            exit_code: Some(1),
+            ..Default::default()
+        });
+        assert!(manager.on_tool_event(&session.id, &reply.id, result, 12, &mut running_tools).await);
+        assert!(running_tools.is_empty());
+        {
+            let db = manager.database.lock().await;
+            db.cowork_update_message(&reply.id, &session.id, Some("Let me check. Found a.txt"), None)
+                .unwrap();
+        }
+
+        // 回复保持原位，工具消息记录其在回复正文中的位置
+        let messages = manager.list_messages(session.id.clone()).await.unwrap();
+        let types: Vec<&str> = messages.iter().map(|m| m.r#type.as_str()).collect();
+        assert_eq!(types, vec!["user", "assistant", "tool_call", "tool_result"]);
+        let call_metadata = parse_metadata(messages[2].metadata.as_deref());
+        assert_eq!(call_metadata["tool_call"]["status"], cowork_tools::STATUS_ERROR);
+        assert!(call_metadata["tool_call"]["duration_ms"].is_i64());
+        assert_eq!(call_metadata["reply_offset"], 12);
+        let offset = call_metadata["reply_offset"].as_u64().unwrap() as usize;
+        let before: String = messages[1].content.chars().take(offset).collect();
+        assert_eq!(before, "Let me check");
+        let result_metadata = parse_metadata(messages[3].metadata.as_deref());
+        assert_eq!(result_metadata["tool_result"]["name"], "shell");
+        assert_eq!(result_metadata["tool_call_message_id"], messages[2].id.as_str());
+        assert_eq!(result_metadata["reply_offset"], 12);
+
+        // 工具消息只用于展示和回放，不进入模型上下文
+        let request = manager.build_chat_request(&session.id, None).await.unwrap();
+        assert_eq!(request.messages.len(), 2);
+
+        // 重新生成时上一轮的工具消息一并删除
+        assert!(manager.regenerate_reply(session.id.clone()).await.is_err());
+        let messages = manager.list_messages(session.id.clone()).await.unwrap();
+        let types: Vec<&str> = messages.iter().map(|m| m.r#type.as_str()).collect();
+        assert_eq!(types, vec!["user", "assistant"]);
+    }
+
+    #[tokio::test]
+    async fn test_tool_approvals() {
+        let temp_dir = tempdir().unwrap();
+        let manager = manager(temp_dir.path());
+        let session = manager
+            .create_session("Approvals".to_string(), Some("/work".to_string()), None, None)
+            .await
+            .unwrap();
+        let request = |id: &str, command: &str| ApprovalRequest {
+            id: id.to_string(),
+            session_id: session.id.clone(),
+            tool: "shell".to_string(),
+            command: Some(command.to_string()),
+            path: None,
+            arguments: serde_json::json!({ "command": command }),
+            requested_at: 0,
+        };
+
+        // 没有规则时等待用户决定
+        manager.on_approval_request(request("a1", "git status"), "msg_1").await;
+        assert_eq!(manager.list_pending_approvals(Some(session.id.clone())).len(), 1);
+        assert!(manager
+            .respond_approval("a1".to_string(), true, Some("forever".to_string()), None, None)
+            .await
+            .is_err());
+        let rule = manager
+            .respond_approval("a1".to_string(), true, Some("session".to_string()), Some("git *".to_string()), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((rule.command_pattern.as_deref(), rule.session_id.as_deref()), (Some("git *"), Some(session.id.as_str())));
+        assert!(manager.list_pending_approvals(None).is_empty());
+        assert!(manager.respond_approval("a1".to_string(), true, None, None, None).await.is_err());
+
+        // 规则自动放行，不匹配的命令仍需询问
+        manager.on_approval_request(request("a2", "git log"), "msg_1").await;
+        manager.on_approval_request(request("a3", "rm -rf build"), "msg_1").await;
+        let pending = manager.list_pending_approvals(None);
+        assert_eq!(pending.iter().map(|a| a.id.as_str()).collect::<Vec<_>>(), vec!["a3"]);
+
+        // 等待审批期间不计空闲超时，答复后通知回复重新计时
+        let mut guard = manager.begin_reply(&session.id).unwrap();
+        assert!(manager.approval_pending(&session.id));
+        manager.respond_approval("a3".to_string(), false, None, None, None).await.unwrap();
+        assert!(!manager.approval_pending(&session.id));
+        assert!(matches!(guard.events.try_recv(), Ok(StreamEvent::ApprovalAnswered)));
+        drop(guard);
+
+        let audit = manager.list_approval_audit(Some(session.id.clone()), None, None).await.unwrap();
+        let entries: Vec<(&str, &str)> = audit
+            .iter()
+            .map(|e| (e["decision"].as_str().unwrap(), e["source"].as_str().unwrap()))
+            .collect();
+        assert_eq!(entries, vec![("deny", "user"), ("allow", "rule"), ("allow", "user")]);
+        assert_eq!(audit[1]["rule_id"], rule.id.as_str());
+
+        assert!(manager
+            .add_permission_rule(" ".to_string(), None, None, None, true)
+            .await
+            .is_err());
+        let global = manager
+            .add_permission_rule("write_file".to_string(), None, Some("/work".to_string()), None, false)
+            .await
+            .unwrap();
+        assert_eq!(manager.list_permission_rules(Some(session.id.clone())).await.unwrap().len(), 2);
+        assert!(manager.delete_permission_rule(global.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sync_memory_file() {
+        let temp_dir = tempdir().unwrap();
+        let mut manager = manager(temp_dir.path());
+        manager.set_data_dir(temp_dir.path().to_path_buf());
+        let kept = manager
+            .create_user_memory("Prefers concise answers".to_string(), 1.0, true, None, None, None)
+            .await
+            .unwrap();
+        let removed = manager
+            .create_user_memory("Uses npm".to_string(), 1.0, true, None, None, None)
+            .await
+            .unwrap();
+
+        let report = manager.sync_memory_file().await.unwrap();
+        assert!(report.exported);
+        let path = temp_dir.path().join(cowork_memory_sync::MEMORY_FILE_NAME);
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains(&kept.id) && content.contains(&removed.id));
+        assert!(!manager.memory_file_outdated().await.unwrap());
+
+        // 文件中修改一条、删除一条、新增一条，同时在应用中修改被修改的那条
+        let edited: String = content
+            .lines()
+            .filter(|line| !line.contains(&removed.id))
+            .map(|line| line.replace("Prefers concise answers", "Prefers bullet points"))
+            .chain(["- Uses pnpm".to_string()])
+            .collect::<Vec<_>>()
+            .join("\n");
+        std::fs::write(&path, edited).unwrap();
+        manager
+            .update_user_memory(
+                kept.id.clone(),
+                Some("Prefers short answers".to_string()),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(manager.memory_file_outdated().await.unwrap());
+
+        let report = manager.sync_memory_file().await.unwrap();
+        assert_eq!((report.created, report.deleted), (2, 1));
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].memory_id, kept.id);
+
+        let memories = manager.list_user_memories().await.unwrap();
+        let text_of = |text: &str| memories.iter().find(|m| m.text == text).map(|m| m.status.clone());
+        assert_eq!(text_of("Prefers short answers").as_deref(), Some("created"));
+        assert_eq!(text_of("Prefers bullet points").as_deref(), Some(cowork_memory::STATUS_PENDING));
+        assert_eq!(text_of("Uses pnpm").as_deref(), Some("created"));
+        assert_eq!(text_of("Uses npm").as_deref(), Some("deleted"));
+
+        // 冲突副本确认前仍写在文件中，再次同步不会产生新的冲突
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("Prefers short answers") && content.contains("Uses pnpm"));
+        assert!(content.contains("Prefers bullet points") && content.contains("待确认"));
+        assert!(!content.contains("Uses npm"));
+        std::fs::write(&path, format!("{}\n", content)).unwrap();
+        let report = manager.sync_memory_file().await.unwrap();
+        assert!(report.conflicts.is_empty() && report.created == 0);
+
+        // 在文件中删除冲突副本即丢弃
+        let pending_id = memories.iter().find(|m| m.text == "Prefers bullet points").unwrap().id.clone();
+        let content = std::fs::read_to_string(&path).unwrap();
+        let edited: String = content
+            .lines()
+            .filter(|line| !line.contains(&pending_id))
+            .collect::<Vec<_>>()
+            .join("\n");
+        std::fs::write(&path, edited).unwrap();
+        let report = manager.sync_memory_file().await.unwrap();
+        assert_eq!(report.deleted, 1);
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("Prefers bullet points") && !content.contains("待确认"));
+    }
+
+    #[tokio::test]
+    async fn test_revert_turn() {
+        let temp_dir = tempdir().unwrap();
+        let mut manager = manager(temp_dir.path());
+        manager.set_data_dir(temp_dir.path().to_path_buf());
+        let work = temp_dir.path().join("work");
+        std::fs::create_dir_all(&work).unwrap();
+        std::fs::write(work.join("a.txt"), "v1").unwrap();
+        let session = manager
+            .create_session("Files".to_string(), Some(work.to_string_lossy().to_string()), None, None)
+            .await
+            .unwrap();
+
+        // chat.tool_call 通知到达时文件可能已被写入，不据此保存快照
+        let call = ToolEvent::Call {
+            call_id: "call_1".to_string(),
+            name: "edit_file".to_string(),
+            arguments: serde_json::json!({ "path": "a.txt" }),
+        };
+        assert!(manager.on_tool_event(&session.id, "msg_0", call, 0, &mut HashMap::new()).await);
+        assert!(manager.list_snapshot_turns(session.id.clone()).await.unwrap().is_empty());
+
+        // 第一轮：修改 a.txt 并新建 b.txt，同一文件只保留第一次修改前的内容
+        let edit = serde_json::json!({ "path": "a.txt" });
+        manager.snapshot_before_tool(&session.id, "msg_1", "edit_file", &edit).await;
+        std::fs::write(work.join("a.txt"), "v2").unwrap();
+        manager.snapshot_before_tool(&session.id, "msg_1", "edit_file", &edit).await;
+        manager
+            .snapshot_before_tool(&session.id, "msg_1", "write_file", &serde_json::json!({ "path": "b.txt" }))
+            .await;
+        manager
+            .snapshot_before_tool(&session.id, "msg_1", "write_file", &serde_json::json!({ "path": "/etc/hosts" }))
+            .await;
+        std::fs::write(work.join("a.txt"), "v3").unwrap();
+        std::fs::write(work.join("b.txt"), "new").unwrap();
+        manager.record_snapshot_results(&session.id, "msg_1").await;
+
+        let turns = manager.list_snapshot_turns(session.id.clone()).await.unwrap();
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].files.len(), 2);
+
+        // 这一轮之后文件又被改动时拒绝撤销，任何文件都不动
+        std::fs::write(work.join("a.txt"), "edited by user").unwrap();
+        let error = manager.revert_turn(session.id.clone(), "msg_1".to_string()).await.unwrap_err();
+        assert!(error.to_string().contains("a.txt"));
+        assert!(work.join("b.txt").exists());
+        std::fs::write(work.join("a.txt"), "v3").unwrap();
+        let results = manager.revert_turn(session.id.clone(), "msg_1".to_string()).await.unwrap();
+        let actions: Vec<&str> = results.iter().map(|r| r.action.as_str()).collect();
+        assert_eq!(actions, vec!["deleted", "restored"]);
+        assert_eq!(std::fs::read_to_string(work.join("a.txt")).unwrap(), "v1");
+        assert!(!work.join("b.txt").exists());
+        assert!(manager.revert_turn(session.id.clone(), "msg_1".to_string()).await.is_err());
+        assert!(manager.revert_turn(session.id.clone(), "msg_x".to_string()).await.is_err());
+
+        // 超过保留轮数的快照及其内容被清理
+        manager.set_config(SNAPSHOT_RETENTION_KEY.to_string(), "1".to_string()).await.unwrap();
+        std::fs::write(work.join("a.txt"), "v4").unwrap();
+        manager.snapshot_before_tool(&session.id, "msg_2", "edit_file", &edit).await;
+        manager.prune_snapshots(&session.id).await;
+        let turns = manager.list_snapshot_turns(session.id.clone()).await.unwrap();
+        assert_eq!(turns.iter().map(|t| t.turn_id.as_str()).collect::<Vec<_>>(), vec!["msg_2"]);
+        let blobs = cowork_snapshots::stored_blobs(&temp_dir.path().join(cowork_snapshots::SNAPSHOTS_DIR));
+        assert_eq!(blobs, vec![cowork_attachments::sha256_hex(b"v4")]);
+
+        // 沙箱模式下不保存快照
+        manager
+            .update_session(session.id.clone(), None, None, None, None, None, Some("sandbox".to_string()), None)
+            .await
+            .unwrap();
+        manager.snapshot_before_tool(&session.id, "msg_3", "edit_file", &edit).await;
+        assert_eq!(manager.list_snapshot_turns(session.id.clone()).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_turn_with_missing_snapshots_is_not_revertible() {
+        let temp_dir = tempdir().unwrap();
+        let mut manager = manager(temp_dir.path());
+        manager.set_data_dir(temp_dir.path().to_path_buf());
+        let work = temp_dir.path().join("work");
+        std::fs::create_dir_all(&work).unwrap();
+        std::fs::write(work.join("a.txt"), "v1").unwrap();
+        let session = manager
+            .create_session("Files".to_string(), Some(work.to_string_lossy().to_string()), None, None)
+            .await
+            .unwrap();
+        let call = |call_id: &str, name: &str, path: &str| ToolEvent::Call {
+            call_id: call_id.to_string(),
+            name: name.to_string(),
+            arguments: serde_json::json!({ "path": path }),
+        };
+        let mut running_tools = HashMap::new();
+
+        // 经过审批的修改已有快照，这一轮可以撤销
+        manager
+            .snapshot_before_tool(&session.id, "msg_1", "edit_file", &serde_json::json!({ "path": "a.txt" }))
+            .await;
+        let event = call("call_1", "edit_file", "a.txt");
+        assert!(manager.on_tool_event(&session.id, "msg_1", event, 0, &mut running_tools).await);
+        let event = call("call_2", "read_file", "c.txt");
+        assert!(manager.on_tool_event(&session.id, "msg_1", event, 0, &mut running_tools).await);
+        std::fs::write(work.join("a.txt"), "v2").unwrap();
+        manager.record_missing_snapshots(&session.id, "msg_1").await;
+        let turns = manager.list_snapshot_turns(session.id.clone()).await.unwrap();
+        assert!(turns[0].revertible);
+        assert_eq!(turns[0].files.len(), 1);
+
+        // 未经审批就执行的写入没有快照，整轮都不能撤销
+        manager
+            .snapshot_before_tool(&session.id, "msg_2", "edit_file", &serde_json::json!({ "path": "a.txt" }))
+            .await;
+        let event = call("call_3", "write_file", "b.txt");
+        assert!(manager.on_tool_event(&session.id, "msg_2", event, 0, &mut running_tools).await);
+        std::fs::write(work.join("a.txt"), "v3").unwrap();
+        std::fs::write(work.join("b.txt"), "new").unwrap();
+        manager.record_missing_snapshots(&session.id, "msg_2").await;
+        manager.record_snapshot_results(&session.id, "msg_2").await;
+        let turns = manager.list_snapshot_turns(session.id.clone()).await.unwrap();
+        assert_eq!(turns[0].turn_id, "msg_2");
+        assert!(!turns[0].revertible);
+        assert!(turns[0].files.iter().any(|f| f.missing && f.path.ends_with("b.txt")));
+
+        let error = manager.revert_turn(session.id.clone(), "msg_2".to_string()).await.unwrap_err();
+        assert!(error.to_string().contains("b.txt"));
+        assert_eq!(std::fs::read_to_string(work.join("a.txt")).unwrap(), "v3");
+        assert!(work.join("b.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_workspace_instructions() {
+        let temp_dir = tempdir().unwrap();
+        let manager = manager(temp_dir.path());
+        let work = temp_dir.path().join("work");
+        std::fs::create_dir_all(&work).unwrap();
+        let workspace = work.to_string_lossy().to_string();
+        let session = manager
+            .create_session("First".to_string(), Some(workspace.clone()), None, None)
+            .await
+            .unwrap();
+        manager
+            .create_session("Second".to_string(), Some(format!("{}/", workspace)), None, None)
+            .await
+            .unwrap();
+        manager
+            .create_session("Elsewhere".to_string(), Some("/tmp/elsewhere".to_string()), None, None)
+            .await
+            .unwrap();
+
+        let request = manager.build_chat_request(&session.id, None).await.unwrap();
+        assert!(!request.system_prompt.unwrap_or_default().contains("Project instructions"));
+
+        // 说明文件创建和修改后，下一次请求使用最新内容
+        std::fs::write(work.join("AGENTS.md"), "Run cargo fmt before committing.").unwrap();
+        let system_prompt = manager.build_chat_request(&session.id, None).await.unwrap().system_prompt.unwrap();
+        assert!(system_prompt.contains("## Project instructions (AGENTS.md)"));
+        assert!(system_prompt.contains("Run cargo fmt before committing."));
+        std::fs::write(work.join("AGENTS.md"), "Always write tests for new code.").unwrap();
+        let system_prompt = manager.build_chat_request(&session.id, None).await.unwrap().system_prompt.unwrap();
+        assert!(system_prompt.contains("Always write tests") && !system_prompt.contains("cargo fmt"));
+
+        let workspaces = manager.list_workspaces().await.unwrap();
+        assert_eq!(workspaces.len(), 2);
+        let current = workspaces.iter().find(|w| w.path == workspace).unwrap();
+        assert_eq!((current.name.as_str(), current.session_count), ("work", 2));
+        assert!(current.instructions_path.as_deref().unwrap().ends_with("AGENTS.md"));
+        let page = manager
+            .list_workspace_sessions(format!("{}/", workspace), None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(page.total, 2);
+
+        manager
+            .set_config(WORKSPACE_INSTRUCTIONS_KEY.to_string(), "false".to_string())
+            .await
+            .unwrap();
+        let request = manager.build_chat_request(&session.id, None).await.unwrap();
+        assert!(!request.system_prompt.unwrap_or_default().contains("Project instructions"));
+    }
+
+    #[tokio::test]
+    async fn test_image_attachments() {
+        let temp_dir = tempdir().unwrap();
+        let mut manager = manager(temp_dir.path());
+        manager.set_data_dir(temp_dir.path().to_path_buf());
+        let session = manager
+            .create_session("Images".to_string(), None, None, None)
+            .await
+            .unwrap();
+
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(900, 300))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let input = ImageInput {
+            data: cowork_images::data_url("image/png", &png),
+            name: Some("screenshot.png".to_string()),
+            mime_type: None,
+        };
+        manager
+            .set_config(IMAGE_MAX_DIMENSION_KEY.to_string(), "300".to_string())
+            .await
+            .unwrap();
+        let attachments = manager.store_images(vec![input.clone()]).await.unwrap();
+        assert_eq!((attachments[0].width, attachments[0].height), (Some(300), Some(100)));
+        let thumbnail_sha256 = attachments[0].thumbnail_sha256.clone().unwrap();
+        // 相同图片只保存一份
+        assert_eq!(manager.store_images(vec![input]).await.unwrap(), attachments);
+
+        let (message, error) = manager
+            .send_message_with_error(session.id.clone(), String::new(), attachments.clone())
+            .await
+            .unwrap();
+        assert!(error.is_some() && message.r#type == "assistant");
+        let request = manager.build_chat_request(&session.id, None).await.unwrap();
+        assert_eq!(request.messages[0].images.len(), 1);
+        assert!(request.messages[0].images[0].data_url.starts_with("data:image/png;base64,"));
+        assert!(request.messages[0].attachments[0].path.is_some());
+
+        let thumbnails = manager.session_thumbnails().await.unwrap();
+        assert_eq!(thumbnails.get(&session.id), Some(&thumbnail_sha256));
+        assert!(manager.read_attachment(thumbnail_sha256).await.unwrap().starts_with("data:image/jpeg;base64,"));
+        assert!(manager.read_attachment("0".repeat(64)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_document_attachments() {
+        let temp_dir = tempdir().unwrap();
+        let mut manager = manager(temp_dir.path());
+        manager.set_data_dir(temp_dir.path().to_path_buf());
+        let session = manager
+            .create_session("Documents".to_string(), None, None, None)
+            .await
+            .unwrap();
+
+        let notes = manager
+            .store_attachment("notes.md".to_string(), "第一行\nsecond line".as_bytes().to_vec(), None)
+            .await
+            .unwrap();
+        let archive = manager
+            .store_attachment("data.zip".to_string(), b"PK".to_vec(), None)
+            .await
+            .unwrap();
+        manager
+            .set_config(DOCUMENT_MAX_CHARS_KEY.to_string(), "3".to_string())
+            .await
+            .unwrap();
+        manager
+            .send_message_with_error(session.id.clone(), "Summarize".to_string(), vec![notes.clone(), archive])
+            .await
+            .unwrap();
+
+        let request = manager.build_chat_request(&session.id, None).await.unwrap();
+        let expected = "Summarize\n\n<attachment name=\"notes.md\">\n第一行\n\n[... truncated, 12 more characters]\n</attachment>";
+        assert_eq!(request.messages[0].content, expected);
+        assert_eq!(request.content, expected);
+        // 提取结果被缓存，删除附件时一并清理
+        let store = manager.attachment_store().unwrap();
+        assert!(store.read_text(&notes.sha256).is_some());
+        store.remove(&notes.sha256).unwrap();
+        assert!(store.read_text(&notes.sha256).is_none());
+    }
 }
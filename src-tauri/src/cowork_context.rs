@@ -0,0 +1,257 @@
+use crate::cowork::CoworkMessage;
+use crate::cowork_prompt::ChatMessage;
+use serde::{Deserialize, Serialize};
+
+/// 未单独配置时每个会话使用的上下文预算（token）
+pub const DEFAULT_CONTEXT_BUDGET: i64 = 16_000;
+/// 压缩时保留原文的最近消息条数下限
+const MIN_RECENT_MESSAGES: usize = 4;
+/// 压缩后最近消息最多占用预算的比例，其余留给摘要和新回复
+const RECENT_BUDGET_RATIO: f64 = 0.6;
+/// 每条消息的角色、分隔符等固定开销
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+pub const SUMMARY_MESSAGE_TYPE: &str = "summary";
+const SUMMARY_KIND: &str = "context_summary";
+
+/// 存储在摘要消息 metadata 中的信息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SummaryMetadata {
+    pub kind: String,
+    /// 摘要已覆盖到的最大消息序号
+    pub covers_through: i32,
+    pub token_estimate: usize,
+}
+
+impl SummaryMetadata {
+    pub fn new(covers_through: i32, summary: &str) -> Self {
+        SummaryMetadata {
+            kind: SUMMARY_KIND.to_string(),
+            covers_through,
+            token_estimate: estimate_tokens(summary),
+        }
+    }
+
+    pub fn from_message(message: &CoworkMessage) -> Option<Self> {
+        if message.r#type != SUMMARY_MESSAGE_TYPE {
+            return None;
+        }
+        let metadata: SummaryMetadata = serde_json::from_str(message.metadata.as_deref()?).ok()?;
+        if metadata.kind == SUMMARY_KIND {
+            Some(metadata)
+        } else {
+            None
+        }
+    }
+}
+
+/// 是否为 CJK 文字（含假名、谚文和全角字符），估算 token 和切分检索词项共用
+pub(crate) fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF | 0xFF00..=0xFFEF)
+}
+
+/// 粗略估算文本 token 数：CJK 字符按 1 个计，其余字符按 4 个字符 1 个计
+pub fn estimate_tokens(text: &str) -> usize {
+    let mut cjk: usize = 0;
+    let mut other: usize = 0;
+    for c in text.chars() {
+        if is_cjk(c) {
+            cjk += 1;
+        } else {
+            other += 1;
+        }
+    }
+    cjk + other.div_ceil(4)
+}
+
+pub fn estimate_message_tokens(message: &CoworkMessage) -> usize {
+    estimate_tokens(&message.content) + MESSAGE_OVERHEAD_TOKENS
+}
+
+fn is_context_message(message: &CoworkMessage) -> bool {
+    matches!(message.r#type.as_str(), "user" | "assistant" | "system")
+}
+
+/// 本轮请求的上下文规划结果
+#[derive(Debug, Clone, Default)]
+pub struct ContextPlan {
+    /// 最近一次已存储的摘要
+    pub summary: Option<CoworkMessage>,
+    /// 需要合并进新摘要的旧消息
+    pub to_summarize: Vec<CoworkMessage>,
+    /// 原样发送的消息
+    pub verbatim: Vec<CoworkMessage>,
+}
+
+impl ContextPlan {
+    pub fn needs_summary(&self) -> bool {
+        !self.to_summarize.is_empty()
+    }
+
+    /// 新摘要覆盖到的最大序号
+    pub fn covers_through(&self) -> Option<i32> {
+        self.to_summarize.iter().filter_map(|m| m.sequence).max()
+    }
+}
+
+/// 根据预算决定哪些消息原样发送、哪些需要压缩进摘要
+pub fn plan_context(history: &[CoworkMessage], budget: usize) -> ContextPlan {
+    let summary = history
+        .iter()
+        .filter(|m| SummaryMetadata::from_message(m).is_some())
+        .max_by_key(|m| m.sequence)
+        .cloned();
+    let covered = summary
+        .as_ref()
+        .and_then(SummaryMetadata::from_message)
+        .map(|m| m.covers_through)
+        .unwrap_or(0);
+
+    let candidates: Vec<CoworkMessage> = history
+        .iter()
+        .filter(|m| is_context_message(m))
+        .filter(|m| !matches!(m.sequence, Some(seq) if seq <= covered))
+        .cloned()
+        .collect();
+
+    let summary_tokens = summary.as_ref().map(estimate_message_tokens).unwrap_or(0);
+    let total: usize = summary_tokens + candidates.iter().map(estimate_message_tokens).sum::<usize>();
+    if total <= budget || candidates.len() <= MIN_RECENT_MESSAGES {
+        return ContextPlan {
+            summary,
+            to_summarize: Vec::new(),
+            verbatim: candidates,
+        };
+    }
+
+    let recent_budget = (budget as f64 * RECENT_BUDGET_RATIO) as usize;
+    let mut recent_tokens = 0;
+    let mut split = candidates.len();
+    while split > 0 {
+        let tokens = estimate_message_tokens(&candidates[split - 1]);
+        let kept = candidates.len() - split;
+        if kept >= MIN_RECENT_MESSAGES && recent_tokens + tokens > recent_budget {
+            break;
+        }
+        recent_tokens += tokens;
+        split -= 1;
+    }
+
+    let mut to_summarize = candidates;
+    let verbatim = to_summarize.split_off(split);
+    ContextPlan {
+        summary,
+        to_summarize,
+        verbatim,
+    }
+}
+
+/// 生成请求模型压缩历史时使用的提示词
+pub fn build_summary_prompt(previous_summary: Option<&str>, messages: &[CoworkMessage]) -> String {
+    let mut prompt = vec![
+        "Summarize the conversation below so it can replace the original messages as context for future turns.".to_string(),
+        "Keep facts, decisions, file paths, user preferences and open tasks. Be concise and write in the language of the conversation.".to_string(),
+        "Reply with the summary only.".to_string(),
+    ];
+    if let Some(previous) = previous_summary {
+        prompt.push(String::new());
+        prompt.push("<previous_summary>".to_string());
+        prompt.push(previous.to_string());
+        prompt.push("</previous_summary>".to_string());
+    }
+    prompt.push(String::new());
+    prompt.push("<conversation>".to_string());
+    for message in messages {
+        prompt.push(format!("[{}] {}", message.r#type, message.content));
+    }
+    prompt.push("</conversation>".to_string());
+    prompt.join("\n")
+}
+
+/// 把摘要转换为放在历史最前面的系统消息
+pub fn summary_chat_message(summary: &str) -> ChatMessage {
+    ChatMessage {
+        role: "system".to_string(),
+        content: format!("Summary of the earlier conversation:\n{}", summary),
+        attachments: Vec::new(),
+        images: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(sequence: i32, msg_type: &str, content: &str) -> CoworkMessage {
+        CoworkMessage {
+            id: format!("msg_{}", sequence),
+            session_id: "session_1".to_string(),
+            r#type: msg_type.to_string(),
+            content: content.to_string(),
+            timestamp: 0,
+            metadata: None,
+            sequence: Some(sequence),
+        }
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens("你好"), 2);
+    }
+
+    #[test]
+    fn test_plan_context_within_budget() {
+        let history = vec![message(1, "user", "hi"), message(2, "assistant", "hello")];
+        let plan = plan_context(&history, 1000);
+        assert!(!plan.needs_summary());
+        assert_eq!(plan.verbatim.len(), 2);
+    }
+
+    #[test]
+    fn test_plan_context_compacts_old_messages() {
+        let long = "x".repeat(400);
+        let history: Vec<CoworkMessage> = (1..=10)
+            .map(|i| message(i, if i % 2 == 1 { "user" } else { "assistant" }, &long))
+            .collect();
+        let plan = plan_context(&history, 500);
+
+        assert!(plan.needs_summary());
+        assert_eq!(plan.verbatim.len(), MIN_RECENT_MESSAGES);
+        assert_eq!(plan.covers_through(), Some(6));
+        assert_eq!(plan.verbatim.first().unwrap().sequence, Some(7));
+    }
+
+    #[test]
+    fn test_plan_context_reuses_summary() {
+        let mut summary = message(5, SUMMARY_MESSAGE_TYPE, "earlier stuff");
+        summary.metadata = Some(serde_json::to_string(&SummaryMetadata::new(3, "earlier stuff")).unwrap());
+        let history = vec![
+            message(1, "user", "a"),
+            message(2, "assistant", "b"),
+            message(3, "user", "c"),
+            message(4, "assistant", "d"),
+            summary,
+            message(6, "user", "e"),
+        ];
+        let plan = plan_context(&history, 1000);
+
+        assert!(!plan.needs_summary());
+        assert_eq!(plan.summary.unwrap().sequence, Some(5));
+        let sequences: Vec<_> = plan.verbatim.iter().map(|m| m.sequence.unwrap()).collect();
+        assert_eq!(sequences, vec![4, 6]);
+
+        // 摘要追加在末尾，按 covers_through 而不是位置决定替代的消息
+        let mut history = history;
+        let mut summary = history.remove(4);
+        summary.sequence = Some(7);
+        history.push(summary);
+        let plan = plan_context(&history, 1000);
+        assert_eq!(plan.summary.unwrap().sequence, Some(7));
+        let sequences: Vec<_> = plan.verbatim.iter().map(|m| m.sequence.unwrap()).collect();
+        assert_eq!(sequences, vec![4, 6]);
+    }
+}
@@ -0,0 +1,630 @@
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, XmlVersion};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+/// 注入提示词时单个文档默认最多保留的字符数
+pub const DEFAULT_DOCUMENT_MAX_CHARS: usize = 20000;
+/// 单个表格最多输出的行数
+const MAX_TABLE_ROWS: usize = 200;
+/// 单个表格最多输出的列数
+const MAX_TABLE_COLUMNS: usize = 50;
+/// 压缩包中单个 XML 文件解压后的大小上限，防止压缩炸弹
+const MAX_ENTRY_BYTES: u64 = 64 * 1024 * 1024;
+
+/// 可以提取文字的附件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentKind {
+    Docx,
+    Xlsx,
+    Pptx,
+    Pdf,
+    Text,
+}
+
+/// 根据 MIME 类型和文件扩展名判断文档类型，不支持的返回 None
+pub fn document_kind(mime_type: &str, name: &str) -> Option<DocumentKind> {
+    let ext = name.rsplit('.').next().unwrap_or_default().to_lowercase();
+    match (mime_type, ext.as_str()) {
+        ("application/vnd.openxmlformats-officedocument.wordprocessingml.document", _) | (_, "docx") => {
+            Some(DocumentKind::Docx)
+        }
+        ("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", _) | (_, "xlsx") => {
+            Some(DocumentKind::Xlsx)
+        }
+        ("application/vnd.openxmlformats-officedocument.presentationml.presentation", _) | (_, "pptx") => {
+            Some(DocumentKind::Pptx)
+        }
+        ("application/pdf", _) | (_, "pdf") => Some(DocumentKind::Pdf),
+        (m, _) if m.starts_with("text/") || m == "application/json" => Some(DocumentKind::Text),
+        _ => None,
+    }
+}
+
+/// 把文档转换为纯文本或 Markdown，表格输出为 Markdown 表格，并带有页、工作表、幻灯片标记
+pub fn extract_text(kind: DocumentKind, bytes: &[u8]) -> anyhow::Result<String> {
+    let text = match kind {
+        DocumentKind::Docx => extract_docx(bytes)?,
+        DocumentKind::Xlsx => extract_xlsx(bytes)?,
+        DocumentKind::Pptx => extract_pptx(bytes)?,
+        DocumentKind::Pdf => extract_pdf(bytes)?,
+        DocumentKind::Text => String::from_utf8_lossy(bytes).to_string(),
+    };
+    Ok(text.trim().to_string())
+}
+
+/// 超过 `max_chars` 时截断并注明省略的字符数
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!(
+            "{}\n\n[... truncated, {} more characters]",
+            &text[..end],
+            text[end..].chars().count()
+        ),
+        None => text.to_string(),
+    }
+}
+
+/// 把提取出的文档内容包装为追加在消息末尾的段落
+pub fn attachment_section(name: &str, text: &str) -> String {
+    format!("<attachment name=\"{}\">\n{}\n</attachment>", name.replace('"', "'"), text)
+}
+
+// ---------------------------------------------------------------------------
+// XML
+// ---------------------------------------------------------------------------
+
+enum XmlEvent<'a> {
+    Start { name: &'a str, attrs: &'a BytesStart<'a>, empty: bool },
+    End { name: &'a str },
+    Text(&'a str),
+}
+
+/// 逐个处理 XML 事件。标签名去掉命名空间前缀，实体和字符引用、CDATA 都作为文本交给 `visit`
+fn xml_events(xml: &str, mut visit: impl FnMut(XmlEvent<'_>)) -> anyhow::Result<()> {
+    let mut reader = Reader::from_str(xml);
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| anyhow::anyhow!("Invalid XML at {}: {}", reader.error_position(), e))?;
+        match event {
+            Event::Start(tag) => visit(XmlEvent::Start {
+                name: tag.local_name().into_inner(),
+                attrs: &tag,
+                empty: false,
+            }),
+            Event::Empty(tag) => visit(XmlEvent::Start {
+                name: tag.local_name().into_inner(),
+                attrs: &tag,
+                empty: true,
+            }),
+            Event::End(tag) => visit(XmlEvent::End {
+                name: tag.local_name().into_inner(),
+            }),
+            Event::Text(text) => visit(XmlEvent::Text(&text.xml10_content())),
+            Event::CData(data) => visit(XmlEvent::Text(&data.into_inner())),
+            Event::GeneralRef(reference) => {
+                let resolved = match reference.resolve_char_ref() {
+                    Ok(Some(c)) => Some(c.to_string()),
+                    _ => resolve_predefined_entity(&reference).map(str::to_string),
+                };
+                if let Some(text) = resolved {
+                    visit(XmlEvent::Text(&text));
+                }
+            }
+            Event::Eof => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+/// 读取属性值，`key` 可以带或不带命名空间前缀
+fn xml_attr(attrs: &BytesStart, key: &str) -> Option<String> {
+    attrs
+        .attributes()
+        .with_checks(false)
+        .flatten()
+        .find(|attr| attr.key.into_inner() == key || attr.key.local_name().into_inner() == key)
+        .and_then(|attr| attr.normalized_value(XmlVersion::Implicit1_0).ok().map(|v| v.into_owned()))
+}
+
+// ---------------------------------------------------------------------------
+// Markdown
+// ---------------------------------------------------------------------------
+
+fn escape_cell(cell: &str) -> String {
+    cell.trim()
+        .replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+/// 把行列数据渲染为 Markdown 表格，第一行作为表头
+fn markdown_table(rows: &[Vec<String>]) -> String {
+    let rows: Vec<&Vec<String>> = rows.iter().filter(|r| r.iter().any(|c| !c.trim().is_empty())).collect();
+    if rows.is_empty() {
+        return String::new();
+    }
+    let columns = rows
+        .iter()
+        .map(|r| r.len())
+        .max()
+        .unwrap_or(0)
+        .clamp(1, MAX_TABLE_COLUMNS);
+    let render_row = |row: &Vec<String>| {
+        let cells: Vec<String> = (0..columns)
+            .map(|i| row.get(i).map(|c| escape_cell(c)).unwrap_or_default())
+            .collect();
+        format!("| {} |", cells.join(" | "))
+    };
+
+    let mut lines = vec![render_row(rows[0]), format!("|{}", " --- |".repeat(columns))];
+    for row in rows.iter().skip(1).take(MAX_TABLE_ROWS) {
+        lines.push(render_row(row));
+    }
+    if rows.len() > MAX_TABLE_ROWS + 1 {
+        lines.push(format!("\n[... {} more rows]", rows.len() - MAX_TABLE_ROWS - 1));
+    }
+    lines.join("\n")
+}
+
+// ---------------------------------------------------------------------------
+// Office Open XML
+// ---------------------------------------------------------------------------
+
+fn open_zip(bytes: &[u8]) -> anyhow::Result<zip::ZipArchive<Cursor<&[u8]>>> {
+    zip::ZipArchive::new(Cursor::new(bytes)).map_err(|e| anyhow::anyhow!("Invalid Office document: {}", e))
+}
+
+fn read_zip_entry(archive: &mut zip::ZipArchive<Cursor<&[u8]>>, name: &str) -> Option<String> {
+    let entry = archive.by_name(name).ok()?;
+    let mut content = Vec::new();
+    entry.take(MAX_ENTRY_BYTES).read_to_end(&mut content).ok()?;
+    Some(String::from_utf8_lossy(&content).to_string())
+}
+
+/// 解析 .rels 文件，返回关系 id 到压缩包内路径的映射
+fn read_relationships(
+    archive: &mut zip::ZipArchive<Cursor<&[u8]>>,
+    rels_path: &str,
+    base_dir: &str,
+) -> anyhow::Result<HashMap<String, String>> {
+    let mut relationships = HashMap::new();
+    let xml = match read_zip_entry(archive, rels_path) {
+        Some(xml) => xml,
+        None => return Ok(relationships),
+    };
+    xml_events(&xml, |event| {
+        if let XmlEvent::Start { name: "Relationship", attrs, .. } = event {
+            if let (Some(id), Some(target)) = (xml_attr(attrs, "Id"), xml_attr(attrs, "Target")) {
+                let path = match target.strip_prefix('/') {
+                    Some(absolute) => absolute.to_string(),
+                    None => format!("{}/{}", base_dir, target),
+                };
+                relationships.insert(id, path);
+            }
+        }
+    })?;
+    Ok(relationships)
+}
+
+fn heading_level(style: &str) -> Option<usize> {
+    let style = style.to_lowercase();
+    if style == "title" {
+        return Some(1);
+    }
+    style
+        .strip_prefix("heading")
+        .and_then(|level| level.trim().parse::<usize>().ok())
+        .filter(|level| (1..=6).contains(level))
+}
+
+#[derive(Default)]
+struct TableBuilder {
+    rows: Vec<Vec<String>>,
+    row: Vec<String>,
+    cell: Vec<String>,
+}
+
+fn extract_docx(bytes: &[u8]) -> anyhow::Result<String> {
+    let mut archive = open_zip(bytes)?;
+    let xml = read_zip_entry(&mut archive, "word/document.xml")
+        .ok_or_else(|| anyhow::anyhow!("word/document.xml not found"))?;
+
+    let mut blocks: Vec<String> = Vec::new();
+    let mut tables: Vec<TableBuilder> = Vec::new();
+    let mut paragraph = String::new();
+    let mut heading = None;
+    let mut in_text = false;
+
+    xml_events(&xml, |event| match event {
+        XmlEvent::Start { name: "p", empty: false, .. } => {
+            paragraph.clear();
+            heading = None;
+        }
+        XmlEvent::Start { name: "pStyle", attrs, .. } => {
+            heading = xml_attr(attrs, "val").and_then(|v| heading_level(&v));
+        }
+        XmlEvent::Start { name: "t", empty: false, .. } => in_text = true,
+        XmlEvent::End { name: "t" } => in_text = false,
+        XmlEvent::Text(text) if in_text => paragraph.push_str(text),
+        XmlEvent::Start { name: "tab", .. } => paragraph.push('\t'),
+        XmlEvent::Start { name: "br", .. } | XmlEvent::Start { name: "cr", .. } => paragraph.push('\n'),
+        XmlEvent::End { name: "p" } => {
+            let text = paragraph.trim().to_string();
+            match tables.last_mut() {
+                Some(table) => table.cell.push(text),
+                None if !text.is_empty() => match heading {
+                    Some(level) => blocks.push(format!("{} {}", "#".repeat(level), text)),
+                    None => blocks.push(text),
+                },
+                None => {}
+            }
+            paragraph.clear();
+        }
+        XmlEvent::Start { name: "tbl", empty: false, .. } => tables.push(TableBuilder::default()),
+        XmlEvent::End { name: "tc" } => {
+            if let Some(table) = tables.last_mut() {
+                let cell = table.cell.iter().filter(|p| !p.is_empty()).cloned().collect::<Vec<_>>();
+                table.row.push(cell.join("\n"));
+                table.cell.clear();
+            }
+        }
+        XmlEvent::End { name: "tr" } => {
+            if let Some(table) = tables.last_mut() {
+                let row = std::mem::take(&mut table.row);
+                table.rows.push(row);
+            }
+        }
+        XmlEvent::End { name: "tbl" } => {
+            if let Some(table) = tables.pop() {
+                match tables.last_mut() {
+                    // 嵌套表格按行拼成文字放进外层单元格
+                    Some(outer) => outer.cell.push(
+                        table
+                            .rows
+                            .iter()
+                            .map(|r| r.join(" / "))
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                    ),
+                    None => blocks.push(markdown_table(&table.rows)),
+                }
+            }
+        }
+        _ => {}
+    })?;
+    Ok(blocks
+        .into_iter()
+        .filter(|b| !b.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n"))
+}
+
+/// 把 "AB12" 这样的单元格引用转换为从 0 开始的列号
+fn column_index(reference: &str) -> Option<usize> {
+    let letters: String = reference.chars().take_while(|c| c.is_ascii_alphabetic()).collect();
+    if letters.is_empty() {
+        return None;
+    }
+    letters
+        .to_ascii_uppercase()
+        .bytes()
+        .try_fold(0usize, |acc, b| acc.checked_mul(26)?.checked_add((b - b'A' + 1) as usize))
+        .map(|index| index - 1)
+}
+
+fn read_shared_strings(archive: &mut zip::ZipArchive<Cursor<&[u8]>>) -> anyhow::Result<Vec<String>> {
+    let mut strings = Vec::new();
+    let xml = match read_zip_entry(archive, "xl/sharedStrings.xml") {
+        Some(xml) => xml,
+        None => return Ok(strings),
+    };
+    let mut current = String::new();
+    let mut in_text = false;
+    let mut in_phonetic = false;
+    xml_events(&xml, |event| match event {
+        XmlEvent::Start { name: "si", .. } => current.clear(),
+        XmlEvent::End { name: "si" } => strings.push(std::mem::take(&mut current)),
+        XmlEvent::Start { name: "rPh", empty: false, .. } => in_phonetic = true,
+        XmlEvent::End { name: "rPh" } => in_phonetic = false,
+        XmlEvent::Start { name: "t", empty: false, .. } => in_text = true,
+        XmlEvent::End { name: "t" } => in_text = false,
+        XmlEvent::Text(text) if in_text && !in_phonetic => current.push_str(text),
+        _ => {}
+    })?;
+    Ok(strings)
+}
+
+fn extract_sheet(xml: &str, shared_strings: &[String]) -> anyhow::Result<Vec<Vec<String>>> {
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut cell_type = String::new();
+    let mut column = 0;
+    let mut value = String::new();
+    let mut in_value = false;
+
+    xml_events(xml, |event| match event {
+        XmlEvent::Start { name: "row", empty, .. } => {
+            row.clear();
+            if empty {
+                rows.push(Vec::new());
+            }
+        }
+        XmlEvent::End { name: "row" } => rows.push(std::mem::take(&mut row)),
+        XmlEvent::Start { name: "c", attrs, empty } => {
+            cell_type = xml_attr(attrs, "t").unwrap_or_default();
+            // 列号超出范围时记为 usize::MAX，这个单元格会被丢弃
+            column = match xml_attr(attrs, "r") {
+                Some(reference) => column_index(&reference).unwrap_or(usize::MAX),
+                None => row.len(),
+            };
+            value.clear();
+            if empty && row.len() <= column && column < MAX_TABLE_COLUMNS {
+                row.resize(column + 1, String::new());
+            }
+        }
+        XmlEvent::Start { name: "v", empty: false, .. } | XmlEvent::Start { name: "t", empty: false, .. } => {
+            in_value = true
+        }
+        XmlEvent::End { name: "v" } | XmlEvent::End { name: "t" } => in_value = false,
+        XmlEvent::Text(text) if in_value => value.push_str(text),
+        XmlEvent::End { name: "c" } => {
+            let text = match cell_type.as_str() {
+                "s" => value
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| shared_strings.get(i).cloned())
+                    .unwrap_or_default(),
+                "b" => if value.trim() == "1" { "TRUE" } else { "FALSE" }.to_string(),
+                _ => value.clone(),
+            };
+            if column < MAX_TABLE_COLUMNS {
+                if row.len() <= column {
+                    row.resize(column + 1, String::new());
+                }
+                row[column] = text;
+            }
+        }
+        _ => {}
+    })?;
+    // 去掉表格末尾的空行
+    while rows.last().map(|r| r.iter().all(|c| c.trim().is_empty())).unwrap_or(false) {
+        rows.pop();
+    }
+    Ok(rows)
+}
+
+fn extract_xlsx(bytes: &[u8]) -> anyhow::Result<String> {
+    let mut archive = open_zip(bytes)?;
+    let workbook = read_zip_entry(&mut archive, "xl/workbook.xml")
+        .ok_or_else(|| anyhow::anyhow!("xl/workbook.xml not found"))?;
+    let relationships = read_relationships(&mut archive, "xl/_rels/workbook.xml.rels", "xl")?;
+    let shared_strings = read_shared_strings(&mut archive)?;
+
+    let mut sheets = Vec::new();
+    xml_events(&workbook, |event| {
+        if let XmlEvent::Start { name: "sheet", attrs, .. } = event {
+            let name = xml_attr(attrs, "name").unwrap_or_default();
+            let path = xml_attr(attrs, "r:id")
+                .or_else(|| xml_attr(attrs, "id"))
+                .and_then(|id| relationships.get(&id).cloned())
+                .unwrap_or_else(|| format!("xl/worksheets/sheet{}.xml", sheets.len() + 1));
+            sheets.push((name, path));
+        }
+    })?;
+
+    let mut sections = Vec::new();
+    for (name, path) in sheets {
+        let xml = match read_zip_entry(&mut archive, &path) {
+            Some(xml) => xml,
+            None => continue,
+        };
+        let table = markdown_table(&extract_sheet(&xml, &shared_strings)?);
+        sections.push(format!("## Sheet: {}\n\n{}", name, table).trim().to_string());
+    }
+    Ok(sections.join("\n\n"))
+}
+
+fn slide_text(xml: &str) -> anyhow::Result<String> {
+    let mut paragraphs = Vec::new();
+    let mut paragraph = String::new();
+    let mut in_text = false;
+    xml_events(xml, |event| match event {
+        XmlEvent::Start { name: "p", empty: false, .. } => paragraph.clear(),
+        XmlEvent::Start { name: "t", empty: false, .. } => in_text = true,
+        XmlEvent::End { name: "t" } => in_text = false,
+        XmlEvent::Text(text) if in_text => paragraph.push_str(text),
+        XmlEvent::Start { name: "br", .. } => paragraph.push('\n'),
+        XmlEvent::End { name: "p" } => {
+            let text = paragraph.trim();
+            if !text.is_empty() {
+                paragraphs.push(text.to_string());
+            }
+            paragraph.clear();
+        }
+        _ => {}
+    })?;
+    Ok(paragraphs.join("\n"))
+}
+
+fn extract_pptx(bytes: &[u8]) -> anyhow::Result<String> {
+    let mut archive = open_zip(bytes)?;
+    let relationships = read_relationships(&mut archive, "ppt/_rels/presentation.xml.rels", "ppt")?;
+    let mut slides: Vec<String> = Vec::new();
+    if let Some(xml) = read_zip_entry(&mut archive, "ppt/presentation.xml") {
+        xml_events(&xml, |event| {
+            if let XmlEvent::Start { name: "sldId", attrs, .. } = event {
+                if let Some(path) = xml_attr(attrs, "r:id")
+                    .or_else(|| xml_attr(attrs, "id"))
+                    .and_then(|id| relationships.get(&id).cloned())
+                {
+                    slides.push(path);
+                }
+            }
+        })?;
+    }
+
+    // 没有 presentation.xml 时按文件名中的编号排序
+    if slides.is_empty() {
+        let mut numbered: Vec<(usize, String)> = archive
+            .file_names()
+            .filter_map(|name| {
+                let number = name.strip_prefix("ppt/slides/slide")?.strip_suffix(".xml")?;
+                Some((number.parse().ok()?, name.to_string()))
+            })
+            .collect();
+        numbered.sort();
+        slides = numbered.into_iter().map(|(_, name)| name).collect();
+    }
+
+    let mut sections = Vec::new();
+    for (index, path) in slides.iter().enumerate() {
+        let text = match read_zip_entry(&mut archive, path) {
+            Some(xml) => slide_text(&xml)?,
+            None => String::new(),
+        };
+        sections.push(format!("## Slide {}\n\n{}", index + 1, text).trim().to_string());
+    }
+    Ok(sections.join("\n\n"))
+}
+
+// ---------------------------------------------------------------------------
+// PDF
+// ---------------------------------------------------------------------------
+
+fn extract_pdf(bytes: &[u8]) -> anyhow::Result<String> {
+    if !bytes.starts_with(b"%PDF") {
+        return Err(anyhow::anyhow!("Not a PDF file"));
+    }
+    // pdf-extract 遇到不规范的文件时可能 panic，按解析失败处理
+    let pages = std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem_by_pages(bytes))
+        .map_err(|_| anyhow::anyhow!("Failed to parse PDF"))?
+        .map_err(|e| anyhow::anyhow!("Failed to extract text from PDF: {}", e))?;
+    if pages.is_empty() {
+        return Err(anyhow::anyhow!("No pages found in PDF"));
+    }
+
+    let sections: Vec<String> = pages
+        .iter()
+        .enumerate()
+        .map(|(index, page)| {
+            let text = page
+                .lines()
+                .map(str::trim_end)
+                .filter(|l| !l.trim().is_empty())
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!("## Page {}\n\n{}", index + 1, text).trim().to_string()
+        })
+        .collect();
+    Ok(sections.join("\n\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn zip_file(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, content) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_extract_docx() {
+        let document = r#"<?xml version="1.0" encoding="UTF-8"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>
+<w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>Quarterly Report</w:t></w:r></w:p>
+<w:p><w:r><w:t xml:space="preserve">Revenue &amp; costs </w:t></w:r><w:r><w:t>grew.</w:t></w:r></w:p>
+<w:tbl><w:tr><w:tc><w:p><w:r><w:t>Item</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>Amount</w:t></w:r></w:p></w:tc></w:tr>
+<w:tr><w:tc><w:p><w:r><w:t>A|B</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>10</w:t></w:r></w:p></w:tc></w:tr></w:tbl>
+<w:p><w:r><w:delText>removed</w:delText><w:t>结束</w:t></w:r></w:p>
+</w:body></w:document>"#;
+        let bytes = zip_file(&[("word/document.xml", document)]);
+        let text = extract_text(DocumentKind::Docx, &bytes).unwrap();
+        assert_eq!(
+            text,
+            "# Quarterly Report\n\nRevenue & costs grew.\n\n| Item | Amount |\n| --- | --- |\n| A\\|B | 10 |\n\n结束"
+        );
+    }
+
+    #[test]
+    fn test_extract_xlsx_and_pptx() {
+        let xlsx = zip_file(&[
+            (
+                "xl/workbook.xml",
+                r#"<workbook xmlns:r="r"><sheets><sheet name="Sales" sheetId="1" r:id="rId1"/></sheets></workbook>"#,
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                r#"<Relationships><Relationship Id="rId1" Target="worksheets/sheet1.xml"/></Relationships>"#,
+            ),
+            ("xl/sharedStrings.xml", r#"<sst><si><t>Region</t></si><si><r><t>To</t></r><r><t>tal</t></r></si><si><t>North</t></si></sst>"#),
+            (
+                "xl/worksheets/sheet1.xml",
+                r#"<worksheet><sheetData><row r="1"><c r="A1" t="s"><v>0</v></c><c r="C1" t="s"><v>1</v></c></row><row r="2"><c r="A2" t="s"><v>2</v></c><c r="C2"><v>42.5</v></c></row></sheetData></worksheet>"#,
+            ),
+        ]);
+        let text = extract_text(DocumentKind::Xlsx, &xlsx).unwrap();
+        assert_eq!(text, "## Sheet: Sales\n\n| Region |  | Total |\n| --- | --- | --- |\n| North |  | 42.5 |");
+
+        let pptx = zip_file(&[
+            ("ppt/slides/slide2.xml", r#"<p:sld><a:p><a:r><a:t>Second</a:t></a:r></a:p></p:sld>"#),
+            ("ppt/slides/slide1.xml", r#"<p:sld><a:p><a:r><a:t>Hello</a:t></a:r><a:r><a:t> world</a:t></a:r></a:p><a:p><a:r><a:t>Bullet</a:t></a:r></a:p></p:sld>"#),
+        ]);
+        let text = extract_text(DocumentKind::Pptx, &pptx).unwrap();
+        assert_eq!(text, "## Slide 1\n\nHello world\nBullet\n\n## Slide 2\n\nSecond");
+    }
+
+    #[test]
+    fn test_malformed_xml() {
+        // 未闭合的 CDATA、标签不匹配都返回错误而不是 panic
+        for document in [
+            "<w:document><w:body><w:p><w:r><w:t><![CDATA[你好",
+            "<w:document><w:body><w:p><w:t>文字</w:p></w:body></w:document>",
+        ] {
+            let bytes = zip_file(&[("word/document.xml", document)]);
+            assert!(extract_text(DocumentKind::Docx, &bytes).is_err());
+        }
+        // 超出范围的列号不会溢出，也不会撑大表格
+        let sheet = zip_file(&[
+            ("xl/workbook.xml", r#"<workbook><sheets><sheet name="A"/></sheets></workbook>"#),
+            (
+                "xl/worksheets/sheet1.xml",
+                r#"<worksheet><sheetData><row><c r="A1"><v>1</v></c><c r="ZZZZ1"/><c r="ZZZZ2"><v>2</v></c><c r="ZZZZZZZZZZZZZZZ3"/></row></sheetData></worksheet>"#,
+            ),
+        ]);
+        assert_eq!(extract_text(DocumentKind::Xlsx, &sheet).unwrap(), "## Sheet: A\n\n| 1 |\n| --- |");
+        assert!(extract_text(DocumentKind::Pptx, b"PK not a zip").is_err());
+    }
+
+    #[test]
+    fn test_extract_pdf() {
+        let text = extract_text(DocumentKind::Pdf, include_bytes!("../testdata/example.pdf")).unwrap();
+        assert_eq!(text, "## Page 1\n\nHello World!");
+        let text = extract_text(DocumentKind::Pdf, include_bytes!("../testdata/unicode.pdf")).unwrap();
+        assert_eq!(text, "## Page 1\n\n😀 🔧 🔨");
+
+        assert!(extract_text(DocumentKind::Pdf, b"not a pdf").is_err());
+        assert!(extract_text(DocumentKind::Pdf, b"%PDF-1.4\n1 0 obj\n<< /Length 99999999999 >>\nstream\n").is_err());
+    }
+
+    #[test]
+    fn test_document_kind_and_truncate() {
+        assert_eq!(document_kind("application/octet-stream", "a.DOCX"), Some(DocumentKind::Docx));
+        assert_eq!(document_kind("application/pdf", "file"), Some(DocumentKind::Pdf));
+        assert_eq!(document_kind("text/csv", "a.csv"), Some(DocumentKind::Text));
+        assert_eq!(document_kind("image/png", "a.png"), None);
+        assert_eq!(truncate("abcdef", 3), "abc\n\n[... truncated, 3 more characters]");
+        assert_eq!(truncate("abc", 3), "abc");
+    }
+}
@@ -0,0 +1,461 @@
+use crate::cowork::{CoworkMessage, CoworkSession};
+use crate::cowork_attachments::{self, AttachmentStore};
+use crate::cowork_images;
+use crate::cowork_tools;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// JSON 导出包的格式标识，导入时用于校验
+pub const BUNDLE_FORMAT: &str = "gloai.cowork.session";
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// 无损导出包：完整保留会话设置、消息顺序和 metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionBundle {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: i64,
+    pub session: CoworkSession,
+    pub messages: Vec<CoworkMessage>,
+    /// 消息引用的附件内容（base64），以 SHA-256 为键
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attachments: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> anyhow::Result<Self> {
+        match format.trim().to_lowercase().as_str() {
+            "md" | "markdown" => Ok(ExportFormat::Markdown),
+            "html" | "htm" => Ok(ExportFormat::Html),
+            "json" => Ok(ExportFormat::Json),
+            other => Err(anyhow::anyhow!("Unsupported export format: {}", other)),
+        }
+    }
+}
+
+impl SessionBundle {
+    pub fn new(session: CoworkSession, mut messages: Vec<CoworkMessage>) -> Self {
+        messages.sort_by_key(|m| (m.sequence.unwrap_or(i32::MAX), m.timestamp));
+        SessionBundle {
+            format: BUNDLE_FORMAT.to_string(),
+            version: BUNDLE_VERSION,
+            exported_at: Local::now().timestamp_millis(),
+            session,
+            messages,
+            attachments: BTreeMap::new(),
+        }
+    }
+
+    /// 把消息引用的附件及其缩略图写入导出包，存储中缺失的附件跳过
+    pub fn embed_attachments(&mut self, store: &AttachmentStore) -> anyhow::Result<()> {
+        for message in &self.messages {
+            for attachment in cowork_attachments::attachments_from_metadata(message.metadata.as_deref()) {
+                for sha256 in std::iter::once(attachment.sha256).chain(attachment.thumbnail_sha256) {
+                    if self.attachments.contains_key(&sha256) {
+                        continue;
+                    }
+                    if !store.exists(&sha256) {
+                        println!("[Cowork] Attachment {} missing from store, not exported", sha256);
+                        continue;
+                    }
+                    let bytes = std::fs::read(store.path_for(&sha256))?;
+                    self.attachments.insert(sha256, BASE64.encode(bytes));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 把导出包中的附件写回存储，内容与哈希不符时拒绝导入。返回写入的 (哈希, 类型, 大小)，
+    /// 类型取自消息中的附件信息，缩略图按文件头识别
+    pub fn restore_attachments(&self, store: &AttachmentStore) -> anyhow::Result<Vec<(String, String, u64)>> {
+        let mime_types: HashMap<String, String> = self
+            .messages
+            .iter()
+            .flat_map(|m| cowork_attachments::attachments_from_metadata(m.metadata.as_deref()))
+            .map(|a| (a.sha256, a.mime_type))
+            .collect();
+        let mut decoded = Vec::with_capacity(self.attachments.len());
+        for (sha256, data) in &self.attachments {
+            if !cowork_attachments::is_valid_sha256(sha256) {
+                return Err(anyhow::anyhow!("Invalid attachment hash in bundle: {}", sha256));
+            }
+            let bytes = BASE64
+                .decode(data)
+                .map_err(|e| anyhow::anyhow!("Invalid attachment {} in bundle: {}", sha256, e))?;
+            if bytes.len() > cowork_attachments::MAX_ATTACHMENT_BYTES {
+                return Err(anyhow::anyhow!("Attachment {} in bundle is too large", sha256));
+            }
+            if cowork_attachments::sha256_hex(&bytes) != *sha256 {
+                return Err(anyhow::anyhow!("Attachment {} in bundle does not match its hash", sha256));
+            }
+            decoded.push((sha256, bytes));
+        }
+        let mut restored = Vec::with_capacity(decoded.len());
+        for (sha256, bytes) in decoded {
+            store.put(&bytes)?;
+            let mime_type = mime_types
+                .get(sha256)
+                .cloned()
+                .or_else(|| cowork_images::sniff_mime_type(&bytes).map(str::to_string))
+                .unwrap_or_else(|| "application/octet-stream".to_string());
+            restored.push((sha256.clone(), mime_type, bytes.len() as u64));
+        }
+        Ok(restored)
+    }
+
+    /// 解析并校验 JSON 导出包
+    pub fn parse(data: &str) -> anyhow::Result<Self> {
+        let bundle: SessionBundle = serde_json::from_str(data)
+            .map_err(|e| anyhow::anyhow!("Invalid session bundle: {}", e))?;
+        if bundle.format != BUNDLE_FORMAT {
+            return Err(anyhow::anyhow!("Unknown bundle format: {}", bundle.format));
+        }
+        if bundle.version > BUNDLE_VERSION {
+            return Err(anyhow::anyhow!(
+                "Bundle version {} is newer than supported version {}",
+                bundle.version,
+                BUNDLE_VERSION
+            ));
+        }
+        Ok(bundle)
+    }
+
+    pub fn render(&self, format: ExportFormat) -> anyhow::Result<String> {
+        match format {
+            ExportFormat::Markdown => Ok(to_markdown(self)),
+            ExportFormat::Html => Ok(to_html(self)),
+            ExportFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+        }
+    }
+
+    /// 为导入生成全新的会话和消息 id，工具消息 metadata 中引用的消息 id 随之替换。
+    /// 原 sequence 保持不变（摘要的 covers_through 依赖它），缺少 sequence 的消息排在最后
+    pub fn remap_for_import(mut self) -> (CoworkSession, Vec<CoworkMessage>) {
+        let session_id = format!("session_{}", uuid::Uuid::new_v4());
+        self.session.id = session_id.clone();
+        self.session.status = "idle".to_string();
+        // 来源会话不在本机数据库中，分支关系无法保留
+        self.session.parent_session_id = None;
+        self.session.fork_sequence = None;
+        self.session.archived_at = None;
+
+        self.messages
+            .sort_by_key(|m| (m.sequence.unwrap_or(i32::MAX), m.timestamp));
+        let mut last_sequence = self.messages.iter().filter_map(|m| m.sequence).max().unwrap_or(0);
+        let new_ids: Vec<String> = self
+            .messages
+            .iter()
+            .map(|_| format!("msg_{}", uuid::Uuid::new_v4()))
+            .collect();
+        // 导入文件中的 id 可能重复，引用按首次出现的消息解析
+        let mut ids: HashMap<String, String> = HashMap::new();
+        for (message, new_id) in self.messages.iter().zip(&new_ids) {
+            ids.entry(message.id.clone()).or_insert_with(|| new_id.clone());
+        }
+        let messages = self
+            .messages
+            .into_iter()
+            .zip(new_ids)
+            .map(|(mut message, new_id)| {
+                message.id = new_id;
+                message.session_id = session_id.clone();
+                message.metadata = cowork_tools::remap_message_refs(message.metadata, &ids);
+                if message.sequence.is_none() {
+                    last_sequence += 1;
+                    message.sequence = Some(last_sequence);
+                }
+                message
+            })
+            .collect();
+        (self.session, messages)
+    }
+}
+
+fn format_time(timestamp: i64) -> String {
+    Local
+        .timestamp_millis_opt(timestamp)
+        .single()
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+fn role_label(msg_type: &str) -> &str {
+    match msg_type {
+        "user" => "User",
+        "assistant" => "Assistant",
+        "system" => "System",
+        "summary" => "Summary",
+        "tool_call" => "Tool call",
+        "tool_result" => "Tool result",
+        other => other,
+    }
+}
+
+fn non_empty(value: Option<&String>) -> Option<&str> {
+    value.map(|s| s.trim()).filter(|s| !s.is_empty())
+}
+
+/// 从 metadata 中读取附件名称，用于在文本导出中列出附件
+fn attachment_names(message: &CoworkMessage) -> Vec<String> {
+    message
+        .metadata
+        .as_deref()
+        .and_then(|m| serde_json::from_str::<serde_json::Value>(m).ok())
+        .and_then(|m| m.get("attachments").and_then(|a| a.as_array()).cloned())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|a| a.get("name").and_then(|n| n.as_str()).map(|n| n.to_string()))
+        .collect()
+}
+
+fn session_details(session: &CoworkSession) -> Vec<(&'static str, String)> {
+    let mut details = vec![("Created", format_time(session.created_at))];
+    if let Some(cwd) = non_empty(session.cwd.as_ref()) {
+        details.push(("Working directory", cwd.to_string()));
+    }
+    if let Some(mode) = non_empty(session.execution_mode.as_ref()) {
+        details.push(("Execution mode", mode.to_string()));
+    }
+    if let Some(skills) = non_empty(session.active_skill_ids.as_ref()) {
+        details.push(("Skills", skills.to_string()));
+    }
+    details
+}
+
+pub fn to_markdown(bundle: &SessionBundle) -> String {
+    let session = &bundle.session;
+    let mut out = vec![format!("# {}", session.title), String::new()];
+    for (label, value) in session_details(session) {
+        out.push(format!("- **{}:** {}", label, value));
+    }
+
+    if let Some(system_prompt) = non_empty(session.system_prompt.as_ref()) {
+        out.push(String::new());
+        out.push("## System prompt".to_string());
+        out.push(String::new());
+        for line in system_prompt.lines() {
+            out.push(format!("> {}", line).trim_end().to_string());
+        }
+    }
+
+    for message in &bundle.messages {
+        out.push(String::new());
+        out.push("---".to_string());
+        out.push(String::new());
+        out.push(format!(
+            "### {} · {}",
+            role_label(&message.r#type),
+            format_time(message.timestamp)
+        ));
+        out.push(String::new());
+        out.push(message.content.trim_end().to_string());
+
+        let attachments = attachment_names(message);
+        if !attachments.is_empty() {
+            out.push(String::new());
+            out.push("**Attachments:**".to_string());
+            for name in attachments {
+                out.push(format!("- {}", name));
+            }
+        }
+    }
+    out.push(String::new());
+    out.join("\n")
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const HTML_STYLE: &str = "body{font-family:-apple-system,BlinkMacSystemFont,\"Segoe UI\",\"PingFang SC\",\"Microsoft YaHei\",sans-serif;max-width:860px;margin:40px auto;padding:0 16px;color:#1f2328;line-height:1.6}\
+h1{font-size:24px}dl{display:grid;grid-template-columns:max-content 1fr;gap:4px 12px;color:#57606a}dt{font-weight:600}dd{margin:0}\
+.message{border:1px solid #d0d7de;border-radius:8px;padding:12px 16px;margin:16px 0}.message.user{background:#f6f8fa}\
+.message header{font-size:12px;color:#57606a;margin-bottom:8px}.message header strong{color:#1f2328;margin-right:8px}\
+.content{white-space:pre-wrap;word-wrap:break-word}.system-prompt{border-left:4px solid #d0d7de;padding-left:12px;color:#57606a;white-space:pre-wrap}\
+.attachments{font-size:13px;color:#57606a;margin-top:8px}";
+
+/// 生成不依赖任何外部资源的单文件 HTML
+pub fn to_html(bundle: &SessionBundle) -> String {
+    let session = &bundle.session;
+    let mut body = vec![format!("<h1>{}</h1>", escape_html(&session.title))];
+
+    body.push("<dl>".to_string());
+    for (label, value) in session_details(session) {
+        body.push(format!("<dt>{}</dt><dd>{}</dd>", label, escape_html(&value)));
+    }
+    body.push("</dl>".to_string());
+
+    if let Some(system_prompt) = non_empty(session.system_prompt.as_ref()) {
+        body.push("<h2>System prompt</h2>".to_string());
+        body.push(format!(
+            "<div class=\"system-prompt\">{}</div>",
+            escape_html(system_prompt)
+        ));
+    }
+
+    for message in &bundle.messages {
+        let mut section = format!(
+            "<section class=\"message {}\"><header><strong>{}</strong><time>{}</time></header><div class=\"content\">{}</div>",
+            escape_html(&message.r#type),
+            escape_html(role_label(&message.r#type)),
+            format_time(message.timestamp),
+            escape_html(message.content.trim_end())
+        );
+        let attachments = attachment_names(message);
+        if !attachments.is_empty() {
+            let names: Vec<String> = attachments.iter().map(|n| escape_html(n)).collect();
+            section.push_str(&format!(
+                "<div class=\"attachments\">Attachments: {}</div>",
+                names.join(", ")
+            ));
+        }
+        section.push_str("</section>");
+        body.push(section);
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+        escape_html(&session.title),
+        HTML_STYLE,
+        body.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn bundle() -> SessionBundle {
+        let session = CoworkSession {
+            id: "session_1".to_string(),
+            title: "Report <draft>".to_string(),
+            status: "running".to_string(),
+            pinned: false,
+            cwd: Some("/tmp/project".to_string()),
+            system_prompt: Some("Be brief.".to_string()),
+            execution_mode: Some("local".to_string()),
+            active_skill_ids: None,
+            created_at: 0,
+            updated_at: 0,
+            context_budget: None,
+            parent_session_id: None,
+            fork_sequence: None,
+            title_source: None,
+            folder_id: None,
+            archived_at: None,
+            tags: Vec::new(),
+        };
+        let message = |id: &str, seq: i32, msg_type: &str, content: &str| CoworkMessage {
+            id: id.to_string(),
+            session_id: "session_1".to_string(),
+            r#type: msg_type.to_string(),
+            content: content.to_string(),
+            timestamp: seq as i64,
+            metadata: None,
+            sequence: Some(seq),
+        };
+        let mut reply = message("msg_b", 5, "assistant", "Use <b>tags</b> & more");
+        reply.metadata = Some(r#"{"status":"done","attachments":[{"name":"report.xlsx"}]}"#.to_string());
+        SessionBundle::new(
+            session,
+            vec![reply, message("msg_a", 2, "user", "Write the report")],
+        )
+    }
+
+    #[test]
+    fn test_render_markdown_and_html() {
+        let bundle = bundle();
+        let markdown = bundle.render(ExportFormat::Markdown).unwrap();
+        assert!(markdown.starts_with("# Report <draft>"));
+        assert!(markdown.contains("> Be brief."));
+        assert!(markdown.find("Write the report").unwrap() < markdown.find("Use <b>tags</b>").unwrap());
+        assert!(markdown.contains("- report.xlsx"));
+
+        let html = bundle.render(ExportFormat::Html).unwrap();
+        assert!(html.contains("<title>Report &lt;draft&gt;</title>"));
+        assert!(html.contains("Use &lt;b&gt;tags&lt;/b&gt; &amp; more"));
+        assert!(!html.contains("<link") && !html.contains("<script"));
+    }
+
+    #[test]
+    fn test_json_round_trip_and_remap() {
+        let json = bundle().render(ExportFormat::Json).unwrap();
+        let parsed = SessionBundle::parse(&json).unwrap();
+        assert_eq!(parsed.messages.len(), 2);
+        assert!(parsed.messages[1].metadata.as_deref().unwrap().contains("report.xlsx"));
+
+        let mut parsed = parsed;
+        parsed.messages.push(CoworkMessage {
+            sequence: None,
+            ..parsed.messages[0].clone()
+        });
+        let (session, messages) = parsed.remap_for_import();
+        assert_ne!(session.id, "session_1");
+        assert_eq!(session.status, "idle");
+        assert_eq!(messages[0].content, "Write the report");
+        // 保留原有的序号间隔
+        let sequences: Vec<_> = messages.iter().map(|m| m.sequence).collect();
+        assert_eq!(sequences, vec![Some(2), Some(5), Some(6)]);
+        assert!(messages.iter().all(|m| m.session_id == session.id && m.id.starts_with("msg_")));
+        assert_ne!(messages[0].id, "msg_a");
+        assert_ne!(messages[0].id, messages[2].id);
+
+        assert!(SessionBundle::parse(r#"{"format":"other","version":1}"#).is_err());
+    }
+
+    #[test]
+    fn test_attachments_round_trip() {
+        let temp_dir = tempdir().unwrap();
+        let source = AttachmentStore::new(temp_dir.path().join("source"));
+        let sha256 = source.put(b"quarterly numbers").unwrap();
+        let mut bundle = bundle();
+        bundle.messages[0].metadata = Some(
+            serde_json::json!({
+                "attachments": [
+                    { "sha256": sha256, "name": "notes.txt", "mime_type": "text/plain", "size": 17 },
+                    { "sha256": "0".repeat(64), "name": "lost.txt", "mime_type": "text/plain", "size": 1 },
+                ]
+            })
+            .to_string(),
+        );
+        bundle.embed_attachments(&source).unwrap();
+        assert_eq!(bundle.attachments.keys().collect::<Vec<_>>(), vec![&sha256]);
+
+        let parsed = SessionBundle::parse(&bundle.render(ExportFormat::Json).unwrap()).unwrap();
+        let target = AttachmentStore::new(temp_dir.path().join("target"));
+        assert_eq!(
+            parsed.restore_attachments(&target).unwrap(),
+            vec![(sha256.clone(), "text/plain".to_string(), 17)]
+        );
+        assert_eq!(std::fs::read(target.path_for(&sha256)).unwrap(), b"quarterly numbers");
+
+        // 内容被篡改的附件不会写入存储
+        let mut tampered = parsed.clone();
+        tampered.attachments.insert(sha256.clone(), BASE64.encode(b"other numbers"));
+        let other = AttachmentStore::new(temp_dir.path().join("other"));
+        assert!(tampered.restore_attachments(&other).is_err());
+        assert!(!other.exists(&sha256));
+    }
+}
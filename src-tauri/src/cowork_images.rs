@@ -0,0 +1,177 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// 图片长边默认最大像素，超过时等比缩小
+pub const DEFAULT_IMAGE_MAX_DIMENSION: u32 = 2048;
+/// 缩略图长边像素
+pub const THUMBNAIL_DIMENSION: u32 = 256;
+const JPEG_QUALITY: u8 = 85;
+const THUMBNAIL_JPEG_QUALITY: u8 = 75;
+
+/// 前端粘贴或选择的图片，`data` 可以是 data URL 或纯 base64
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageInput {
+    pub data: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+}
+
+impl ImageInput {
+    /// 解码图片数据，返回 (data URL 中声明的 MIME 类型, 字节)
+    pub fn decode(&self) -> anyhow::Result<(Option<String>, Vec<u8>)> {
+        let data = self.data.trim();
+        let (mime_type, payload) = match data.strip_prefix("data:").and_then(|rest| rest.split_once(',')) {
+            Some((header, payload)) => {
+                let mime_type = header.trim_end_matches(";base64").trim();
+                (Some(mime_type.to_string()).filter(|m| !m.is_empty()), payload)
+            }
+            None => (None, data),
+        };
+        let bytes = BASE64
+            .decode(payload.trim())
+            .map_err(|e| anyhow::anyhow!("Invalid image data: {}", e))?;
+        Ok((self.mime_type.clone().or(mime_type), bytes))
+    }
+}
+
+/// 缩放、去除 EXIF 并重新编码后的图片及其缩略图
+#[derive(Debug, Clone)]
+pub struct ProcessedImage {
+    pub data: Vec<u8>,
+    pub mime_type: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub thumbnail: Vec<u8>,
+    pub thumbnail_mime_type: &'static str,
+}
+
+/// 需要预处理的图片类型；GIF（可能是动图）和 SVG 原样保存
+pub fn is_processable(mime_type: &str) -> bool {
+    matches!(
+        mime_type,
+        "image/png" | "image/jpeg" | "image/jpg" | "image/webp" | "image/bmp"
+    )
+}
+
+fn encode(image: &DynamicImage, jpeg_quality: Option<u8>) -> anyhow::Result<(Vec<u8>, &'static str)> {
+    let mut buffer = Vec::new();
+    match jpeg_quality {
+        Some(quality) if !image.color().has_alpha() => {
+            let encoder = JpegEncoder::new_with_quality(&mut buffer, quality);
+            image.to_rgb8().write_with_encoder(encoder)?;
+            Ok((buffer, "image/jpeg"))
+        }
+        _ => {
+            image.write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png)?;
+            Ok((buffer, "image/png"))
+        }
+    }
+}
+
+/// 按 EXIF 方向摆正图片，长边超过 `max_dimension` 时等比缩小，再重新编码（同时去掉 EXIF 等元数据）。
+/// JPEG 仍输出 JPEG，其余格式输出 PNG；缩略图在不透明时使用 JPEG
+pub fn process_image(bytes: &[u8], max_dimension: u32) -> anyhow::Result<ProcessedImage> {
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let format = reader
+        .format()
+        .ok_or_else(|| anyhow::anyhow!("Unrecognized image format"))?;
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    let max_dimension = max_dimension.max(THUMBNAIL_DIMENSION);
+    if image.width() > max_dimension || image.height() > max_dimension {
+        image = image.resize(max_dimension, max_dimension, image::imageops::FilterType::Lanczos3);
+    }
+    let jpeg_quality = if format == ImageFormat::Jpeg {
+        Some(JPEG_QUALITY)
+    } else {
+        None
+    };
+    let (data, mime_type) = encode(&image, jpeg_quality)?;
+    let (thumbnail, thumbnail_mime_type) = encode(
+        &image.thumbnail(THUMBNAIL_DIMENSION, THUMBNAIL_DIMENSION),
+        Some(THUMBNAIL_JPEG_QUALITY),
+    )?;
+
+    Ok(ProcessedImage {
+        data,
+        mime_type,
+        width: image.width(),
+        height: image.height(),
+        thumbnail,
+        thumbnail_mime_type,
+    })
+}
+
+/// 按文件头识别图片类型，用于没有记录类型的缩略图
+pub fn sniff_mime_type(bytes: &[u8]) -> Option<&'static str> {
+    image::guess_format(bytes).ok().map(|format| format.to_mime_type())
+}
+
+pub fn data_url(mime_type: &str, bytes: &[u8]) -> String {
+    format!("data:{};base64,{}", mime_type, BASE64.encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    fn encoded(image: DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        image.write_to(&mut Cursor::new(&mut buffer), format).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_process_image() {
+        let jpeg = encoded(
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(1200, 600, Rgb([200, 10, 10]))),
+            ImageFormat::Jpeg,
+        );
+        let processed = process_image(&jpeg, 512).unwrap();
+        assert_eq!((processed.width, processed.height), (512, 256));
+        assert_eq!(processed.mime_type, "image/jpeg");
+        assert_eq!(processed.thumbnail_mime_type, "image/jpeg");
+        let thumbnail = image::load_from_memory(&processed.thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (THUMBNAIL_DIMENSION, THUMBNAIL_DIMENSION / 2));
+
+        // 小图不放大；带透明通道的图片保持 PNG
+        let png = encoded(
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(40, 30, Rgba([0, 0, 0, 128]))),
+            ImageFormat::Png,
+        );
+        let processed = process_image(&png, DEFAULT_IMAGE_MAX_DIMENSION).unwrap();
+        assert_eq!((processed.width, processed.height), (40, 30));
+        assert_eq!(processed.mime_type, "image/png");
+        assert_eq!(processed.thumbnail_mime_type, "image/png");
+
+        assert!(process_image(b"not an image", 1024).is_err());
+    }
+
+    #[test]
+    fn test_image_input_decode() {
+        let input = ImageInput {
+            data: "data:image/png;base64,aGVsbG8=".to_string(),
+            name: None,
+            mime_type: None,
+        };
+        assert_eq!(input.decode().unwrap(), (Some("image/png".to_string()), b"hello".to_vec()));
+
+        let input = ImageInput {
+            data: "aGVsbG8=".to_string(),
+            name: Some("a.jpg".to_string()),
+            mime_type: Some("image/jpeg".to_string()),
+        };
+        assert_eq!(input.decode().unwrap().0.as_deref(), Some("image/jpeg"));
+        assert!(ImageInput { data: "%%%".to_string(), name: None, mime_type: None }.decode().is_err());
+        assert_eq!(data_url("image/png", b"hello"), "data:image/png;base64,aGVsbG8=");
+    }
+}
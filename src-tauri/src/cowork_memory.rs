@@ -0,0 +1,629 @@
+use crate::cowork::{CoworkMessage, CoworkSession, UserMemory};
+use crate::cowork_context;
+use crate::cowork_workspaces::normalize_workspace;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// 自动提取的记忆等待用户确认
+pub const STATUS_PENDING: &str = "pending";
+/// 自动提取且置信度足够高、可直接使用的记忆
+pub const STATUS_ACCEPTED: &str = "accepted";
+
+/// 对所有会话生效的记忆
+pub const SCOPE_GLOBAL: &str = "global";
+/// 只对工作目录位于某个路径下的会话生效，scope_value 为该路径
+pub const SCOPE_WORKSPACE: &str = "workspace";
+/// 只对单个会话生效，scope_value 为会话 id
+pub const SCOPE_SESSION: &str = "session";
+
+/// 候选记忆的类别
+pub const MEMORY_KINDS: &[&str] = &["preference", "fact", "habit"];
+
+/// 低于该置信度的候选直接丢弃
+const MIN_CANDIDATE_CONFIDENCE: f64 = 0.5;
+/// 达到该置信度的候选直接标记为 accepted
+const AUTO_ACCEPT_CONFIDENCE: f64 = 0.85;
+/// 每轮对话最多保存的候选条数
+const MAX_CANDIDATES_PER_TURN: usize = 5;
+/// 提取提示词中最多列出的已有记忆条数
+const MAX_EXISTING_IN_PROMPT: usize = 50;
+
+/// 注入提示词的记忆默认最多占用的 token 数
+pub const DEFAULT_MEMORY_TOKEN_BUDGET: usize = 800;
+/// 注入提示词的记忆默认最多条数
+pub const DEFAULT_MEMORY_TOP_K: usize = 8;
+/// 最近使用过的记忆的最大加权，随时间按半衰期衰减
+const RECENCY_BOOST: f64 = 0.5;
+const RECENCY_HALF_LIFE_DAYS: f64 = 14.0;
+
+/// 归一化文本相似度达到该值的记忆视为重复
+pub const DUPLICATE_SIMILARITY_THRESHOLD: f64 = 0.8;
+
+/// 模型从一轮对话中提出的候选记忆
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemoryCandidate {
+    pub text: String,
+    #[serde(default)]
+    pub kind: String,
+    #[serde(default)]
+    pub confidence: f64,
+    /// global / workspace / session，缺省为 global
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+impl MemoryCandidate {
+    pub fn status(&self) -> &'static str {
+        if self.confidence >= AUTO_ACCEPT_CONFIDENCE {
+            STATUS_ACCEPTED
+        } else {
+            STATUS_PENDING
+        }
+    }
+}
+
+/// 归一化记忆文本用于比较：转小写并去掉空白和标点
+pub fn normalize_text(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// 可以被检索并注入提示词的记忆：手动创建或已接受的
+pub fn is_active(memory: &UserMemory) -> bool {
+    matches!(memory.status.as_str(), "created" | STATUS_ACCEPTED)
+}
+
+/// 校验并规范化记忆范围，返回 (scope, scope_value)
+pub fn validate_scope(scope: &str, scope_value: Option<&str>) -> anyhow::Result<(String, Option<String>)> {
+    let value = scope_value.map(str::trim).filter(|v| !v.is_empty());
+    match scope {
+        SCOPE_GLOBAL => Ok((SCOPE_GLOBAL.to_string(), None)),
+        SCOPE_WORKSPACE => {
+            let path = value.ok_or_else(|| anyhow::anyhow!("Workspace scope requires a path"))?;
+            Ok((SCOPE_WORKSPACE.to_string(), Some(normalize_workspace(path).to_string())))
+        }
+        SCOPE_SESSION => {
+            let session_id = value.ok_or_else(|| anyhow::anyhow!("Session scope requires a session id"))?;
+            Ok((SCOPE_SESSION.to_string(), Some(session_id.to_string())))
+        }
+        other => Err(anyhow::anyhow!("Unknown memory scope: {}", other)),
+    }
+}
+
+pub fn is_expired(memory: &UserMemory, now: i64) -> bool {
+    matches!(memory.expires_at, Some(expires_at) if expires_at <= now)
+}
+
+/// 记忆是否适用于指定会话：未过期，且为全局记忆、会话工作目录位于记忆的工作区内或属于该会话
+pub fn applies_to(memory: &UserMemory, session: &CoworkSession, now: i64) -> bool {
+    if is_expired(memory, now) {
+        return false;
+    }
+    match memory.scope.as_str() {
+        SCOPE_WORKSPACE => {
+            let (workspace, cwd) = match (memory.scope_value.as_deref(), session.cwd.as_deref()) {
+                (Some(workspace), Some(cwd)) if !cwd.trim().is_empty() => {
+                    (normalize_workspace(workspace), normalize_workspace(cwd))
+                }
+                _ => return false,
+            };
+            cwd == workspace
+                || cwd
+                    .strip_prefix(workspace)
+                    .map(|rest| rest.starts_with(['/', '\\']))
+                    .unwrap_or(false)
+        }
+        SCOPE_SESSION => memory.scope_value.as_deref() == Some(session.id.as_str()),
+        _ => true,
+    }
+}
+
+/// 把模型给出的候选范围映射到具体会话，缺少工作目录时退回全局
+pub fn candidate_scope(candidate: &MemoryCandidate, session: &CoworkSession) -> (String, Option<String>) {
+    match candidate.scope.as_deref() {
+        Some(SCOPE_SESSION) => (SCOPE_SESSION.to_string(), Some(session.id.clone())),
+        Some(SCOPE_WORKSPACE) => match session.cwd.as_deref().map(str::trim).filter(|c| !c.is_empty()) {
+            Some(cwd) => (SCOPE_WORKSPACE.to_string(), Some(normalize_workspace(cwd).to_string())),
+            None => (SCOPE_GLOBAL.to_string(), None),
+        },
+        _ => (SCOPE_GLOBAL.to_string(), None),
+    }
+}
+
+/// 切分检索用的词项：拉丁字母和数字按单词，CJK 文本按相邻二字组
+fn terms(text: &str) -> HashSet<String> {
+    fn flush(word: &mut String, cjk_run: &mut Vec<char>, terms: &mut HashSet<String>) {
+        if word.chars().count() >= 2 {
+            terms.insert(word.clone());
+        }
+        match cjk_run.len() {
+            0 => {}
+            1 => {
+                terms.insert(cjk_run[0].to_string());
+            }
+            _ => {
+                for pair in cjk_run.windows(2) {
+                    terms.insert(pair.iter().collect());
+                }
+            }
+        }
+        word.clear();
+        cjk_run.clear();
+    }
+
+    let mut terms = HashSet::new();
+    let mut word = String::new();
+    let mut cjk_run = Vec::new();
+    for c in text.chars().flat_map(|c| c.to_lowercase()) {
+        // 全角标点同属 CJK 区段，但不作为检索词项
+        if cowork_context::is_cjk(c) && c.is_alphanumeric() {
+            if !word.is_empty() {
+                flush(&mut word, &mut Vec::new(), &mut terms);
+            }
+            cjk_run.push(c);
+        } else if c.is_alphanumeric() {
+            if !cjk_run.is_empty() {
+                flush(&mut String::new(), &mut cjk_run, &mut terms);
+            }
+            word.push(c);
+        } else {
+            flush(&mut word, &mut cjk_run, &mut terms);
+        }
+    }
+    flush(&mut word, &mut cjk_run, &mut terms);
+    terms
+}
+
+/// 按与当前输入的词项重合度打分，再按置信度和最近使用时间加权，
+/// 只返回有词项命中的记忆，分数从高到低排列
+pub fn rank_memories<'a>(query: &str, memories: &'a [UserMemory], now: i64) -> Vec<(f64, &'a UserMemory)> {
+    let query_terms = terms(query);
+    if query_terms.is_empty() {
+        return Vec::new();
+    }
+
+    let mut ranked: Vec<(f64, &UserMemory)> = memories
+        .iter()
+        .filter(|m| is_active(m))
+        .filter_map(|memory| {
+            let memory_terms = terms(&memory.text);
+            let matched = memory_terms.intersection(&query_terms).count();
+            if matched == 0 {
+                return None;
+            }
+            let lexical = matched as f64 / (memory_terms.len() as f64).sqrt();
+            let confidence = 0.5 + 0.5 * memory.confidence.clamp(0.0, 1.0);
+            let recency = memory
+                .last_used_at
+                .map(|used| {
+                    let age_days = (now - used).max(0) as f64 / 86_400_000.0;
+                    1.0 + RECENCY_BOOST * 0.5f64.powf(age_days / RECENCY_HALF_LIFE_DAYS)
+                })
+                .unwrap_or(1.0);
+            Some((lexical * confidence * recency, memory))
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    ranked
+}
+
+/// 按排名依次选取记忆，直到达到条数上限或 token 预算
+pub fn select_memories<'a>(
+    ranked: &[(f64, &'a UserMemory)],
+    top_k: usize,
+    token_budget: usize,
+) -> Vec<&'a UserMemory> {
+    let mut selected = Vec::new();
+    let mut used = 0;
+    for (_, memory) in ranked {
+        if selected.len() >= top_k {
+            break;
+        }
+        let tokens = cowork_context::estimate_tokens(&memory.text) + 2;
+        if used + tokens > token_budget {
+            continue;
+        }
+        used += tokens;
+        selected.push(*memory);
+    }
+    selected
+}
+
+/// 把选中的记忆格式化为系统提示词段落
+pub fn build_memory_prompt(memories: &[&UserMemory]) -> String {
+    if memories.is_empty() {
+        return String::new();
+    }
+    let mut prompt = vec![
+        "## User memories".to_string(),
+        "Things you know about the user from earlier conversations. Use them when relevant; do not mention them otherwise.".to_string(),
+    ];
+    for memory in memories {
+        prompt.push(format!("- {}", memory.text));
+    }
+    prompt.join("\n")
+}
+
+fn dice<T: Eq + std::hash::Hash>(a: &HashSet<T>, b: &HashSet<T>) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    2.0 * a.intersection(b).count() as f64 / (a.len() + b.len()) as f64
+}
+
+/// 两条记忆文本的相似度（0~1），取字符二元组和词项两种 Dice 系数中的较大值，
+/// 前者容忍错别字和标点差异，后者容忍词序变化
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let (na, nb) = (normalize_text(a), normalize_text(b));
+    if na.is_empty() || nb.is_empty() {
+        return 0.0;
+    }
+    if na == nb {
+        return 1.0;
+    }
+    let bigrams = |text: &str| -> HashSet<(char, char)> {
+        let chars: Vec<char> = text.chars().collect();
+        chars.windows(2).map(|w| (w[0], w[1])).collect()
+    };
+    dice(&bigrams(&na), &bigrams(&nb)).max(dice(&terms(a), &terms(b)))
+}
+
+/// 一组重复记忆的合并方案
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergePlan {
+    pub survivor_id: String,
+    pub merged_ids: Vec<String>,
+    /// 合并后的置信度：各条独立佐证同一事实，按 1 - Π(1 - c) 累积
+    pub confidence: f64,
+    pub status: String,
+    pub is_explicit: bool,
+    /// 每条被合并记忆与保留记忆的相似度，顺序与 merged_ids 一致
+    pub similarities: Vec<f64>,
+}
+
+fn status_rank(status: &str) -> u8 {
+    match status {
+        "created" => 3,
+        STATUS_ACCEPTED => 2,
+        STATUS_PENDING => 1,
+        _ => 0,
+    }
+}
+
+/// 找出同一范围内相似度超过阈值的记忆并生成合并方案。保留记忆优先选择手动创建的、
+/// 其次置信度高的、再次最近更新的；已删除的记忆不参与合并
+pub fn plan_merges(memories: &[UserMemory], threshold: f64) -> Vec<MergePlan> {
+    let candidates: Vec<&UserMemory> = memories.iter().filter(|m| m.status != "deleted").collect();
+    let n = candidates.len();
+
+    // 并查集：相似的记忆归入同一组
+    let mut parent: Vec<usize> = (0..n).collect();
+    fn find(parent: &mut [usize], i: usize) -> usize {
+        let mut root = i;
+        while parent[root] != root {
+            root = parent[root];
+        }
+        parent[i] = root;
+        root
+    }
+    for i in 0..n {
+        for j in (i + 1)..n {
+            let same_scope = candidates[i].scope == candidates[j].scope
+                && candidates[i].scope_value == candidates[j].scope_value;
+            if same_scope && similarity(&candidates[i].text, &candidates[j].text) >= threshold {
+                let (ri, rj) = (find(&mut parent, i), find(&mut parent, j));
+                if ri != rj {
+                    parent[rj] = ri;
+                }
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<&UserMemory>> = Vec::new();
+    let mut group_of_root: HashMap<usize, usize> = HashMap::new();
+    for (i, memory) in candidates.iter().enumerate() {
+        let root = find(&mut parent, i);
+        let index = *group_of_root.entry(root).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[index].push(memory);
+    }
+
+    groups
+        .into_iter()
+        .filter(|group| group.len() > 1)
+        .map(|mut group| {
+            group.sort_by(|a, b| {
+                b.is_explicit
+                    .cmp(&a.is_explicit)
+                    .then(b.confidence.partial_cmp(&a.confidence).unwrap_or(std::cmp::Ordering::Equal))
+                    .then(b.updated_at.cmp(&a.updated_at))
+            });
+            let survivor = group[0];
+            let confidence = 1.0
+                - group
+                    .iter()
+                    .map(|m| 1.0 - m.confidence.clamp(0.0, 1.0))
+                    .product::<f64>();
+            let status = group
+                .iter()
+                .map(|m| m.status.as_str())
+                .max_by_key(|s| status_rank(s))
+                .unwrap_or(STATUS_PENDING)
+                .to_string();
+            MergePlan {
+                survivor_id: survivor.id.clone(),
+                merged_ids: group[1..].iter().map(|m| m.id.clone()).collect(),
+                confidence,
+                status,
+                is_explicit: group.iter().any(|m| m.is_explicit),
+                similarities: group[1..]
+                    .iter()
+                    .map(|m| similarity(&survivor.text, &m.text))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// 生成让模型从一轮对话中提取长期记忆的提示词
+pub fn build_extraction_prompt(
+    user_message: &CoworkMessage,
+    assistant_message: &CoworkMessage,
+    existing: &[UserMemory],
+) -> String {
+    let mut prompt = vec![
+        "Extract long-term memories about the user from the conversation turn below.".to_string(),
+        "Only keep stable information that will be useful in future conversations: preferences (how the user likes things done), facts (about the user, their work or environment) and habits (recurring behaviour).".to_string(),
+        "Ignore one-off requests, task details and anything already covered by the known memories.".to_string(),
+        "Write each memory as one short sentence in the language of the conversation.".to_string(),
+        "Set scope to \"workspace\" for memories that only hold for the current project or working directory, \"session\" for memories that only matter in this conversation, and \"global\" otherwise.".to_string(),
+        format!(
+            "Reply with a JSON array only, e.g. [{{\"text\": \"...\", \"kind\": \"preference\", \"scope\": \"global\", \"confidence\": 0.9}}], where kind is one of {} and confidence is between 0 and 1. Reply with [] if there is nothing to remember.",
+            MEMORY_KINDS.join(", ")
+        ),
+    ];
+
+    if !existing.is_empty() {
+        prompt.push(String::new());
+        prompt.push("<known_memories>".to_string());
+        for memory in existing.iter().take(MAX_EXISTING_IN_PROMPT) {
+            prompt.push(format!("- {}", memory.text));
+        }
+        prompt.push("</known_memories>".to_string());
+    }
+
+    prompt.push(String::new());
+    prompt.push("<conversation>".to_string());
+    prompt.push(format!("[user] {}", user_message.content));
+    prompt.push(format!("[assistant] {}", assistant_message.content));
+    prompt.push("</conversation>".to_string());
+    prompt.join("\n")
+}
+
+/// 从模型回复中解析候选记忆，兼容代码块包裹和前后多余文字，
+/// 过滤低置信度、类别无效以及与已有记忆重复的候选
+pub fn parse_candidates(response: &str, existing: &[UserMemory]) -> Vec<MemoryCandidate> {
+    let (start, end) = match (response.find('['), response.rfind(']')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return Vec::new(),
+    };
+    let candidates: Vec<MemoryCandidate> = match serde_json::from_str(&response[start..=end]) {
+        Ok(candidates) => candidates,
+        Err(e) => {
+            println!("[Cowork] Failed to parse memory candidates: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut seen: Vec<String> = existing.iter().map(|m| normalize_text(&m.text)).collect();
+    let mut result = Vec::new();
+    for mut candidate in candidates {
+        candidate.text = candidate.text.trim().to_string();
+        candidate.confidence = candidate.confidence.clamp(0.0, 1.0);
+        if !MEMORY_KINDS.contains(&candidate.kind.as_str()) {
+            candidate.kind = "fact".to_string();
+        }
+
+        let normalized = normalize_text(&candidate.text);
+        if normalized.is_empty()
+            || candidate.confidence < MIN_CANDIDATE_CONFIDENCE
+            || seen.contains(&normalized)
+        {
+            continue;
+        }
+        seen.push(normalized);
+        result.push(candidate);
+        if result.len() >= MAX_CANDIDATES_PER_TURN {
+            break;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(text: &str) -> UserMemory {
+        UserMemory {
+            id: "memory_1".to_string(),
+            text: text.to_string(),
+            confidence: 1.0,
+            is_explicit: true,
+            status: "created".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            last_used_at: None,
+            kind: None,
+            scope: SCOPE_GLOBAL.to_string(),
+            scope_value: None,
+            expires_at: None,
+        }
+    }
+
+    fn session(cwd: Option<&str>) -> CoworkSession {
+        CoworkSession {
+            id: "session_1".to_string(),
+            title: "Test".to_string(),
+            status: "idle".to_string(),
+            pinned: false,
+            cwd: cwd.map(|c| c.to_string()),
+            system_prompt: None,
+            execution_mode: None,
+            active_skill_ids: None,
+            created_at: 0,
+            updated_at: 0,
+            context_budget: None,
+            parent_session_id: None,
+            fork_sequence: None,
+            title_source: None,
+            folder_id: None,
+            archived_at: None,
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_memory_scopes() {
+        let now = 1000;
+        let mut workspace = memory("Uses pnpm");
+        workspace.scope = SCOPE_WORKSPACE.to_string();
+        workspace.scope_value = Some("/home/me/app".to_string());
+        let mut per_session = memory("Draft is for the board meeting");
+        per_session.scope = SCOPE_SESSION.to_string();
+        per_session.scope_value = Some("session_1".to_string());
+        let mut expired = memory("On vacation");
+        expired.expires_at = Some(now);
+
+        let inside = session(Some("/home/me/app/packages/web/"));
+        let outside = session(Some("/home/me/application"));
+        assert!(applies_to(&workspace, &inside, now));
+        assert!(!applies_to(&workspace, &outside, now));
+        assert!(!applies_to(&workspace, &session(None), now));
+        assert!(applies_to(&per_session, &inside, now));
+        let mut other = inside.clone();
+        other.id = "session_2".to_string();
+        assert!(!applies_to(&per_session, &other, now));
+        assert!(!applies_to(&expired, &inside, now));
+        assert!(applies_to(&memory("Likes tea"), &outside, now));
+
+        assert_eq!(
+            validate_scope(SCOPE_WORKSPACE, Some("/home/me/app/")).unwrap(),
+            (SCOPE_WORKSPACE.to_string(), Some("/home/me/app".to_string()))
+        );
+        assert_eq!(validate_scope(SCOPE_GLOBAL, Some("x")).unwrap().1, None);
+        assert!(validate_scope(SCOPE_SESSION, None).is_err());
+        assert!(validate_scope("team", None).is_err());
+
+        let candidate = MemoryCandidate {
+            text: "Uses pnpm".to_string(),
+            kind: "habit".to_string(),
+            confidence: 0.9,
+            scope: Some(SCOPE_WORKSPACE.to_string()),
+        };
+        assert_eq!(candidate_scope(&candidate, &session(None)).0, SCOPE_GLOBAL);
+        assert_eq!(
+            candidate_scope(&candidate, &inside).1.as_deref(),
+            Some("/home/me/app/packages/web")
+        );
+    }
+
+    #[test]
+    fn test_rank_and_select_memories() {
+        let now = 100 * 86_400_000;
+        let mut rust = memory("Prefers Rust for backend services");
+        rust.id = "rust".to_string();
+        let mut recent = memory("Uses tokio in Rust projects");
+        recent.id = "recent".to_string();
+        recent.last_used_at = Some(now);
+        let mut chinese = memory("回复请使用中文");
+        chinese.id = "chinese".to_string();
+        let mut pending = memory("Rust rust rust");
+        pending.status = STATUS_PENDING.to_string();
+        let memories = vec![rust, recent, chinese, pending];
+
+        let ranked = rank_memories("How should I structure a Rust backend?", &memories, now);
+        let ids: Vec<_> = ranked.iter().map(|(_, m)| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["rust", "recent"]);
+
+        let ranked = rank_memories("请用中文回复", &memories, now);
+        assert_eq!(ranked.len(), 1);
+        assert_eq!(ranked[0].1.id, "chinese");
+
+        let ranked = rank_memories("rust tokio backend", &memories, now);
+        assert_eq!(select_memories(&ranked, 1, 1000).len(), 1);
+        assert!(select_memories(&ranked, 10, 3).is_empty());
+        let prompt = build_memory_prompt(&select_memories(&ranked, 10, 1000));
+        assert!(prompt.starts_with("## User memories"));
+        assert!(prompt.contains("- Uses tokio in Rust projects"));
+    }
+
+    #[test]
+    fn test_plan_merges() {
+        let mut a = memory("Prefers Chinese replies");
+        a.id = "a".to_string();
+        a.is_explicit = false;
+        a.confidence = 0.6;
+        a.status = STATUS_PENDING.to_string();
+        let mut b = memory("prefers replies in Chinese.");
+        b.id = "b".to_string();
+        b.is_explicit = false;
+        b.confidence = 0.5;
+        b.status = STATUS_ACCEPTED.to_string();
+        let mut c = memory("Prefers Chinese replies!");
+        c.id = "c".to_string();
+        c.is_explicit = false;
+        c.confidence = 0.9;
+        c.status = STATUS_PENDING.to_string();
+        let mut d = memory("Works at Acme");
+        d.id = "d".to_string();
+        let mut e = memory("Prefers Chinese replies");
+        e.id = "e".to_string();
+        e.status = "deleted".to_string();
+
+        assert!(similarity(&a.text, &b.text) >= DUPLICATE_SIMILARITY_THRESHOLD);
+        assert!(similarity(&a.text, &d.text) < DUPLICATE_SIMILARITY_THRESHOLD);
+
+        let plans = plan_merges(&[a, b, c, d, e], DUPLICATE_SIMILARITY_THRESHOLD);
+        assert_eq!(plans.len(), 1);
+        let plan = &plans[0];
+        assert_eq!(plan.survivor_id, "c");
+        assert_eq!(plan.merged_ids, vec!["a".to_string(), "b".to_string()]);
+        assert!((plan.confidence - (1.0 - 0.1 * 0.4 * 0.5)).abs() < 1e-9);
+        assert_eq!(plan.status, STATUS_ACCEPTED);
+        assert!(!plan.is_explicit);
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(normalize_text("Prefers  Chinese replies!"), "preferschinesereplies");
+        assert_eq!(normalize_text("喜欢用中文，回复。"), "喜欢用中文回复");
+    }
+
+    #[test]
+    fn test_parse_candidates() {
+        let response = r#"Sure:
+```json
+[
+  {"text": "Prefers Chinese replies", "kind": "preference", "confidence": 0.95},
+  {"text": "prefers chinese replies.", "kind": "preference", "confidence": 0.9},
+  {"text": "Works at Acme", "kind": "unknown", "confidence": 0.7},
+  {"text": "Maybe likes tea", "kind": "preference", "confidence": 0.2},
+  {"text": "Uses vim", "kind": "habit", "confidence": 0.8}
+]
+```"#;
+        let candidates = parse_candidates(response, &[memory("Uses Vim")]);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].status(), STATUS_ACCEPTED);
+        assert_eq!(candidates[1].text, "Works at Acme");
+        assert_eq!(candidates[1].kind, "fact");
+        assert_eq!(candidates[1].status(), STATUS_PENDING);
+
+        assert!(parse_candidates("[]", &[]).is_empty());
+        assert!(parse_candidates("nothing to remember", &[]).is_empty());
+    }
+}
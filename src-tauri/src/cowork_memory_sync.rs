@@ -0,0 +1,470 @@
+use crate::cowork::UserMemory;
+use crate::cowork_memory::{self, SCOPE_GLOBAL, SCOPE_SESSION, SCOPE_WORKSPACE};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+/// 数据目录下可编辑的记忆文件
+pub const MEMORY_FILE_NAME: &str = "memories.md";
+/// 上次同步时的快照，用于判断文件和数据库各自改了什么
+pub const SYNC_STATE_FILE_NAME: &str = "memories.sync.json";
+
+const FILE_HEADER: &str = "# Memories\n\n\
+<!-- 由 GloAI 自动同步。每条记忆占一行，可以直接增加、删除或修改；\
+行尾的 id 注释用于关联已有记忆，新增记忆不需要写 id。\
+用 \"## workspace: <路径>\" 或 \"## session: <会话 id>\" 标题限定记忆的适用范围。 -->\n";
+const ID_MARKER: &str = "<!-- id:";
+
+/// 一条记忆在文件中的内容，也是同步快照中保存的内容
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncEntry {
+    pub text: String,
+    pub scope: String,
+    #[serde(default)]
+    pub scope_value: Option<String>,
+}
+
+impl SyncEntry {
+    pub fn from_memory(memory: &UserMemory) -> Self {
+        SyncEntry {
+            text: single_line(&memory.text),
+            scope: memory.scope.clone(),
+            scope_value: memory.scope_value.clone(),
+        }
+    }
+}
+
+/// 上次同步完成时的状态
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncState {
+    /// 上次写出的文件内容的哈希
+    #[serde(default)]
+    pub file_hash: Option<String>,
+    /// 上次同步时数据库中记忆的指纹
+    #[serde(default)]
+    pub db_fingerprint: Option<String>,
+    #[serde(default)]
+    pub synced_at: i64,
+    #[serde(default)]
+    pub entries: HashMap<String, SyncEntry>,
+    /// 冲突时由文件版本另存的待确认记忆，确认或丢弃前继续写在文件中
+    #[serde(default)]
+    pub conflict_ids: Vec<String>,
+}
+
+/// 参与同步的记忆：未过期的有效记忆，以及尚未处理的冲突副本
+pub fn synced_memories<'a>(memories: &'a [UserMemory], conflict_ids: &[String], now: i64) -> Vec<&'a UserMemory> {
+    memories
+        .iter()
+        .filter(|m| {
+            let unresolved = m.status == cowork_memory::STATUS_PENDING && conflict_ids.contains(&m.id);
+            (cowork_memory::is_active(m) || unresolved) && !cowork_memory::is_expired(m, now)
+        })
+        .collect()
+}
+
+/// 读取同步快照，文件不存在或无法解析时返回 None
+pub fn load_state(path: &Path) -> Option<SyncState> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+}
+
+/// 从文件中解析出的一条记忆，id 为 None 表示用户新增的
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    pub id: Option<String>,
+    pub entry: SyncEntry,
+}
+
+/// 同步时需要写入数据库的操作
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncAction {
+    Create { entry: SyncEntry, status: &'static str },
+    Update { id: String, entry: SyncEntry },
+    Delete { id: String },
+}
+
+/// 文件和应用两边都改动了同一条记忆
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemorySyncConflict {
+    pub memory_id: String,
+    /// both_edited / deleted_in_file / deleted_in_app
+    pub kind: String,
+    pub app_text: Option<String>,
+    pub file_text: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+    pub conflicts: Vec<MemorySyncConflict>,
+}
+
+pub fn single_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn content_hash(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
+}
+
+fn section_heading(scope: &str, scope_value: Option<&str>) -> String {
+    match scope_value {
+        Some(value) if scope != SCOPE_GLOBAL => format!("## {}: {}", scope, value),
+        _ => format!("## {}", SCOPE_GLOBAL),
+    }
+}
+
+fn scope_order(scope: &str) -> u8 {
+    match scope {
+        SCOPE_GLOBAL => 0,
+        SCOPE_WORKSPACE => 1,
+        _ => 2,
+    }
+}
+
+/// 把记忆按范围分组渲染成 Markdown，全局记忆在前，组内按创建时间排序。
+/// 待确认的冲突副本前加一行注释提示
+pub fn render(memories: &[&UserMemory]) -> String {
+    let mut sorted: Vec<&&UserMemory> = memories.iter().collect();
+    sorted.sort_by(|a, b| {
+        scope_order(&a.scope)
+            .cmp(&scope_order(&b.scope))
+            .then_with(|| a.scope_value.cmp(&b.scope_value))
+            .then_with(|| a.created_at.cmp(&b.created_at))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+
+    let mut output = String::from(FILE_HEADER);
+    let mut current: Option<String> = None;
+    if sorted.is_empty() {
+        output.push_str(&format!("\n{}\n", section_heading(SCOPE_GLOBAL, None)));
+    }
+    for memory in sorted {
+        let heading = section_heading(&memory.scope, memory.scope_value.as_deref());
+        if current.as_deref() != Some(heading.as_str()) {
+            output.push_str(&format!("\n{}\n\n", heading));
+            current = Some(heading);
+        }
+        if memory.status == cowork_memory::STATUS_PENDING {
+            output.push_str("<!-- 待确认：文件和应用同时修改了这条记忆，在应用中确认，或删除这一行丢弃 -->\n");
+        }
+        output.push_str(&format!(
+            "- {} {}{} -->\n",
+            single_line(&memory.text),
+            ID_MARKER,
+            memory.id
+        ));
+    }
+    output
+}
+
+fn parse_heading(heading: &str) -> (String, Option<String>) {
+    let (scope, value) = match heading.split_once(':') {
+        Some((scope, value)) => (scope.trim().to_lowercase(), Some(value.trim())),
+        None => (heading.trim().to_lowercase(), None),
+    };
+    match scope.as_str() {
+        SCOPE_WORKSPACE | SCOPE_SESSION => {
+            cowork_memory::validate_scope(&scope, value).unwrap_or((SCOPE_GLOBAL.to_string(), None))
+        }
+        _ => (SCOPE_GLOBAL.to_string(), None),
+    }
+}
+
+/// 解析记忆文件：二级标题决定范围，列表项为记忆，其余内容忽略
+pub fn parse(content: &str) -> Vec<FileEntry> {
+    let mut scope = (SCOPE_GLOBAL.to_string(), None);
+    let mut entries = Vec::new();
+    let mut in_comment = false;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if in_comment {
+            in_comment = !line.contains("-->");
+            continue;
+        }
+        if line.starts_with("<!--") {
+            in_comment = !line.contains("-->");
+            continue;
+        }
+        if let Some(heading) = line.strip_prefix("## ") {
+            scope = parse_heading(heading);
+            continue;
+        }
+        let item = match line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+            Some(item) => item,
+            None => continue,
+        };
+
+        let (text, id) = match item.rfind(ID_MARKER) {
+            Some(pos) => {
+                let id = item[pos + ID_MARKER.len()..]
+                    .trim_end()
+                    .trim_end_matches("-->")
+                    .trim();
+                (&item[..pos], Some(id.to_string()).filter(|id| !id.is_empty()))
+            }
+            None => (item, None),
+        };
+        let text = single_line(text);
+        if text.is_empty() {
+            continue;
+        }
+        entries.push(FileEntry {
+            id,
+            entry: SyncEntry {
+                text,
+                scope: scope.0.clone(),
+                scope_value: scope.1.clone(),
+            },
+        });
+    }
+    entries
+}
+
+/// 以上次同步的快照为基准做三方合并：
+/// 只有一边改动时采用改动的一边；两边改成不同内容时保留应用中的版本，
+/// 文件中的版本另存为待确认的记忆；修改优先于删除。
+/// 没有快照（首次同步）的记忆两边内容一致时视为已同步
+fn same_entry(a: &SyncEntry, b: &SyncEntry) -> bool {
+    a.scope == b.scope
+        && a.scope_value == b.scope_value
+        && cowork_memory::normalize_text(&a.text) == cowork_memory::normalize_text(&b.text)
+}
+
+pub fn plan_sync(app: &[&UserMemory], file: &[FileEntry], base: &SyncState) -> SyncPlan {
+    let app_entries: HashMap<&str, SyncEntry> = app
+        .iter()
+        .map(|m| (m.id.as_str(), SyncEntry::from_memory(m)))
+        .collect();
+
+    let mut file_entries: HashMap<&str, &SyncEntry> = HashMap::new();
+    let mut additions: Vec<&SyncEntry> = Vec::new();
+    for item in file {
+        match item.id.as_deref() {
+            Some(id) if !file_entries.contains_key(id) => {
+                file_entries.insert(id, &item.entry);
+            }
+            _ => additions.push(&item.entry),
+        }
+    }
+
+    let ids: BTreeSet<&str> = base
+        .entries
+        .keys()
+        .map(String::as_str)
+        .chain(file_entries.keys().copied())
+        .chain(app_entries.keys().copied())
+        .collect();
+
+    let mut plan = SyncPlan::default();
+    for id in ids {
+        let base_entry = base.entries.get(id);
+        let file_entry = file_entries.get(id).copied();
+        let app_entry = app_entries.get(id);
+
+        let file_changed = file_entry != base_entry;
+        let app_changed = app_entry != base_entry;
+        if !file_changed || file_entry == app_entry {
+            continue;
+        }
+        if let (None, Some(file_entry), Some(app_entry)) = (base_entry, file_entry, app_entry) {
+            if same_entry(file_entry, app_entry) {
+                continue;
+            }
+        }
+
+        if !app_changed {
+            match (file_entry, app_entry) {
+                (Some(entry), Some(_)) => plan.actions.push(SyncAction::Update {
+                    id: id.to_string(),
+                    entry: entry.clone(),
+                }),
+                (None, Some(_)) => plan.actions.push(SyncAction::Delete { id: id.to_string() }),
+                // 文件里出现了应用中不存在（或未激活）的 id，按新增处理
+                (Some(entry), None) => additions.push(entry),
+                (None, None) => {}
+            }
+            continue;
+        }
+
+        let conflict = |kind: &str| MemorySyncConflict {
+            memory_id: id.to_string(),
+            kind: kind.to_string(),
+            app_text: app_entry.map(|e| e.text.clone()),
+            file_text: file_entry.map(|e| e.text.clone()),
+        };
+        match (file_entry, app_entry) {
+            (Some(entry), Some(_)) => {
+                plan.conflicts.push(conflict("both_edited"));
+                plan.actions.push(SyncAction::Create {
+                    entry: entry.clone(),
+                    status: cowork_memory::STATUS_PENDING,
+                });
+            }
+            (None, Some(_)) => plan.conflicts.push(conflict("deleted_in_file")),
+            (Some(entry), None) => {
+                plan.conflicts.push(conflict("deleted_in_app"));
+                plan.actions.push(SyncAction::Create {
+                    entry: entry.clone(),
+                    status: cowork_memory::STATUS_PENDING,
+                });
+            }
+            (None, None) => {}
+        }
+    }
+
+    // 新增条目与已有记忆（或同一次新增中的其他条目）文本相同时跳过
+    let mut seen: Vec<(String, &str, Option<&str>)> = app_entries
+        .values()
+        .map(|e| {
+            (
+                cowork_memory::normalize_text(&e.text),
+                e.scope.as_str(),
+                e.scope_value.as_deref(),
+            )
+        })
+        .collect();
+    for entry in additions {
+        let key = (
+            cowork_memory::normalize_text(&entry.text),
+            entry.scope.as_str(),
+            entry.scope_value.as_deref(),
+        );
+        if key.0.is_empty() || seen.contains(&key) {
+            continue;
+        }
+        seen.push(key);
+        plan.actions.push(SyncAction::Create {
+            entry: entry.clone(),
+            status: "created",
+        });
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(id: &str, text: &str, scope: &str, scope_value: Option<&str>) -> UserMemory {
+        UserMemory {
+            id: id.to_string(),
+            text: text.to_string(),
+            confidence: 1.0,
+            is_explicit: true,
+            status: "created".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            last_used_at: None,
+            kind: None,
+            scope: scope.to_string(),
+            scope_value: scope_value.map(str::to_string),
+            expires_at: None,
+        }
+    }
+
+    fn state(memories: &[&UserMemory]) -> SyncState {
+        SyncState {
+            entries: memories
+                .iter()
+                .map(|m| (m.id.clone(), SyncEntry::from_memory(m)))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_and_parse_roundtrip() {
+        let a = memory("m1", "Prefers concise\nanswers", SCOPE_GLOBAL, None);
+        let b = memory("m2", "Uses pnpm", SCOPE_WORKSPACE, Some("/repo"));
+        let rendered = render(&[&b, &a]);
+        assert!(rendered.find("## global").unwrap() < rendered.find("## workspace: /repo").unwrap());
+
+        let parsed = parse(&rendered);
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].id.as_deref(), Some("m1"));
+        assert_eq!(parsed[0].entry, SyncEntry::from_memory(&a));
+        assert_eq!(parsed[1].entry, SyncEntry::from_memory(&b));
+
+        let edited = format!("{}\n## session: s1\n\n* Answer in French\n- \n", rendered);
+        let parsed = parse(&edited);
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[2].id, None);
+        assert_eq!(parsed[2].entry.scope, SCOPE_SESSION);
+        assert_eq!(parsed[2].entry.scope_value.as_deref(), Some("s1"));
+    }
+
+    #[test]
+    fn test_plan_sync() {
+        let a = memory("m1", "Prefers concise answers", SCOPE_GLOBAL, None);
+        let b = memory("m2", "Uses pnpm", SCOPE_GLOBAL, None);
+        let c = memory("m3", "Works on weekends", SCOPE_GLOBAL, None);
+        let base = state(&[&a, &b, &c]);
+
+        // 文件中修改 m1、删除 m2、新增一条，应用中未改动
+        let mut file = parse(&render(&[&a, &c]));
+        file[0].entry.text = "Prefers very concise answers".to_string();
+        file.push(FileEntry {
+            id: None,
+            entry: SyncEntry {
+                text: "Likes dark mode".to_string(),
+                scope: SCOPE_GLOBAL.to_string(),
+                scope_value: None,
+            },
+        });
+        file.push(FileEntry {
+            id: None,
+            entry: SyncEntry::from_memory(&c),
+        });
+        let plan = plan_sync(&[&a, &b, &c], &file, &base);
+        assert!(plan.conflicts.is_empty());
+        assert_eq!(plan.actions.len(), 3);
+        assert!(plan.actions.contains(&SyncAction::Delete { id: "m2".to_string() }));
+        assert!(plan.actions.iter().any(|a| matches!(a,
+            SyncAction::Update { id, entry } if id == "m1" && entry.text == "Prefers very concise answers")));
+        assert!(plan.actions.iter().any(|a| matches!(a,
+            SyncAction::Create { entry, status } if entry.text == "Likes dark mode" && *status == "created")));
+
+        // 应用中修改、文件未改动：不需要写数据库
+        let mut a_edited = a.clone();
+        a_edited.text = "Prefers short answers".to_string();
+        let file = parse(&render(&[&a, &b, &c]));
+        let plan = plan_sync(&[&a_edited, &b, &c], &file, &base);
+        assert!(plan.actions.is_empty() && plan.conflicts.is_empty());
+
+        // 两边都改：保留应用版本，文件版本存为待确认记忆；文件删除但应用修改：保留应用版本
+        let mut file = parse(&render(&[&a, &c]));
+        file[0].entry.text = "Prefers very concise answers".to_string();
+        let mut b_edited = b.clone();
+        b_edited.text = "Uses pnpm 9".to_string();
+        let plan = plan_sync(&[&a_edited, &b_edited, &c], &file, &base);
+        assert_eq!(plan.conflicts.len(), 2);
+        assert!(plan.conflicts.iter().any(|c| c.memory_id == "m1" && c.kind == "both_edited"));
+        assert!(plan.conflicts.iter().any(|c| c.memory_id == "m2" && c.kind == "deleted_in_file"));
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Create {
+                entry: file[0].entry.clone(),
+                status: cowork_memory::STATUS_PENDING,
+            }]
+        );
+
+        // 首次同步没有快照：内容一致（忽略大小写和标点）的条目视为已同步
+        let mut file = parse(&render(&[&a, &b]));
+        file[1].entry.text = "uses PNPM.".to_string();
+        let plan = plan_sync(&[&a, &b], &file, &SyncState::default());
+        assert!(plan.actions.is_empty() && plan.conflicts.is_empty());
+
+        // 应用中删除、文件中修改：文件版本存为待确认记忆
+        let mut file = parse(&render(&[&a, &b, &c]));
+        file[2].entry.text = "Works on Saturdays".to_string();
+        let plan = plan_sync(&[&a, &b], &file, &base);
+        assert_eq!(plan.conflicts.len(), 1);
+        assert_eq!(plan.conflicts[0].kind, "deleted_in_app");
+        assert_eq!(plan.actions.len(), 1);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::cowork::{CoworkMessage, CoworkSession};
+use crate::cowork_attachments::{self, Attachment};
+use crate::cowork_context;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<ChatAttachment>,
+    /// 供视觉模型读取的图片，由附件中的图片生成
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ChatImage>,
+}
+
+/// 以 data URL 内联的图片，与前端 ImageAttachment 的格式一致
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatImage {
+    pub name: String,
+    pub mime_type: String,
+    pub data_url: String,
+}
+
+/// 随消息发送的附件，`path` 为附件在本地存储中的文件路径
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatAttachment {
+    #[serde(flatten)]
+    pub attachment: Attachment,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+/// 发送给 GoClaw `chat` 方法的完整请求
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatRequest {
+    pub session_id: String,
+    pub content: String,
+    pub messages: Vec<ChatMessage>,
+    pub system_prompt: Option<String>,
+    pub cwd: Option<String>,
+    pub execution_mode: Option<String>,
+    pub stream: bool,
+}
+
+/// 解析会话的 active_skill_ids，兼容 JSON 数组和逗号分隔两种格式
+pub fn parse_skill_ids(raw: Option<&str>) -> Option<Vec<String>> {
+    let raw = raw.map(str::trim).filter(|s| !s.is_empty())?;
+    if let Ok(ids) = serde_json::from_str::<Vec<String>>(raw) {
+        return Some(ids);
+    }
+    Some(
+        raw.split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect(),
+    )
+}
+
+fn non_empty(value: Option<&String>) -> Option<&str> {
+    value.map(|s| s.trim()).filter(|s| !s.is_empty())
+}
+
+/// 把消息类型映射为模型角色，不参与对话上下文的消息返回 None
+fn chat_role(message: &CoworkMessage) -> Option<&'static str> {
+    let role = match message.r#type.as_str() {
+        "user" => "user",
+        "assistant" => "assistant",
+        "system" => "system",
+        _ => return None,
+    };
+
+    // 失败的回复和空占位消息（不带附件）不应进入上下文
+    let failed = message
+        .metadata
+        .as_deref()
+        .and_then(|m| serde_json::from_str::<serde_json::Value>(m).ok())
+        .and_then(|m| m.get("status").and_then(|s| s.as_str()).map(|s| s == "error"))
+        .unwrap_or(false);
+    let has_attachments = !cowork_attachments::attachments_from_metadata(message.metadata.as_deref()).is_empty();
+    if failed || (message.content.trim().is_empty() && !has_attachments) {
+        return None;
+    }
+    Some(role)
+}
+
+/// 组合会话系统提示词、工作环境以及技能路由、用户记忆等附加段落
+pub fn build_system_prompt(session: &CoworkSession, extra_sections: &[String]) -> Option<String> {
+    let mut sections = Vec::new();
+
+    if let Some(system_prompt) = non_empty(session.system_prompt.as_ref()) {
+        sections.push(system_prompt.to_string());
+    }
+
+    let cwd = non_empty(session.cwd.as_ref());
+    let execution_mode = non_empty(session.execution_mode.as_ref());
+    if cwd.is_some() || execution_mode.is_some() {
+        let mut env = vec!["## Environment".to_string()];
+        if let Some(cwd) = cwd {
+            env.push(format!("- Working directory: {}", cwd));
+        }
+        if let Some(mode) = execution_mode {
+            env.push(format!("- Execution mode: {}", mode));
+        }
+        sections.push(env.join("\n"));
+    }
+
+    for section in extra_sections {
+        if !section.trim().is_empty() {
+            sections.push(section.trim().to_string());
+        }
+    }
+
+    if sections.is_empty() {
+        None
+    } else {
+        Some(sections.join("\n\n"))
+    }
+}
+
+pub fn history_to_chat_messages(history: &[CoworkMessage]) -> Vec<ChatMessage> {
+    history
+        .iter()
+        .filter_map(|m| {
+            chat_role(m).map(|role| ChatMessage {
+                role: role.to_string(),
+                content: m.content.clone(),
+                attachments: cowork_attachments::attachments_from_metadata(m.metadata.as_deref())
+                    .into_iter()
+                    .map(|attachment| ChatAttachment { attachment, path: None })
+                    .collect(),
+                images: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// 根据会话设置和已存储的历史构建请求，`history` 的最后一条用户消息即本轮输入，
+/// `summary` 为更早历史的压缩摘要，`extra_sections` 按顺序追加到系统提示词末尾
+pub fn build_chat_request(
+    session: &CoworkSession,
+    history: &[CoworkMessage],
+    summary: Option<&str>,
+    extra_sections: &[String],
+) -> ChatRequest {
+    let mut messages = history_to_chat_messages(history);
+    if let Some(summary) = summary {
+        messages.insert(0, cowork_context::summary_chat_message(summary));
+    }
+    let content = messages
+        .iter()
+        .rev()
+        .find(|m| m.role == "user")
+        .map(|m| m.content.clone())
+        .unwrap_or_default();
+
+    ChatRequest {
+        session_id: session.id.clone(),
+        content,
+        messages,
+        system_prompt: build_system_prompt(session, extra_sections),
+        cwd: non_empty(session.cwd.as_ref()).map(|s| s.to_string()),
+        execution_mode: non_empty(session.execution_mode.as_ref()).map(|s| s.to_string()),
+        stream: true,
+    }
+}
+
+/// 自动生成的会话标题最多保留的字符数
+const MAX_TITLE_CHARS: usize = 40;
+/// 生成标题时每条消息最多截取的字符数
+const TITLE_EXCERPT_CHARS: usize = 1000;
+
+fn excerpt(text: &str, max_chars: usize) -> String {
+    let text = text.trim();
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// 根据第一轮问答生成请求会话标题的提示词
+pub fn build_title_prompt(user_message: &str, assistant_reply: &str) -> String {
+    format!(
+        "Write a short title (at most 8 words) for the conversation below. \
+Use the same language as the user. Reply with the title only, without quotes or punctuation at the end.\n\n\
+User:\n{}\n\nAssistant:\n{}",
+        excerpt(user_message, TITLE_EXCERPT_CHARS),
+        excerpt(assistant_reply, TITLE_EXCERPT_CHARS)
+    )
+}
+
+/// 清理模型返回的标题：取第一行，去掉 "Title:" 前缀、引号和结尾标点并限制长度，结果为空时返回 None
+pub fn clean_title(response: &str) -> Option<String> {
+    let line = response.lines().map(str::trim).find(|l| !l.is_empty())?;
+    let line = line.trim_start_matches('#').trim();
+    let line = ["Title:", "title:", "标题：", "标题:"]
+        .iter()
+        .find_map(|prefix| line.strip_prefix(prefix))
+        .unwrap_or(line);
+    let quotes = ['"', '\'', '“', '”', '‘', '’', '「', '」', '《', '》', '*', '`'];
+    let punctuation = ['.', '。', '!', '！', '?', '？', ',', '，', ':', '：', ';', '；'];
+    let title = line
+        .trim_start_matches(|c: char| quotes.contains(&c) || c.is_whitespace())
+        .trim_end_matches(|c: char| quotes.contains(&c) || punctuation.contains(&c) || c.is_whitespace());
+    let title: String = title.chars().take(MAX_TITLE_CHARS).collect();
+    let title = title.trim().to_string();
+    if title.is_empty() {
+        None
+    } else {
+        Some(title)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> CoworkSession {
+        CoworkSession {
+            id: "session_1".to_string(),
+            title: "Test".to_string(),
+            status: "idle".to_string(),
+            pinned: false,
+            cwd: Some("/tmp/project".to_string()),
+            system_prompt: Some("You are helpful.".to_string()),
+            execution_mode: Some("local".to_string()),
+            active_skill_ids: None,
+            created_at: 0,
+            updated_at: 0,
+            context_budget: None,
+            parent_session_id: None,
+            fork_sequence: None,
+            title_source: None,
+            folder_id: None,
+            archived_at: None,
+            tags: Vec::new(),
+        }
+    }
+
+    fn message(msg_type: &str, content: &str, metadata: Option<&str>) -> CoworkMessage {
+        CoworkMessage {
+            id: format!("msg_{}", content),
+            session_id: "session_1".to_string(),
+            r#type: msg_type.to_string(),
+            content: content.to_string(),
+            timestamp: 0,
+            metadata: metadata.map(|m| m.to_string()),
+            sequence: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_skill_ids() {
+        assert_eq!(
+            parse_skill_ids(Some(r#"["docx","pdf"]"#)),
+            Some(vec!["docx".to_string(), "pdf".to_string()])
+        );
+        assert_eq!(
+            parse_skill_ids(Some("docx, pdf")),
+            Some(vec!["docx".to_string(), "pdf".to_string()])
+        );
+        assert_eq!(parse_skill_ids(Some("  ")), None);
+        assert_eq!(parse_skill_ids(None), None);
+    }
+
+    #[test]
+    fn test_build_chat_request() {
+        let history = vec![
+            message("user", "hello", None),
+            message("assistant", "failed", Some(r#"{"status":"error"}"#)),
+            message("assistant", "hi there", Some(r#"{"status":"done"}"#)),
+            message("user", "what did I say?", None),
+        ];
+        let sections = vec!["## Memories".to_string(), "  ".to_string(), "## Skills".to_string()];
+        let request = build_chat_request(&session(), &history, Some("said hello"), &sections);
+
+        assert_eq!(request.content, "what did I say?");
+        assert_eq!(request.messages.len(), 4);
+        assert_eq!(request.messages[0].role, "system");
+        assert_eq!(request.messages[2].content, "hi there");
+
+        let system_prompt = request.system_prompt.unwrap();
+        assert!(system_prompt.starts_with("You are helpful."));
+        assert!(system_prompt.contains("Working directory: /tmp/project"));
+        assert!(system_prompt.ends_with("## Memories\n\n## Skills"));
+
+        // 只有附件没有文字的消息也要发送，附件随消息一起序列化
+        let metadata = serde_json::json!({
+            "attachments": [{ "sha256": "a".repeat(64), "name": "chart.png", "mime_type": "image/png", "size": 3 }]
+        })
+        .to_string();
+        let history = vec![message("user", "", Some(&metadata))];
+        let request = build_chat_request(&session(), &history, None, &[]);
+        assert_eq!(request.messages.len(), 1);
+        let value = serde_json::to_value(&request.messages[0]).unwrap();
+        assert_eq!(value["attachments"][0]["name"], "chart.png");
+        assert!(value["attachments"][0].get("path").is_none());
+        assert!(serde_json::to_value(&history_to_chat_messages(&[message("user", "hi", None)])[0])
+            .unwrap()
+            .get("attachments")
+            .is_none());
+    }
+
+    #[test]
+    fn test_clean_title() {
+        assert_eq!(clean_title("\"Rust lifetimes explained.\"\n"), Some("Rust lifetimes explained".to_string()));
+        assert_eq!(clean_title("Title: Fix build error"), Some("Fix build error".to_string()));
+        assert_eq!(clean_title("\n《周报总结》。\nextra"), Some("周报总结".to_string()));
+        assert_eq!(clean_title(&"a".repeat(100)).map(|t| t.chars().count()), Some(MAX_TITLE_CHARS));
+        assert_eq!(clean_title("  \"\"  "), None);
+        assert!(build_title_prompt("hello", "hi").contains("User:\nhello"));
+    }
+}
@@ -0,0 +1,347 @@
+use crate::cowork_approvals;
+use crate::cowork_attachments::{self, AttachmentStore};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 数据目录下存放文件快照内容的子目录，与附件一样按 SHA-256 寻址
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+/// 每个会话默认保留快照的回复轮数
+pub const DEFAULT_SNAPSHOT_RETENTION: usize = 20;
+/// 超过此大小的文件不保存快照
+pub const MAX_SNAPSHOT_BYTES: u64 = 20 * 1024 * 1024;
+
+/// 工具参数中表示被修改文件的字段
+const PATH_KEYS: [&str; 8] = [
+    "path",
+    "file_path",
+    "file",
+    "target",
+    "destination",
+    "new_path",
+    "source",
+    "old_path",
+];
+/// 参数中列出多个被修改文件的字段
+const PATH_LIST_KEYS: [&str; 3] = ["modifies", "paths", "files"];
+/// 工具名包含这些词时视为会修改文件
+const MUTATING_TOOL_WORDS: [&str; 11] = [
+    "write", "edit", "create", "delete", "remove", "move", "rename", "patch", "replace", "append", "insert",
+];
+
+/// 一轮回复中某个文件被修改前的状态，`existed` 为 false 表示该文件由这一轮创建
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileSnapshot {
+    pub id: i64,
+    pub session_id: String,
+    /// 产生修改的助手回复消息 id
+    pub turn_id: String,
+    pub path: String,
+    pub existed: bool,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    pub size: u64,
+    pub created_at: i64,
+    #[serde(default)]
+    pub reverted_at: Option<i64>,
+    /// 回复结束时文件是否存在，None 表示未记录
+    #[serde(default)]
+    pub after_existed: Option<bool>,
+    #[serde(default)]
+    pub after_sha256: Option<String>,
+    /// 修改前的内容没有保存（未经审批就执行的工具、文件过大等），这一轮不能撤销
+    #[serde(default)]
+    pub missing: bool,
+}
+
+/// 一轮回复修改过的所有文件，`revertible` 为 false 时有文件缺少快照，前端不提供撤销
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotTurn {
+    pub turn_id: String,
+    pub session_id: String,
+    pub created_at: i64,
+    pub reverted_at: Option<i64>,
+    pub revertible: bool,
+    pub files: Vec<FileSnapshot>,
+}
+
+/// 撤销一轮修改时单个文件的处理结果，`action` 为 restored / deleted / unchanged / failed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevertedFile {
+    pub path: String,
+    pub action: String,
+    pub error: Option<String>,
+}
+
+pub fn is_mutating_tool(name: &str) -> bool {
+    let name = name.to_lowercase();
+    MUTATING_TOOL_WORDS.iter().any(|word| name.contains(word))
+}
+
+/// 找出工具调用会修改的文件，返回位于 cwd 之内的绝对路径。参数中显式列出的 modifies / paths / files
+/// 总是计入，其余路径字段只在工具名看起来会写文件时计入
+pub fn modified_paths(tool: &str, arguments: &serde_json::Value, cwd: &str) -> Vec<PathBuf> {
+    let mut raw: Vec<&str> = PATH_LIST_KEYS
+        .iter()
+        .filter_map(|key| arguments.get(*key).and_then(|v| v.as_array()))
+        .flatten()
+        .filter_map(|v| v.as_str())
+        .collect();
+    if is_mutating_tool(tool) {
+        raw.extend(PATH_KEYS.iter().filter_map(|key| arguments.get(*key).and_then(|v| v.as_str())));
+    }
+
+    let root = cowork_approvals::resolve_path(cwd, None);
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for path in raw.into_iter().map(str::trim).filter(|p| !p.is_empty()) {
+        let path = cowork_approvals::resolve_path(path, Some(cwd));
+        if path.starts_with(&root) && path != root && !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// 读取文件当前状态并把内容存入快照存储，返回 (是否存在, 内容哈希, 大小)。
+/// 目录和超过大小上限的文件返回错误
+pub fn capture(store: &AttachmentStore, path: &Path) -> anyhow::Result<(bool, Option<String>, u64)> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((false, None, 0)),
+        Err(e) => return Err(e.into()),
+    };
+    if !metadata.is_file() {
+        return Err(anyhow::anyhow!("Not a regular file: {}", path.display()));
+    }
+    if metadata.len() > MAX_SNAPSHOT_BYTES {
+        return Err(anyhow::anyhow!(
+            "File is too large to snapshot: {} ({} bytes)",
+            path.display(),
+            metadata.len()
+        ));
+    }
+    let bytes = fs::read(path)?;
+    let sha256 = store.put(&bytes)?;
+    Ok((true, Some(sha256), bytes.len() as u64))
+}
+
+/// 读取文件当前状态，返回 (是否存在, 内容哈希)，不写入快照存储
+pub fn file_state(path: &Path) -> anyhow::Result<(bool, Option<String>)> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((false, None)),
+        Err(e) => return Err(e.into()),
+    };
+    if !metadata.is_file() {
+        return Err(anyhow::anyhow!("Not a regular file: {}", path.display()));
+    }
+    Ok((true, Some(cowork_attachments::sha256_hex(&fs::read(path)?))))
+}
+
+/// 文件在这一轮结束后是否又被改动过，撤销这类文件会覆盖之后的修改。
+/// 结束时的状态未记录或读取失败时同样视为已改动，已与快照一致的文件不算
+pub fn changed_since_turn(snapshot: &FileSnapshot) -> bool {
+    let current = match file_state(Path::new(&snapshot.path)) {
+        Ok(current) => current,
+        Err(_) => return true,
+    };
+    if current == (snapshot.existed, snapshot.sha256.clone()) {
+        return false;
+    }
+    match snapshot.after_existed {
+        Some(after_existed) => current != (after_existed, snapshot.after_sha256.clone()),
+        None => true,
+    }
+}
+
+/// 把文件恢复到快照时的状态：原本存在的文件写回原内容并保留当前的权限，本轮新建的文件删除
+pub fn restore(store: &AttachmentStore, snapshot: &FileSnapshot) -> anyhow::Result<&'static str> {
+    let path = Path::new(&snapshot.path);
+    if !snapshot.existed {
+        return match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_file() => {
+                fs::remove_file(path)?;
+                Ok("deleted")
+            }
+            Ok(_) => Err(anyhow::anyhow!("Not a regular file: {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok("unchanged"),
+            Err(e) => Err(e.into()),
+        };
+    }
+
+    let sha256 = snapshot
+        .sha256
+        .as_deref()
+        .filter(|s| store.exists(s))
+        .ok_or_else(|| anyhow::anyhow!("Snapshot content missing for {}", path.display()))?;
+    let original = fs::read(store.path_for(sha256))?;
+    if fs::read(path).ok().as_deref() == Some(original.as_slice()) {
+        return Ok("unchanged");
+    }
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Invalid path: {}", path.display()))?;
+    fs::create_dir_all(dir)?;
+    let tmp_path = dir.join(format!(
+        ".{}.{}.tmp",
+        path.file_name().and_then(|n| n.to_str()).unwrap_or("snapshot"),
+        uuid::Uuid::new_v4()
+    ));
+    fs::write(&tmp_path, &original)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        if let Err(e) = fs::set_permissions(&tmp_path, metadata.permissions()) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+    }
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+    Ok("restored")
+}
+
+/// 列出快照存储中的所有内容哈希
+pub fn stored_blobs(root: &Path) -> Vec<String> {
+    let mut blobs = Vec::new();
+    let dirs = match fs::read_dir(root) {
+        Ok(dirs) => dirs,
+        Err(_) => return blobs,
+    };
+    for dir in dirs.flatten() {
+        if let Ok(files) = fs::read_dir(dir.path()) {
+            blobs.extend(
+                files
+                    .flatten()
+                    .filter_map(|f| f.file_name().to_str().map(|n| n.to_string()))
+                    .filter(|n| cowork_attachments::is_valid_sha256(n)),
+            );
+        }
+    }
+    blobs
+}
+
+/// 按回复轮次分组，最近的一轮在前
+pub fn group_turns(snapshots: Vec<FileSnapshot>) -> Vec<SnapshotTurn> {
+    let mut turns: Vec<SnapshotTurn> = Vec::new();
+    for snapshot in snapshots {
+        match turns.iter_mut().find(|t| t.turn_id == snapshot.turn_id) {
+            Some(turn) => {
+                turn.created_at = turn.created_at.min(snapshot.created_at);
+                turn.revertible &= !snapshot.missing;
+                turn.files.push(snapshot);
+            }
+            None => turns.push(SnapshotTurn {
+                turn_id: snapshot.turn_id.clone(),
+                session_id: snapshot.session_id.clone(),
+                created_at: snapshot.created_at,
+                reverted_at: snapshot.reverted_at,
+                revertible: !snapshot.missing,
+                files: vec![snapshot],
+            }),
+        }
+    }
+    turns.sort_by_key(|t| std::cmp::Reverse(t.created_at));
+    turns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_modified_paths() {
+        let arguments = serde_json::json!({ "path": "src/main.rs", "content": "fn main() {}" });
+        assert_eq!(
+            modified_paths("write_file", &arguments, "/work"),
+            vec![PathBuf::from("/work/src/main.rs")]
+        );
+        assert!(modified_paths("read_file", &arguments, "/work").is_empty());
+
+        // 显式列出的文件总是计入，cwd 之外的路径忽略
+        let arguments = serde_json::json!({
+            "command": "cargo fmt",
+            "modifies": ["a.rs", "./a.rs", "/etc/hosts", "../other/b.rs", "."],
+        });
+        assert_eq!(modified_paths("shell", &arguments, "/work"), vec![PathBuf::from("/work/a.rs")]);
+        let arguments = serde_json::json!({ "source": "a.txt", "destination": "b/a.txt" });
+        assert_eq!(modified_paths("move_file", &arguments, "/work").len(), 2);
+    }
+
+    #[test]
+    fn test_capture_and_restore() {
+        let temp_dir = tempdir().unwrap();
+        let store = AttachmentStore::new(temp_dir.path().join(SNAPSHOTS_DIR));
+        let work = temp_dir.path().join("work");
+        fs::create_dir_all(&work).unwrap();
+        let edited = work.join("notes.md");
+        let created = work.join("new/file.txt");
+        fs::write(&edited, "original").unwrap();
+
+        let snapshot = |path: &Path| {
+            let (existed, sha256, size) = capture(&store, path).unwrap();
+            FileSnapshot {
+                id: 0,
+                session_id: "session_1".to_string(),
+                turn_id: "msg_1".to_string(),
+                path: path.to_string_lossy().to_string(),
+                existed,
+                sha256,
+                size,
+                created_at: 0,
+                reverted_at: None,
+                after_existed: None,
+                after_sha256: None,
+                missing: false,
+            }
+        };
+        let edited_snapshot = snapshot(&edited);
+        let created_snapshot = snapshot(&created);
+        assert!(edited_snapshot.existed && !created_snapshot.existed);
+        assert!(capture(&store, &work).is_err());
+        assert_eq!(stored_blobs(&temp_dir.path().join(SNAPSHOTS_DIR)), vec![edited_snapshot.sha256.clone().unwrap()]);
+
+        let mut edited_snapshot = edited_snapshot;
+        let mut created_snapshot = created_snapshot;
+        fs::write(&edited, "changed by agent").unwrap();
+        fs::create_dir_all(created.parent().unwrap()).unwrap();
+        fs::write(&created, "new").unwrap();
+
+        // 结束时的状态未记录时无法确认文件之后是否被改动
+        assert!(changed_since_turn(&edited_snapshot));
+        for snapshot in [&mut edited_snapshot, &mut created_snapshot] {
+            let (existed, sha256) = file_state(Path::new(&snapshot.path)).unwrap();
+            snapshot.after_existed = Some(existed);
+            snapshot.after_sha256 = sha256;
+        }
+        assert!(!changed_since_turn(&edited_snapshot) && !changed_since_turn(&created_snapshot));
+        fs::write(&edited, "changed by user").unwrap();
+        assert!(changed_since_turn(&edited_snapshot));
+        fs::write(&edited, "original").unwrap();
+        assert!(!changed_since_turn(&edited_snapshot));
+        fs::write(&edited, "changed by agent").unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&edited, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        assert_eq!(restore(&store, &edited_snapshot).unwrap(), "restored");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&edited).unwrap().permissions().mode() & 0o777, 0o755);
+        }
+        assert_eq!(restore(&store, &created_snapshot).unwrap(), "deleted");
+        assert_eq!(fs::read_to_string(&edited).unwrap(), "original");
+        assert!(!created.exists());
+        assert_eq!(restore(&store, &edited_snapshot).unwrap(), "unchanged");
+        assert_eq!(restore(&store, &created_snapshot).unwrap(), "unchanged");
+
+        // 被删除的原有文件会重新写回
+        fs::remove_file(&edited).unwrap();
+        assert_eq!(restore(&store, &edited_snapshot).unwrap(), "restored");
+        assert_eq!(fs::read_to_string(&edited).unwrap(), "original");
+    }
+}
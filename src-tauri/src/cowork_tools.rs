@@ -0,0 +1,280 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// GoClaw 开始执行工具时推送的通知方法名
+pub const TOOL_CALL_METHOD: &str = "chat.tool_call";
+/// GoClaw 工具执行结束时推送的通知方法名
+pub const TOOL_RESULT_METHOD: &str = "chat.tool_result";
+
+/// 工具调用消息的 type，详细信息在 metadata.tool_call 中
+pub const TOOL_CALL_MESSAGE_TYPE: &str = "tool_call";
+/// 工具结果消息的 type，详细信息在 metadata.tool_result 中
+pub const TOOL_RESULT_MESSAGE_TYPE: &str = "tool_result";
+
+/// 工具消息 metadata 中引用同一会话其他消息 id 的字段
+const MESSAGE_REF_KEYS: [&str; 2] = ["reply_id", "tool_call_message_id"];
+
+/// stdout / stderr 各自最多保存的字符数，超出时保留首尾
+pub const MAX_TOOL_OUTPUT_CHARS: usize = 32 * 1024;
+/// 工具调用消息正文中参数摘要的最大字符数
+const ARGUMENTS_PREVIEW_CHARS: usize = 200;
+
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_DONE: &str = "done";
+pub const STATUS_ERROR: &str = "error";
+/// 回复结束（完成、失败或取消）时仍未收到结果的调用
+pub const STATUS_INTERRUPTED: &str = "interrupted";
+
+pub fn is_tool_message(msg_type: &str) -> bool {
+    msg_type == TOOL_CALL_MESSAGE_TYPE || msg_type == TOOL_RESULT_MESSAGE_TYPE
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub call_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub arguments: serde_json::Value,
+    pub status: String,
+    pub started_at: i64,
+    #[serde(default)]
+    pub finished_at: Option<i64>,
+    #[serde(default)]
+    pub duration_ms: Option<i64>,
+}
+
+impl ToolCall {
+    /// 标记调用结束并计算耗时
+    pub fn finish(&mut self, status: &str, finished_at: i64) {
+        self.status = status.to_string();
+        self.finished_at = Some(finished_at);
+        self.duration_ms = Some((finished_at - self.started_at).max(0));
+    }
+
+    /// 消息正文：工具名和参数摘要，用于检索和导出
+    pub fn summary(&self) -> String {
+        let arguments = match &self.arguments {
+            serde_json::Value::Null => String::new(),
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        format!("{}({})", self.name, truncate_middle(&arguments, ARGUMENTS_PREVIEW_CHARS).0)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolResult {
+    pub call_id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// 输出是否被 GoClaw 或本地存储截断
+    #[serde(default)]
+    pub truncated: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl ToolResult {
+    pub fn is_error(&self) -> bool {
+        self.error.is_some() || matches!(self.exit_code, Some(code) if code != 0)
+    }
+
+    /// 消息正文：stdout，有 stderr 或错误时附在后面
+    pub fn content(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.stdout.is_empty() {
+            parts.push(self.stdout.clone());
+        }
+        if !self.stderr.is_empty() {
+            parts.push(format!("[stderr]\n{}", self.stderr));
+        }
+        if let Some(error) = &self.error {
+            parts.push(format!("[error] {}", error));
+        }
+        parts.join("\n")
+    }
+}
+
+/// GoClaw 推送的工具事件
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolEvent {
+    Call {
+        call_id: String,
+        name: String,
+        arguments: serde_json::Value,
+    },
+    Result(ToolResult),
+}
+
+/// 消息复制到新会话（分支、导入）并换用新 id 后，把 metadata 中引用的旧消息 id 换成新 id，
+/// 被引用的消息没有一起复制时置空。`ids` 为旧 id 到新 id 的映射
+pub fn remap_message_refs(metadata: Option<String>, ids: &HashMap<String, String>) -> Option<String> {
+    let raw = metadata?;
+    let mut value = match serde_json::from_str::<serde_json::Value>(&raw) {
+        Ok(value @ serde_json::Value::Object(_)) => value,
+        _ => return Some(raw),
+    };
+    let mut changed = false;
+    for key in MESSAGE_REF_KEYS {
+        if let Some(old_id) = value.get(key).and_then(|v| v.as_str()).map(str::to_string) {
+            value[key] = ids
+                .get(&old_id)
+                .map(|id| serde_json::Value::String(id.clone()))
+                .unwrap_or(serde_json::Value::Null);
+            changed = true;
+        }
+    }
+    if changed {
+        Some(value.to_string())
+    } else {
+        Some(raw)
+    }
+}
+
+/// 超过 `max_chars` 时保留首尾各一半，返回 (文本, 是否截断)
+pub fn truncate_middle(text: &str, max_chars: usize) -> (String, bool) {
+    let count = text.chars().count();
+    if count <= max_chars {
+        return (text.to_string(), false);
+    }
+    let head: String = text.chars().take(max_chars / 2).collect();
+    let tail: String = text.chars().skip(count - max_chars / 2).collect();
+    (
+        format!("{}\n… [{} characters omitted] …\n{}", head, count - head.chars().count() - tail.chars().count(), tail),
+        true,
+    )
+}
+
+fn string_field(params: &serde_json::Value, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .find_map(|key| params.get(*key).and_then(|v| v.as_str()))
+        .map(|s| s.to_string())
+}
+
+/// 解析工具通知，返回 (会话 id, 事件)；不是工具通知或缺少必要字段时返回 None
+pub fn parse_notification(method: &str, params: &serde_json::Value) -> Option<(String, ToolEvent)> {
+    let session_id = string_field(params, &["session_id"])?;
+    let call_id = string_field(params, &["call_id", "id"])?;
+    let event = match method {
+        TOOL_CALL_METHOD => ToolEvent::Call {
+            call_id,
+            name: string_field(params, &["name", "tool"]).unwrap_or_else(|| "tool".to_string()),
+            arguments: params
+                .get("arguments")
+                .or_else(|| params.get("args"))
+                .cloned()
+                .unwrap_or(serde_json::Value::Null),
+        },
+        TOOL_RESULT_METHOD => {
+            let (stdout, stdout_truncated) = truncate_middle(
+                &string_field(params, &["stdout", "output"]).unwrap_or_default(),
+                MAX_TOOL_OUTPUT_CHARS,
+            );
+            let (stderr, stderr_truncated) = truncate_middle(
+                &string_field(params, &["stderr"]).unwrap_or_default(),
+                MAX_TOOL_OUTPUT_CHARS,
+            );
+            ToolEvent::Result(ToolResult {
+                call_id,
+                name: string_field(params, &["name", "tool"]).unwrap_or_default(),
+                stdout,
+                stderr,
+                exit_code: params
+                    .get("exit_code")
+                    .and_then(|v| v.as_i64())
+                    .map(|code| code as i32),
+                truncated: params.get("truncated").and_then(|v| v.as_bool()).unwrap_or(false)
+                    || stdout_truncated
+                    || stderr_truncated,
+                error: string_field(params, &["error"]).filter(|e| !e.is_empty()),
+            })
+        }
+        _ => return None,
+    };
+    Some((session_id, event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_notification() {
+        let params = serde_json::json!({
+            "session_id": "session_1",
+            "call_id": "call_1",
+            "name": "shell",
+            "arguments": { "command": "ls -la" },
+        });
+        let (session_id, event) = parse_notification(TOOL_CALL_METHOD, &params).unwrap();
+        assert_eq!(session_id, "session_1");
+        assert_eq!(
+            event,
+            ToolEvent::Call {
+                call_id: "call_1".to_string(),
+                name: "shell".to_string(),
+                arguments: serde_json::json!({ "command": "ls -la" }),
+            }
+        );
+
+        let long_output = "x".repeat(MAX_TOOL_OUTPUT_CHARS + 10);
+        let params = serde_json::json!({
+            "session_id": "session_1",
+            "call_id": "call_1",
+            "stdout": long_output,
+            "stderr": "warning",
+            "exit_code": 2,
+        });
+        let (_, event) = parse_notification(TOOL_RESULT_METHOD, &params).unwrap();
+        let result = match event {
+            ToolEvent::Result(result) => result,
+            other => panic!("unexpected event: {:?}", other),
+        };
+        assert!(result.truncated && result.is_error());
+        assert!(result.stdout.contains("[10 characters omitted]"));
+        assert!(result.content().ends_with("[stderr]\nwarning"));
+
+        assert!(parse_notification("chat.delta", &params).is_none());
+        assert!(parse_notification(TOOL_CALL_METHOD, &serde_json::json!({ "call_id": "c" })).is_none());
+    }
+
+    #[test]
+    fn test_tool_call_finish() {
+        let mut call = ToolCall {
+            call_id: "call_1".to_string(),
+            name: "read_file".to_string(),
+            arguments: serde_json::json!({ "path": "a.txt" }),
+            status: STATUS_RUNNING.to_string(),
+            started_at: 1000,
+            finished_at: None,
+            duration_ms: None,
+        };
+        assert_eq!(call.summary(), r#"read_file({"path":"a.txt"})"#);
+        call.finish(STATUS_DONE, 1250);
+        assert_eq!((call.status.as_str(), call.duration_ms), (STATUS_DONE, Some(250)));
+        assert_eq!(truncate_middle("abcdef", 4), ("ab\n… [2 characters omitted] …\nef".to_string(), true));
+    }
+
+    #[test]
+    fn test_remap_message_refs() {
+        let ids = HashMap::from([
+            ("msg_reply".to_string(), "msg_a".to_string()),
+            ("msg_call".to_string(), "msg_b".to_string()),
+        ]);
+        let metadata = serde_json::json!({ "reply_id": "msg_reply", "tool_call_message_id": "msg_call", "reply_offset": 3 });
+        let remapped: serde_json::Value =
+            serde_json::from_str(&remap_message_refs(Some(metadata.to_string()), &ids).unwrap()).unwrap();
+        assert_eq!(remapped, serde_json::json!({ "reply_id": "msg_a", "tool_call_message_id": "msg_b", "reply_offset": 3 }));
+
+        let metadata = serde_json::json!({ "reply_id": "msg_elsewhere" }).to_string();
+        assert_eq!(remap_message_refs(Some(metadata), &ids).as_deref(), Some(r#"{"reply_id":null}"#));
+        assert_eq!(remap_message_refs(Some("not json".to_string()), &ids).as_deref(), Some("not json"));
+        assert_eq!(remap_message_refs(None, &ids), None);
+    }
+}
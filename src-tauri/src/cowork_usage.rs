@@ -0,0 +1,267 @@
+use crate::cowork_context;
+use crate::cowork_prompt::ChatRequest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 价格表中匹配任意模型的兜底条目
+pub const DEFAULT_PRICE_KEY: &str = "*";
+
+/// 一次助手回复消耗的 token，记录在回复消息的 metadata.usage 中
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// GoClaw 未返回用量时按字符数估算
+    #[serde(default)]
+    pub estimated: bool,
+}
+
+fn token_field(value: &serde_json::Value, keys: &[&str]) -> Option<u64> {
+    keys.iter().find_map(|key| value.get(*key).and_then(|v| v.as_u64()))
+}
+
+/// 从 chat 响应中读取用量，兼容 prompt/completion 与 input/output 两种命名
+pub fn usage_from_response(response: &serde_json::Value) -> Option<TokenUsage> {
+    let usage = response.get("usage")?;
+    let prompt_tokens = token_field(usage, &["prompt_tokens", "input_tokens"]);
+    let completion_tokens = token_field(usage, &["completion_tokens", "output_tokens"]);
+    if prompt_tokens.is_none() && completion_tokens.is_none() {
+        return None;
+    }
+    let model = response
+        .get("model")
+        .or_else(|| usage.get("model"))
+        .and_then(|m| m.as_str())
+        .filter(|m| !m.is_empty())
+        .map(|m| m.to_string());
+    Some(TokenUsage {
+        prompt_tokens: prompt_tokens.unwrap_or(0),
+        completion_tokens: completion_tokens.unwrap_or(0),
+        model,
+        estimated: false,
+    })
+}
+
+/// 按请求内容和回复文本估算用量
+pub fn estimate_usage(request: &ChatRequest, reply: &str, model: Option<String>) -> TokenUsage {
+    let prompt_tokens = request
+        .messages
+        .iter()
+        .map(|m| cowork_context::estimate_tokens(&m.content))
+        .sum::<usize>()
+        + request
+            .system_prompt
+            .as_deref()
+            .map(cowork_context::estimate_tokens)
+            .unwrap_or(0);
+    TokenUsage {
+        prompt_tokens: prompt_tokens as u64,
+        completion_tokens: cowork_context::estimate_tokens(reply) as u64,
+        model,
+        estimated: true,
+    }
+}
+
+/// 单个模型每百万 token 的价格
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+/// 保存在 cowork 配置 usage_price_table 中的价格表，模型名为 "*" 的条目匹配未单独定价的模型
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceTable {
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    #[serde(default)]
+    pub models: HashMap<String, ModelPrice>,
+}
+
+fn default_currency() -> String {
+    "USD".to_string()
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        PriceTable {
+            currency: default_currency(),
+            models: HashMap::new(),
+        }
+    }
+}
+
+impl std::str::FromStr for PriceTable {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl PriceTable {
+    pub fn price_for(&self, model: Option<&str>) -> Option<&ModelPrice> {
+        model
+            .and_then(|m| self.models.get(m))
+            .or_else(|| self.models.get(DEFAULT_PRICE_KEY))
+    }
+
+    /// 计算费用，模型没有价格时返回 None
+    pub fn cost(&self, model: Option<&str>, prompt_tokens: u64, completion_tokens: u64) -> Option<f64> {
+        self.price_for(model).map(|price| {
+            (prompt_tokens as f64 * price.prompt + completion_tokens as f64 * price.completion) / 1_000_000.0
+        })
+    }
+}
+
+/// 一个会话或一天的用量汇总
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageSummaryRow {
+    /// 按会话汇总时为会话 id，按天汇总时为 YYYY-MM-DD
+    pub key: String,
+    /// 按会话汇总时的会话标题，会话已删除时为 None
+    pub title: Option<String>,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    pub replies: u64,
+    /// 其中按估算计入的回复数
+    pub estimated_replies: u64,
+    pub models: Vec<String>,
+    /// 已定价部分的费用
+    pub cost: f64,
+    /// 没有匹配价格的 token 数
+    pub unpriced_tokens: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageSummary {
+    pub group_by: String,
+    pub currency: String,
+    pub rows: Vec<UsageSummaryRow>,
+    pub total: UsageSummaryRow,
+}
+
+/// 数据库按 (key, model) 分组的一行用量
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageGroup {
+    pub key: String,
+    pub title: Option<String>,
+    pub model: Option<String>,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub replies: u64,
+    pub estimated_replies: u64,
+}
+
+fn add_group(row: &mut UsageSummaryRow, group: &UsageGroup, prices: &PriceTable) {
+    row.prompt_tokens += group.prompt_tokens;
+    row.completion_tokens += group.completion_tokens;
+    row.total_tokens += group.prompt_tokens + group.completion_tokens;
+    row.replies += group.replies;
+    row.estimated_replies += group.estimated_replies;
+    if let Some(model) = &group.model {
+        if !row.models.contains(model) {
+            row.models.push(model.clone());
+        }
+    }
+    match prices.cost(group.model.as_deref(), group.prompt_tokens, group.completion_tokens) {
+        Some(cost) => row.cost += cost,
+        None => row.unpriced_tokens += group.prompt_tokens + group.completion_tokens,
+    }
+}
+
+/// 把按 (key, model) 分组的用量合并为每个 key 一行并计算费用，行顺序与输入中 key 首次出现的顺序一致
+pub fn summarize(group_by: &str, groups: &[UsageGroup], prices: &PriceTable) -> UsageSummary {
+    let mut rows: Vec<UsageSummaryRow> = Vec::new();
+    let mut total = UsageSummaryRow {
+        key: "total".to_string(),
+        ..Default::default()
+    };
+    for group in groups {
+        let index = match rows.iter().position(|r| r.key == group.key) {
+            Some(index) => index,
+            None => {
+                rows.push(UsageSummaryRow {
+                    key: group.key.clone(),
+                    title: group.title.clone(),
+                    ..Default::default()
+                });
+                rows.len() - 1
+            }
+        };
+        add_group(&mut rows[index], group, prices);
+        add_group(&mut total, group, prices);
+    }
+    UsageSummary {
+        group_by: group_by.to_string(),
+        currency: prices.currency.clone(),
+        rows,
+        total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_from_response() {
+        let response = serde_json::json!({
+            "content": "hi",
+            "model": "gpt-4o",
+            "usage": { "prompt_tokens": 120, "completion_tokens": 30 }
+        });
+        assert_eq!(
+            usage_from_response(&response),
+            Some(TokenUsage {
+                prompt_tokens: 120,
+                completion_tokens: 30,
+                model: Some("gpt-4o".to_string()),
+                estimated: false,
+            })
+        );
+        let response = serde_json::json!({ "usage": { "input_tokens": 5, "output_tokens": 7, "model": "claude" } });
+        let usage = usage_from_response(&response).unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.model.as_deref()), (5, 7, Some("claude")));
+        assert!(usage_from_response(&serde_json::json!({ "content": "hi" })).is_none());
+        assert!(usage_from_response(&serde_json::json!("hi")).is_none());
+    }
+
+    #[test]
+    fn test_summarize() {
+        let prices: PriceTable = r#"{"currency": "CNY", "models": {"gpt-4o": {"prompt": 2.0, "completion": 8.0}}}"#
+            .parse()
+            .unwrap();
+        let group = |key: &str, model: Option<&str>, prompt: u64, completion: u64| UsageGroup {
+            key: key.to_string(),
+            model: model.map(|m| m.to_string()),
+            prompt_tokens: prompt,
+            completion_tokens: completion,
+            replies: 1,
+            ..Default::default()
+        };
+        let groups = vec![
+            group("2024-05-02", Some("gpt-4o"), 500_000, 100_000),
+            group("2024-05-02", Some("local"), 1000, 1000),
+            group("2024-05-01", Some("gpt-4o"), 1_000_000, 0),
+        ];
+        let summary = summarize("day", &groups, &prices);
+        assert_eq!(summary.currency, "CNY");
+        assert_eq!(summary.rows.len(), 2);
+        assert_eq!(summary.rows[0].key, "2024-05-02");
+        assert!((summary.rows[0].cost - 1.8).abs() < 1e-9);
+        assert_eq!(summary.rows[0].unpriced_tokens, 2000);
+        assert_eq!(summary.rows[0].models, vec!["gpt-4o".to_string(), "local".to_string()]);
+        assert_eq!(summary.total.replies, 3);
+        assert!((summary.total.cost - 3.8).abs() < 1e-9);
+
+        // "*" 为未单独定价的模型兜底
+        let mut prices = prices;
+        prices.models.insert(DEFAULT_PRICE_KEY.to_string(), ModelPrice { prompt: 1.0, completion: 1.0 });
+        assert_eq!(prices.cost(Some("local"), 1_000_000, 0), Some(1.0));
+        assert_eq!(PriceTable::default().cost(Some("gpt-4o"), 10, 10), None);
+    }
+}
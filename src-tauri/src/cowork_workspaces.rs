@@ -0,0 +1,166 @@
+use crate::cowork_attachments;
+use crate::cowork_documents;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// 默认在工作区根目录查找的说明文件，按顺序取第一个存在的
+pub const DEFAULT_INSTRUCTION_FILES: &str = "AGENTS.md";
+/// 注入系统提示词的说明文件最多保留的字符数
+pub const DEFAULT_INSTRUCTIONS_MAX_CHARS: usize = 20000;
+
+/// 归一化工作区路径：去掉首尾空白和末尾的路径分隔符，与工作区记忆的匹配方式一致
+pub fn normalize_workspace(path: &str) -> &str {
+    let trimmed = path.trim().trim_end_matches(['/', '\\']);
+    if trimmed.is_empty() {
+        path.trim()
+    } else {
+        trimmed
+    }
+}
+
+/// 工作区显示名称，取目录名
+pub fn workspace_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| n.to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// 解析配置中逗号分隔的说明文件名，忽略包含路径分隔符的条目
+pub fn parse_file_names(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty() && !n.contains(['/', '\\']) && *n != "..")
+        .map(|n| n.to_string())
+        .collect()
+}
+
+/// 从工作区根目录读取的说明文件
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkspaceInstructions {
+    pub workspace: String,
+    pub path: String,
+    pub content: String,
+    pub truncated: bool,
+    /// 读取时文件的修改时间（毫秒）、大小和内容哈希，用于判断文件是否变化。
+    /// 修改时间精度有限，同一毫秒内写入等长内容时只能靠哈希区分
+    pub modified_at: i64,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl WorkspaceInstructions {
+    pub fn signature(&self) -> (String, i64, u64, String) {
+        (self.path.clone(), self.modified_at, self.size, self.sha256.clone())
+    }
+
+    /// 注入系统提示词的段落
+    pub fn prompt_section(&self) -> String {
+        let file_name = Path::new(&self.path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("instructions");
+        format!(
+            "## Project instructions ({})\nThe following instructions apply to every task in this workspace.\n\n{}",
+            file_name,
+            self.content.trim()
+        )
+    }
+}
+
+/// 工作区中第一个存在的说明文件的路径
+pub fn find_instructions(workspace: &str, file_names: &[String]) -> Option<String> {
+    file_names.iter().find_map(|name| {
+        let path = Path::new(workspace).join(name);
+        path.is_file().then(|| path.to_string_lossy().to_string())
+    })
+}
+
+/// 读取说明文件，返回内容和 (路径, 修改时间, 大小, 内容哈希)
+fn read_instructions(workspace: &str, file_names: &[String]) -> Option<(Vec<u8>, (String, i64, u64, String))> {
+    let path = find_instructions(workspace, file_names)?;
+    let metadata = std::fs::metadata(&path).ok()?;
+    let modified_at = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+    let bytes = std::fs::read(&path).ok()?;
+    let sha256 = cowork_attachments::sha256_hex(&bytes);
+    let size = bytes.len() as u64;
+    Some((bytes, (path, modified_at, size, sha256)))
+}
+
+/// 工作区中第一个存在的说明文件的 (路径, 修改时间, 大小, 内容哈希)，都不存在时返回 None
+pub fn current_signature(workspace: &str, file_names: &[String]) -> Option<(String, i64, u64, String)> {
+    read_instructions(workspace, file_names).map(|(_, signature)| signature)
+}
+
+/// 读取工作区说明文件，超过 `max_chars` 时截断；文件不存在、为空或无法读取时返回 None
+pub fn load_instructions(workspace: &str, file_names: &[String], max_chars: usize) -> Option<WorkspaceInstructions> {
+    let (bytes, (path, modified_at, size, sha256)) = read_instructions(workspace, file_names)?;
+    let text = String::from_utf8_lossy(&bytes);
+    if text.trim().is_empty() {
+        return None;
+    }
+    let content = cowork_documents::truncate(&text, max_chars);
+    Some(WorkspaceInstructions {
+        workspace: workspace.to_string(),
+        path,
+        truncated: content.len() != text.len(),
+        content,
+        modified_at,
+        size,
+        sha256,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_workspace_paths() {
+        assert_eq!(normalize_workspace(" /home/me/app/ "), "/home/me/app");
+        assert_eq!(normalize_workspace("C:\\code\\app\\"), "C:\\code\\app");
+        assert_eq!(normalize_workspace("/"), "/");
+        assert_eq!(workspace_name("/home/me/app"), "app");
+        assert_eq!(
+            parse_file_names("AGENTS.md, ../secret, .github/AGENTS.md,, CONVENTIONS.md"),
+            vec!["AGENTS.md".to_string(), "CONVENTIONS.md".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_load_instructions() {
+        let temp_dir = tempdir().unwrap();
+        let workspace = temp_dir.path().to_string_lossy().to_string();
+        let names = vec!["AGENTS.md".to_string(), "CONVENTIONS.md".to_string()];
+        assert!(load_instructions(&workspace, &names, 100).is_none());
+
+        std::fs::write(temp_dir.path().join("CONVENTIONS.md"), "Use tabs.").unwrap();
+        let loaded = load_instructions(&workspace, &names, 100).unwrap();
+        assert!(loaded.path.ends_with("CONVENTIONS.md"));
+        assert!(loaded.prompt_section().starts_with("## Project instructions (CONVENTIONS.md)"));
+
+        // 靠前的文件名优先，超长内容被截断
+        std::fs::write(temp_dir.path().join("AGENTS.md"), "x".repeat(200)).unwrap();
+        let loaded = load_instructions(&workspace, &names, 100).unwrap();
+        assert!(loaded.path.ends_with("AGENTS.md") && loaded.truncated);
+        assert_eq!(current_signature(&workspace, &names), Some(loaded.signature()));
+
+        // 修改时间和大小相同时按内容哈希识别变化
+        let path = temp_dir.path().join("AGENTS.md");
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(&path, "y".repeat(200)).unwrap();
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        let signature = current_signature(&workspace, &names).unwrap();
+        assert_eq!((signature.1, signature.2), (loaded.modified_at, loaded.size));
+        assert_ne!(signature, loaded.signature());
+        std::fs::write(temp_dir.path().join("AGENTS.md"), "  \n").unwrap();
+        assert!(load_instructions(&workspace, &names, 100).is_none());
+    }
+}
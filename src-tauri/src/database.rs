@@ -1,5 +1,5 @@
 use chrono::Local;
-use rusqlite::{Connection, Result};
+use rusqlite::{Connection, OptionalExtension, Result};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::path::PathBuf;
@@ -46,6 +46,210 @@ pub struct Database {
     conn: RwLock<Connection>,
 }
 
+// cowork_sessions 查询统一使用的列，顺序需与 session_from_row 保持一致
+const SESSION_COLUMNS: &str = "id, title, status, pinned, cwd, system_prompt, execution_mode, active_skill_ids, created_at, updated_at, context_budget, parent_session_id, fork_sequence, title_source, folder_id, archived_at";
+
+fn session_from_row(row: &rusqlite::Row) -> Result<serde_json::Value> {
+    Ok(serde_json::json! ({
+        "id": row.get::<_, String>(0)?,
+        "title": row.get::<_, String>(1)?,
+        "status": row.get::<_, String>(2)?,
+        "pinned": row.get::<_, bool>(3)?,
+        "cwd": row.get::<_, Option<String>>(4)?,
+        "system_prompt": row.get::<_, Option<String>>(5)?,
+        "execution_mode": row.get::<_, Option<String>>(6)?,
+        "active_skill_ids": row.get::<_, Option<String>>(7)?,
+        "created_at": row.get::<_, i64>(8)?,
+        "updated_at": row.get::<_, i64>(9)?,
+        "context_budget": row.get::<_, Option<i64>>(10)?,
+        "parent_session_id": row.get::<_, Option<String>>(11)?,
+        "fork_sequence": row.get::<_, Option<i32>>(12)?,
+        "title_source": row.get::<_, Option<String>>(13)?,
+        "folder_id": row.get::<_, Option<String>>(14)?,
+        "archived_at": row.get::<_, Option<i64>>(15)?,
+    }))
+}
+
+// 为查询出的会话补充 tags 字段
+fn with_session_tags(conn: &Connection, mut sessions: Vec<serde_json::Value>) -> Result<Vec<serde_json::Value>> {
+    if sessions.is_empty() {
+        return Ok(sessions);
+    }
+    let ids: Vec<String> = sessions
+        .iter()
+        .map(|s| s["id"].as_str().unwrap_or_default().to_string())
+        .collect();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT session_id, tag FROM cowork_session_tags WHERE session_id IN ({}) ORDER BY tag",
+        vec!["?"; ids.len()].join(", ")
+    ))?;
+    let mut tags: std::collections::HashMap<String, Vec<String>> = std::collections::HashMap::new();
+    let rows = stmt.query_map(rusqlite::params_from_iter(&ids), |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in rows {
+        let (session_id, tag) = row?;
+        tags.entry(session_id).or_default().push(tag);
+    }
+    for (session, id) in sessions.iter_mut().zip(ids) {
+        session["tags"] = serde_json::json!(tags.remove(&id).unwrap_or_default());
+    }
+    Ok(sessions)
+}
+
+// 去掉空白标签并去重，保持原有顺序
+fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if !tag.is_empty() && !normalized.iter().any(|t| t == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+    normalized
+}
+
+// cowork_messages 查询统一使用的列，顺序需与 message_from_row 保持一致
+const MESSAGE_COLUMNS: &str = "id, session_id, type, content, timestamp, metadata, sequence";
+
+fn message_from_row(row: &rusqlite::Row) -> Result<serde_json::Value> {
+    Ok(serde_json::json!({
+        "id": row.get::<_, String>(0)?,
+        "session_id": row.get::<_, String>(1)?,
+        "type": row.get::<_, String>(2)?,
+        "content": row.get::<_, String>(3)?,
+        "timestamp": row.get::<_, i64>(4)?,
+        "metadata": row.get::<_, Option<String>>(5)?,
+        "sequence": row.get::<_, Option<i32>>(6)?,
+    }))
+}
+
+fn json_to_sql(value: &serde_json::Value) -> rusqlite::types::Value {
+    match value {
+        serde_json::Value::Null => rusqlite::types::Value::Null,
+        serde_json::Value::Bool(b) => rusqlite::types::Value::Integer(*b as i64),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => rusqlite::types::Value::Integer(i),
+            None => rusqlite::types::Value::Real(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(s) => rusqlite::types::Value::Text(s.clone()),
+        other => rusqlite::types::Value::Text(other.to_string()),
+    }
+}
+
+/// 消息全文检索的过滤条件
+#[derive(Debug, Clone, Default)]
+pub struct MessageSearchFilter<'a> {
+    pub session_id: Option<&'a str>,
+    pub message_types: &'a [String],
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+/// 分页列出会话的过滤条件
+#[derive(Debug, Clone, Default)]
+pub struct SessionListFilter<'a> {
+    pub tag: Option<&'a str>,
+    pub folder_id: Option<&'a str>,
+    pub status: Option<&'a str>,
+    /// Some(true) 只列出已归档会话，Some(false) 只列出未归档会话，None 不区分
+    pub archived: Option<bool>,
+    pub cwd: Option<&'a str>,
+    /// 工作区路径（已归一化），匹配去掉末尾路径分隔符后与之相同的 cwd
+    pub workspace: Option<&'a str>,
+    /// 按 updated_at 过滤的时间范围（毫秒）
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+/// 记忆的适用范围和过期时间
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryScope<'a> {
+    pub scope: &'a str,
+    pub scope_value: Option<&'a str>,
+    pub expires_at: Option<i64>,
+}
+
+impl MemoryScope<'static> {
+    /// 对所有会话生效、不会过期
+    pub const GLOBAL: MemoryScope<'static> = MemoryScope {
+        scope: "global",
+        scope_value: None,
+        expires_at: None,
+    };
+}
+
+/// 去掉 cwd 首尾空白和末尾路径分隔符后的工作区路径
+const WORKSPACE_EXPR: &str = "RTRIM(TRIM(cwd), '/\\')";
+
+// 子串匹配时手动截取命中位置附近的内容并高亮
+fn highlight_snippet(content: &str, terms: &[&str]) -> String {
+    const CONTEXT_CHARS: usize = 32;
+    let chars: Vec<char> = content.chars().collect();
+    let lower: Vec<char> = content.to_lowercase().chars().collect();
+    if lower.len() != chars.len() {
+        return chars.iter().take(CONTEXT_CHARS * 2).collect();
+    }
+
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for term in terms {
+        let needle: Vec<char> = term.to_lowercase().chars().collect();
+        if needle.is_empty() || needle.len() > lower.len() {
+            continue;
+        }
+        if let Some(pos) = (0..=lower.len() - needle.len()).find(|&i| lower[i..i + needle.len()] == needle[..]) {
+            ranges.push((pos, pos + needle.len()));
+        }
+    }
+    ranges.sort();
+
+    let first = ranges.first().map(|r| r.0).unwrap_or(0);
+    let start = first.saturating_sub(CONTEXT_CHARS);
+    let end = (first + CONTEXT_CHARS * 2).min(chars.len());
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut i = start;
+    while i < end {
+        if let Some(&(range_start, range_end)) = ranges.iter().find(|r| r.0 == i) {
+            snippet.push_str("<mark>");
+            snippet.extend(&chars[range_start..range_end]);
+            snippet.push_str("</mark>");
+            i = range_end;
+        } else {
+            snippet.push(chars[i]);
+            i += 1;
+        }
+    }
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+// 为已存在的旧表补充新增列
+fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(|name| name.ok())
+        .any(|name| name == column);
+    if !exists {
+        println!("[Database] Adding column {}.{}", table, column);
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
 unsafe impl Send for Database {}
 unsafe impl Sync for Database {}
 
@@ -106,6 +310,168 @@ impl Database {
             println!("[Database] Error creating cowork_sessions table: {}", e);
             e
         })?;
+        ensure_column(&conn, "cowork_sessions", "context_budget", "INTEGER")?;
+        ensure_column(&conn, "cowork_sessions", "parent_session_id", "TEXT")?;
+        ensure_column(&conn, "cowork_sessions", "fork_sequence", "INTEGER")?;
+        // 标题来源：auto 为自动生成，manual 为用户修改，NULL 为创建时的默认标题
+        ensure_column(&conn, "cowork_sessions", "title_source", "TEXT")?;
+        ensure_column(&conn, "cowork_sessions", "folder_id", "TEXT")?;
+        // 归档时间，未归档为 NULL；与 status 分开，避免被运行状态覆盖
+        ensure_column(&conn, "cowork_sessions", "archived_at", "INTEGER")?;
+        conn.execute_batch(
+            "CREATE INDEX IF NOT EXISTS idx_cowork_sessions_pinned_updated ON cowork_sessions (pinned DESC, updated_at DESC);
+            CREATE INDEX IF NOT EXISTS idx_cowork_sessions_folder_updated ON cowork_sessions (folder_id, updated_at DESC);
+            CREATE INDEX IF NOT EXISTS idx_cowork_sessions_cwd_updated ON cowork_sessions (cwd, updated_at DESC);
+            CREATE INDEX IF NOT EXISTS idx_cowork_sessions_archived_at ON cowork_sessions (archived_at);",
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork_sessions indexes: {}", e);
+            e
+        })?;
+
+        // 创建用量记录表，每条助手回复一行；会话或消息删除后仍保留，用于统计实际花费
+        println!("[Database] Creating cowork_usage table...");
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS cowork_usage (
+                message_id TEXT PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                model TEXT,
+                prompt_tokens INTEGER NOT NULL,
+                completion_tokens INTEGER NOT NULL,
+                estimated BOOLEAN DEFAULT 0,
+                day TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_cowork_usage_session ON cowork_usage (session_id, created_at);
+            CREATE INDEX IF NOT EXISTS idx_cowork_usage_day ON cowork_usage (day);",
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork_usage table: {}", e);
+            e
+        })?;
+
+        // 创建会话模板表
+        println!("[Database] Creating cowork_session_templates table...");
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS cowork_session_templates (
+                id TEXT PRIMARY KEY,
+                name TEXT UNIQUE NOT NULL,
+                cwd TEXT,
+                system_prompt TEXT,
+                execution_mode TEXT,
+                active_skill_ids TEXT,
+                context_budget INTEGER,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL
+            )",
+            [],
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork_session_templates table: {}", e);
+            e
+        })?;
+
+        // 创建会话文件夹表
+        println!("[Database] Creating cowork_folders table...");
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS cowork_folders (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL
+            )",
+            [],
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork_folders table: {}", e);
+            e
+        })?;
+
+        // 创建会话标签表
+        println!("[Database] Creating cowork_session_tags table...");
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS cowork_session_tags (
+                session_id TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (session_id, tag)
+            );
+            CREATE INDEX IF NOT EXISTS idx_cowork_session_tags_tag ON cowork_session_tags (tag, session_id);",
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork_session_tags table: {}", e);
+            e
+        })?;
+
+        // 创建工具审批规则表，session_id 为空的规则对所有会话生效
+        println!("[Database] Creating cowork_permission_rules table...");
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS cowork_permission_rules (
+                id TEXT PRIMARY KEY,
+                tool TEXT NOT NULL,
+                command_pattern TEXT,
+                path_prefix TEXT,
+                session_id TEXT,
+                decision TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_cowork_permission_rules_session ON cowork_permission_rules (session_id);",
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork_permission_rules table: {}", e);
+            e
+        })?;
+
+        // 创建工具审批审计表，会话删除后仍保留
+        println!("[Database] Creating cowork_approval_audit table...");
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS cowork_approval_audit (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                approval_id TEXT NOT NULL,
+                session_id TEXT NOT NULL,
+                tool TEXT NOT NULL,
+                command TEXT,
+                path TEXT,
+                arguments TEXT,
+                decision TEXT NOT NULL,
+                source TEXT NOT NULL,
+                rule_id TEXT,
+                requested_at INTEGER NOT NULL,
+                decided_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_cowork_approval_audit_session ON cowork_approval_audit (session_id, decided_at);
+            CREATE INDEX IF NOT EXISTS idx_cowork_approval_audit_decided ON cowork_approval_audit (decided_at);",
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork_approval_audit table: {}", e);
+            e
+        })?;
+
+        // 创建文件快照表，记录每轮回复中文件被修改前的状态，内容保存在快照存储中；
+        // after_* 为回复结束时文件的状态，撤销前据此判断文件之后是否又被改动；
+        // missing 表示文件被修改前的内容没有保存，这一轮无法撤销
+        println!("[Database] Creating cowork_file_snapshots table...");
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS cowork_file_snapshots (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id TEXT NOT NULL,
+                turn_id TEXT NOT NULL,
+                path TEXT NOT NULL,
+                existed BOOLEAN NOT NULL,
+                sha256 TEXT,
+                size INTEGER NOT NULL DEFAULT 0,
+                created_at INTEGER NOT NULL,
+                reverted_at INTEGER,
+                after_existed BOOLEAN,
+                after_sha256 TEXT,
+                missing BOOLEAN NOT NULL DEFAULT 0,
+                UNIQUE (turn_id, path)
+            );
+            CREATE INDEX IF NOT EXISTS idx_cowork_file_snapshots_session ON cowork_file_snapshots (session_id, created_at);",
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork_file_snapshots table: {}", e);
+            e
+        })?;
 
         // 创建消息表
         println!("[Database] Creating cowork_messages table...");
@@ -126,6 +492,70 @@ impl Database {
             println!("[Database] Error creating cowork_messages table: {}", e);
             e
         })?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_cowork_messages_session_sequence ON cowork_messages (session_id, sequence)",
+            [],
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork_messages index: {}", e);
+            e
+        })?;
+
+        // 创建消息全文索引（trigram 分词可同时支持中英文子串检索）
+        println!("[Database] Creating cowork_messages_fts table...");
+        let fts_exists = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'cowork_messages_fts'",
+                [],
+                |row| row.get::<_, i64>(0),
+            )?
+            > 0;
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS cowork_messages_fts USING fts5(
+                message_id UNINDEXED,
+                session_id UNINDEXED,
+                content,
+                tokenize = 'trigram'
+            );
+            CREATE TRIGGER IF NOT EXISTS cowork_messages_fts_insert AFTER INSERT ON cowork_messages BEGIN
+                INSERT INTO cowork_messages_fts (message_id, session_id, content)
+                VALUES (new.id, new.session_id, new.content);
+            END;
+            CREATE TRIGGER IF NOT EXISTS cowork_messages_fts_update AFTER UPDATE OF content ON cowork_messages BEGIN
+                UPDATE cowork_messages_fts SET content = new.content WHERE message_id = old.id;
+            END;
+            CREATE TRIGGER IF NOT EXISTS cowork_messages_fts_delete AFTER DELETE ON cowork_messages BEGIN
+                DELETE FROM cowork_messages_fts WHERE message_id = old.id;
+            END;",
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork_messages_fts table: {}", e);
+            e
+        })?;
+        if !fts_exists {
+            let count = conn.execute(
+                "INSERT INTO cowork_messages_fts (message_id, session_id, content)
+                 SELECT id, session_id, content FROM cowork_messages",
+                [],
+            )?;
+            println!("[Database] Indexed {} existing cowork messages", count);
+        }
+
+        // 创建附件表，文件本身按 sha256 存放在数据目录下，引用关系记录在消息 metadata 中
+        println!("[Database] Creating cowork_attachments table...");
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS cowork_attachments (
+                sha256 TEXT PRIMARY KEY,
+                mime_type TEXT,
+                size INTEGER NOT NULL,
+                stored_at INTEGER NOT NULL
+            )",
+            [],
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork_attachments table: {}", e);
+            e
+        })?;
 
         // 创建配置表
         println!("[Database] Creating cowork_config table...");
@@ -162,6 +592,10 @@ impl Database {
             println!("[Database] Error creating user_memories table: {}", e);
             e
         })?;
+        ensure_column(&conn, "user_memories", "kind", "TEXT")?;
+        ensure_column(&conn, "user_memories", "scope", "TEXT NOT NULL DEFAULT 'global'")?;
+        ensure_column(&conn, "user_memories", "scope_value", "TEXT")?;
+        ensure_column(&conn, "user_memories", "expires_at", "INTEGER")?;
 
         // 创建记忆来源表
         println!("[Database] Creating user_memory_sources table...");
@@ -183,6 +617,24 @@ impl Database {
             e
         })?;
 
+        // 创建记忆合并记录表
+        println!("[Database] Creating user_memory_merges table...");
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS user_memory_merges (
+                id TEXT PRIMARY KEY,
+                survivor_id TEXT NOT NULL,
+                merged_id TEXT NOT NULL,
+                merged_text TEXT NOT NULL,
+                similarity REAL NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+            [],
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating user_memory_merges table: {}", e);
+            e
+        })?;
+
         // 创建定时任务表
         println!("[Database] Creating scheduled_tasks table...");
         conn.execute(
@@ -313,57 +765,893 @@ impl Database {
         Ok(())
     }
 
-    pub fn kv_remove(&self, key: &str) -> Result<()> {
-        println!("[Database] KV remove: {}", key);
-        let conn = self.conn.write().unwrap();
-        let count = conn
-            .execute("DELETE FROM kv WHERE key = ?", [key])
+    pub fn kv_remove(&self, key: &str) -> Result<()> {
+        println!("[Database] KV remove: {}", key);
+        let conn = self.conn.write().unwrap();
+        let count = conn
+            .execute("DELETE FROM kv WHERE key = ?", [key])
+            .map_err(|e| {
+                println!("[Database] Error removing KV: {}", e);
+                e
+            })?;
+        println!("[Database] KV removed: {}, rows affected: {}", key, count);
+        Ok(())
+    }
+
+    // 会话操作
+    pub fn cowork_list_sessions(&self) -> Result<Vec<serde_json::Value>> {
+        println!("[Database] Listing cowork sessions...");
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM cowork_sessions ORDER BY pinned DESC, updated_at DESC",
+            SESSION_COLUMNS
+        )).map_err(|e| {
+            println!("[Database] Error preparing cowork_list_sessions statement: {}", e);
+            e
+        })?;
+        let rows = stmt
+            .query_map([], session_from_row)
+            .map_err(|e| {
+                println!("[Database] Error querying cowork sessions: {}", e);
+                e
+            })?;
+
+        let mut sessions = Vec::new();
+        for row in rows {
+            sessions.push(row?);
+        }
+        println!("[Database] Found {} cowork sessions", sessions.len());
+        with_session_tags(&conn, sessions)
+    }
+
+    /// 按工作区（归一化后的 cwd）汇总会话，最近有更新的在前
+    pub fn cowork_list_workspaces(&self) -> Result<Vec<serde_json::Value>> {
+        let conn = self.conn.read().unwrap();
+        let sql = format!(
+            "SELECT {expr} AS workspace, COUNT(*), SUM(archived_at IS NULL), MAX(updated_at)
+             FROM cowork_sessions
+             WHERE cwd IS NOT NULL AND {expr} != ''
+             GROUP BY workspace
+             ORDER BY MAX(updated_at) DESC",
+            expr = WORKSPACE_EXPR
+        );
+        let mut stmt = conn.prepare(&sql).map_err(|e| {
+            println!("[Database] Error preparing cowork_list_workspaces statement: {}", e);
+            e
+        })?;
+        let rows = stmt.query_map([], |row| {
+            Ok(serde_json::json!({
+                "path": row.get::<_, String>(0)?,
+                "session_count": row.get::<_, i64>(1)?,
+                "active_session_count": row.get::<_, i64>(2)?,
+                "updated_at": row.get::<_, i64>(3)?,
+            }))
+        })?;
+        let mut workspaces = Vec::new();
+        for row in rows {
+            workspaces.push(row?);
+        }
+        Ok(workspaces)
+    }
+
+    /// 按条件分页列出会话，返回 (当前页会话, 满足条件的总数)
+    pub fn cowork_list_sessions_page(&self, filter: &SessionListFilter) -> Result<(Vec<serde_json::Value>, i64)> {
+        println!("[Database] Listing cowork sessions page, filter: {:?}", filter);
+        let mut conditions = Vec::new();
+        let mut params: Vec<rusqlite::types::Value> = Vec::new();
+        if let Some(tag) = filter.tag {
+            conditions.push("id IN (SELECT session_id FROM cowork_session_tags WHERE tag = ?)");
+            params.push(tag.to_string().into());
+        }
+        if let Some(folder_id) = filter.folder_id {
+            conditions.push("folder_id = ?");
+            params.push(folder_id.to_string().into());
+        }
+        if let Some(status) = filter.status {
+            conditions.push("status = ?");
+            params.push(status.to_string().into());
+        }
+        match filter.archived {
+            Some(true) => conditions.push("archived_at IS NOT NULL"),
+            Some(false) => conditions.push("archived_at IS NULL"),
+            None => {}
+        }
+        if let Some(cwd) = filter.cwd {
+            conditions.push("cwd = ?");
+            params.push(cwd.to_string().into());
+        }
+        let workspace_condition = format!("{} = ?", WORKSPACE_EXPR);
+        if let Some(workspace) = filter.workspace {
+            conditions.push(&workspace_condition);
+            params.push(workspace.to_string().into());
+        }
+        if let Some(start_time) = filter.start_time {
+            conditions.push("updated_at >= ?");
+            params.push(start_time.into());
+        }
+        if let Some(end_time) = filter.end_time {
+            conditions.push("updated_at <= ?");
+            params.push(end_time.into());
+        }
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        let conn = self.conn.read().unwrap();
+        let total: i64 = conn
+            .query_row(
+                &format!("SELECT COUNT(*) FROM cowork_sessions {}", where_clause),
+                rusqlite::params_from_iter(&params),
+                |row| row.get(0),
+            )
+            .map_err(|e| {
+                println!("[Database] Error counting cowork sessions: {}", e);
+                e
+            })?;
+
+        params.push(i64::from(filter.limit).into());
+        params.push(i64::from(filter.offset).into());
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM cowork_sessions {} ORDER BY pinned DESC, updated_at DESC, id LIMIT ? OFFSET ?",
+                SESSION_COLUMNS, where_clause
+            ))
+            .map_err(|e| {
+                println!("[Database] Error preparing cowork_list_sessions_page statement: {}", e);
+                e
+            })?;
+        let rows = stmt
+            .query_map(rusqlite::params_from_iter(params), session_from_row)
+            .map_err(|e| {
+                println!("[Database] Error querying cowork sessions page: {}", e);
+                e
+            })?;
+
+        let mut sessions = Vec::new();
+        for row in rows {
+            sessions.push(row?);
+        }
+        println!("[Database] Found {} of {} cowork sessions", sessions.len(), total);
+        Ok((with_session_tags(&conn, sessions)?, total))
+    }
+
+    pub fn cowork_get_session(&self, id: &str) -> Result<Option<serde_json::Value>> {
+        println!("[Database] Getting cowork session: {}", id);
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM cowork_sessions WHERE id = ?",
+            SESSION_COLUMNS
+        )).map_err(|e| {
+            println!("[Database] Error preparing cowork_get_session statement: {}", e);
+            e
+        })?;
+        let session = stmt.query_row([id], session_from_row).optional()?;
+        match session {
+            Some(session) => Ok(with_session_tags(&conn, vec![session])?.pop()),
+            None => Ok(None),
+        }
+    }
+
+    /// 替换会话的全部标签，会话不存在时返回 false
+    pub fn cowork_set_session_tags(&self, id: &str, tags: &[String]) -> Result<bool> {
+        println!("[Database] Setting tags for session: {}, tags: {:?}", id, tags);
+        let mut conn = self.conn.write().unwrap();
+        let tx = conn.transaction()?;
+        let exists: bool = tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM cowork_sessions WHERE id = ?)",
+            [id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Ok(false);
+        }
+        tx.execute("DELETE FROM cowork_session_tags WHERE session_id = ?", [id])?;
+        for tag in normalize_tags(tags) {
+            tx.execute(
+                "INSERT INTO cowork_session_tags (session_id, tag) VALUES (?, ?)",
+                [id, tag.as_str()],
+            )
+            .map_err(|e| {
+                println!("[Database] Error inserting session tag: {}", e);
+                e
+            })?;
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// 所有标签及使用它的会话数，按标签名排序
+    pub fn cowork_list_tags(&self) -> Result<Vec<(String, i64)>> {
+        println!("[Database] Listing cowork session tags...");
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT t.tag, COUNT(*) FROM cowork_session_tags t
+             JOIN cowork_sessions s ON s.id = t.session_id
+             GROUP BY t.tag ORDER BY t.tag",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut tags = Vec::new();
+        for row in rows {
+            tags.push(row?);
+        }
+        Ok(tags)
+    }
+
+    /// 归档或取消归档会话，不改变 updated_at。会话不存在时返回 false
+    pub fn cowork_set_session_archived(&self, id: &str, archived: bool) -> Result<bool> {
+        println!("[Database] Setting session archived: {}, archived: {}", id, archived);
+        let conn = self.conn.write().unwrap();
+        let archived_at = if archived {
+            Some(Local::now().timestamp_millis())
+        } else {
+            None
+        };
+        let updated = conn
+            .execute(
+                "UPDATE cowork_sessions SET archived_at = ? WHERE id = ?",
+                rusqlite::params![archived_at, id],
+            )
+            .map_err(|e| {
+                println!("[Database] Error setting session archived: {}", e);
+                e
+            })?;
+        Ok(updated > 0)
+    }
+
+    /// 把会话移入文件夹，`folder_id` 为 None 时移出文件夹
+    pub fn cowork_set_session_folder(&self, id: &str, folder_id: Option<&str>) -> Result<()> {
+        println!("[Database] Moving session {} to folder {:?}", id, folder_id);
+        let conn = self.conn.write().unwrap();
+        conn.execute(
+            "UPDATE cowork_sessions SET folder_id = ? WHERE id = ?",
+            rusqlite::params![folder_id, id],
+        )
+        .map_err(|e| {
+            println!("[Database] Error moving session to folder: {}", e);
+            e
+        })?;
+        Ok(())
+    }
+
+    // 用量操作
+    /// 记录一条回复的用量，以消息 id 为键，同一条消息重复写入时保留最后一次
+    pub fn cowork_usage_record(
+        &self,
+        message_id: &str,
+        session_id: &str,
+        model: Option<&str>,
+        prompt_tokens: u64,
+        completion_tokens: u64,
+        estimated: bool,
+    ) -> Result<()> {
+        let conn = self.conn.write().unwrap();
+        let now = Local::now();
+        conn.execute(
+            "INSERT INTO cowork_usage (message_id, session_id, model, prompt_tokens, completion_tokens, estimated, day, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT(message_id) DO UPDATE SET model = excluded.model, prompt_tokens = excluded.prompt_tokens,
+                completion_tokens = excluded.completion_tokens, estimated = excluded.estimated",
+            rusqlite::params![
+                message_id,
+                session_id,
+                model,
+                prompt_tokens as i64,
+                completion_tokens as i64,
+                estimated,
+                now.format("%Y-%m-%d").to_string(),
+                now.timestamp_millis(),
+            ],
+        )
+        .map_err(|e| {
+            println!("[Database] Error recording cowork usage: {}", e);
+            e
+        })?;
+        Ok(())
+    }
+
+    /// 按会话（`by_day` 为 false）或按天汇总用量，每个 (会话或日期, 模型) 一行。
+    /// 按会话时以最近使用时间倒序排列，按天时以日期倒序排列
+    pub fn cowork_usage_groups(
+        &self,
+        by_day: bool,
+        session_id: Option<&str>,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+    ) -> Result<Vec<serde_json::Value>> {
+        println!(
+            "[Database] Summarizing cowork usage, by_day: {}, session: {:?}",
+            by_day, session_id
+        );
+        let (key, order) = if by_day {
+            ("u.day", "u.day DESC")
+        } else {
+            ("u.session_id", "MAX(MAX(u.created_at)) OVER (PARTITION BY u.session_id) DESC, u.session_id")
+        };
+        let sql = format!(
+            "SELECT {key}, s.title, u.model, SUM(u.prompt_tokens), SUM(u.completion_tokens), COUNT(*), SUM(u.estimated)
+             FROM cowork_usage u
+             LEFT JOIN cowork_sessions s ON s.id = u.session_id
+             WHERE (?1 IS NULL OR u.session_id = ?1)
+               AND (?2 IS NULL OR u.created_at >= ?2)
+               AND (?3 IS NULL OR u.created_at <= ?3)
+             GROUP BY {key}, u.model
+             ORDER BY {order}, u.model",
+            key = key,
+            order = order
+        );
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(&sql).map_err(|e| {
+            println!("[Database] Error preparing cowork_usage_groups statement: {}", e);
+            e
+        })?;
+        let rows = stmt.query_map(rusqlite::params![session_id, start_time, end_time], |row| {
+            Ok(serde_json::json!({
+                "key": row.get::<_, String>(0)?,
+                "title": if by_day { None } else { row.get::<_, Option<String>>(1)? },
+                "model": row.get::<_, Option<String>>(2)?,
+                "prompt_tokens": row.get::<_, i64>(3)?,
+                "completion_tokens": row.get::<_, i64>(4)?,
+                "replies": row.get::<_, i64>(5)?,
+                "estimated_replies": row.get::<_, i64>(6)?,
+            }))
+        })?;
+        let mut groups = Vec::new();
+        for row in rows {
+            groups.push(row?);
+        }
+        Ok(groups)
+    }
+
+    // 工具审批操作
+    pub fn cowork_add_permission_rule(
+        &self,
+        id: &str,
+        tool: &str,
+        command_pattern: Option<&str>,
+        path_prefix: Option<&str>,
+        session_id: Option<&str>,
+        decision: &str,
+    ) -> Result<()> {
+        println!(
+            "[Database] Adding cowork permission rule: {}, tool: {}, decision: {}",
+            id, tool, decision
+        );
+        let conn = self.conn.write().unwrap();
+        conn.execute(
+            "INSERT INTO cowork_permission_rules (id, tool, command_pattern, path_prefix, session_id, decision, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            rusqlite::params![
+                id,
+                tool,
+                command_pattern,
+                path_prefix,
+                session_id,
+                decision,
+                Local::now().timestamp_millis()
+            ],
+        )
+        .map_err(|e| {
+            println!("[Database] Error adding cowork permission rule: {}", e);
+            e
+        })?;
+        Ok(())
+    }
+
+    pub fn cowork_delete_permission_rule(&self, id: &str) -> Result<bool> {
+        println!("[Database] Deleting cowork permission rule: {}", id);
+        let conn = self.conn.write().unwrap();
+        let count = conn
+            .execute("DELETE FROM cowork_permission_rules WHERE id = ?", [id])
+            .map_err(|e| {
+                println!("[Database] Error deleting cowork permission rule: {}", e);
+                e
+            })?;
+        Ok(count > 0)
+    }
+
+    /// 列出审批规则；指定会话时只返回全局规则和该会话的规则
+    pub fn cowork_list_permission_rules(&self, session_id: Option<&str>) -> Result<Vec<serde_json::Value>> {
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, tool, command_pattern, path_prefix, session_id, decision, created_at
+             FROM cowork_permission_rules
+             WHERE ?1 IS NULL OR session_id IS NULL OR session_id = ?1
+             ORDER BY created_at, id",
+        )?;
+        let rows = stmt.query_map([session_id], |row| {
+            Ok(serde_json::json!({
+                "id": row.get::<_, String>(0)?,
+                "tool": row.get::<_, String>(1)?,
+                "command_pattern": row.get::<_, Option<String>>(2)?,
+                "path_prefix": row.get::<_, Option<String>>(3)?,
+                "session_id": row.get::<_, Option<String>>(4)?,
+                "decision": row.get::<_, String>(5)?,
+                "created_at": row.get::<_, i64>(6)?,
+            }))
+        })?;
+        let mut rules = Vec::new();
+        for row in rows {
+            rules.push(row?);
+        }
+        Ok(rules)
+    }
+
+    /// 记录一次审批决定
+    pub fn cowork_record_approval(
+        &self,
+        approval_id: &str,
+        session_id: &str,
+        tool: &str,
+        command: Option<&str>,
+        path: Option<&str>,
+        arguments: &str,
+        decision: &str,
+        source: &str,
+        rule_id: Option<&str>,
+        requested_at: i64,
+    ) -> Result<()> {
+        let conn = self.conn.write().unwrap();
+        conn.execute(
+            "INSERT INTO cowork_approval_audit
+                (approval_id, session_id, tool, command, path, arguments, decision, source, rule_id, requested_at, decided_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            rusqlite::params![
+                approval_id,
+                session_id,
+                tool,
+                command,
+                path,
+                arguments,
+                decision,
+                source,
+                rule_id,
+                requested_at,
+                Local::now().timestamp_millis()
+            ],
+        )
+        .map_err(|e| {
+            println!("[Database] Error recording cowork approval: {}", e);
+            e
+        })?;
+        Ok(())
+    }
+
+    /// 按决定时间倒序列出审批记录
+    pub fn cowork_list_approval_audit(
+        &self,
+        session_id: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<serde_json::Value>> {
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, approval_id, session_id, tool, command, path, arguments, decision, source, rule_id,
+                    requested_at, decided_at
+             FROM cowork_approval_audit
+             WHERE ?1 IS NULL OR session_id = ?1
+             ORDER BY decided_at DESC, id DESC
+             LIMIT ?2 OFFSET ?3",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![session_id, limit, offset], |row| {
+            let arguments = row
+                .get::<_, Option<String>>(6)?
+                .and_then(|a| serde_json::from_str::<serde_json::Value>(&a).ok())
+                .unwrap_or(serde_json::Value::Null);
+            Ok(serde_json::json!({
+                "id": row.get::<_, i64>(0)?,
+                "approval_id": row.get::<_, String>(1)?,
+                "session_id": row.get::<_, String>(2)?,
+                "tool": row.get::<_, String>(3)?,
+                "command": row.get::<_, Option<String>>(4)?,
+                "path": row.get::<_, Option<String>>(5)?,
+                "arguments": arguments,
+                "decision": row.get::<_, String>(7)?,
+                "source": row.get::<_, String>(8)?,
+                "rule_id": row.get::<_, Option<String>>(9)?,
+                "requested_at": row.get::<_, i64>(10)?,
+                "decided_at": row.get::<_, i64>(11)?,
+            }))
+        })?;
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(row?);
+        }
+        Ok(entries)
+    }
+
+    // 文件快照操作
+    pub fn cowork_has_file_snapshot(&self, turn_id: &str, path: &str) -> Result<bool> {
+        let conn = self.conn.read().unwrap();
+        let count = conn.query_row(
+            "SELECT COUNT(*) FROM cowork_file_snapshots WHERE turn_id = ? AND path = ?",
+            [turn_id, path],
+            |row| row.get::<_, i64>(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    /// 记录文件在本轮第一次被修改前的状态，同一轮中已有记录时忽略并返回 false
+    pub fn cowork_add_file_snapshot(
+        &self,
+        session_id: &str,
+        turn_id: &str,
+        path: &str,
+        existed: bool,
+        sha256: Option<&str>,
+        size: u64,
+    ) -> Result<bool> {
+        let conn = self.conn.write().unwrap();
+        let count = conn
+            .execute(
+                "INSERT OR IGNORE INTO cowork_file_snapshots (session_id, turn_id, path, existed, sha256, size, created_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                rusqlite::params![
+                    session_id,
+                    turn_id,
+                    path,
+                    existed,
+                    sha256,
+                    size as i64,
+                    Local::now().timestamp_millis()
+                ],
+            )
+            .map_err(|e| {
+                println!("[Database] Error adding cowork file snapshot: {}", e);
+                e
+            })?;
+        Ok(count > 0)
+    }
+
+    /// 记录文件在本轮被修改前的内容没有保存，同一轮中已有记录时忽略并返回 false
+    pub fn cowork_add_missing_file_snapshot(&self, session_id: &str, turn_id: &str, path: &str) -> Result<bool> {
+        let conn = self.conn.write().unwrap();
+        let count = conn
+            .execute(
+                "INSERT OR IGNORE INTO cowork_file_snapshots (session_id, turn_id, path, existed, missing, created_at)
+                 VALUES (?, ?, ?, 0, 1, ?)",
+                rusqlite::params![session_id, turn_id, path, Local::now().timestamp_millis()],
+            )
+            .map_err(|e| {
+                println!("[Database] Error adding missing cowork file snapshot: {}", e);
+                e
+            })?;
+        Ok(count > 0)
+    }
+
+    /// 列出会话（或其中一轮）的文件快照，按记录顺序排列
+    pub fn cowork_list_file_snapshots(
+        &self,
+        session_id: &str,
+        turn_id: Option<&str>,
+    ) -> Result<Vec<serde_json::Value>> {
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, session_id, turn_id, path, existed, sha256, size, created_at, reverted_at,
+                    after_existed, after_sha256, missing
+             FROM cowork_file_snapshots
+             WHERE session_id = ?1 AND (?2 IS NULL OR turn_id = ?2)
+             ORDER BY created_at, id",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![session_id, turn_id], |row| {
+            Ok(serde_json::json!({
+                "id": row.get::<_, i64>(0)?,
+                "session_id": row.get::<_, String>(1)?,
+                "turn_id": row.get::<_, String>(2)?,
+                "path": row.get::<_, String>(3)?,
+                "existed": row.get::<_, bool>(4)?,
+                "sha256": row.get::<_, Option<String>>(5)?,
+                "size": row.get::<_, i64>(6)?,
+                "created_at": row.get::<_, i64>(7)?,
+                "reverted_at": row.get::<_, Option<i64>>(8)?,
+                "after_existed": row.get::<_, Option<bool>>(9)?,
+                "after_sha256": row.get::<_, Option<String>>(10)?,
+                "missing": row.get::<_, bool>(11)?,
+            }))
+        })?;
+        let mut snapshots = Vec::new();
+        for row in rows {
+            snapshots.push(row?);
+        }
+        Ok(snapshots)
+    }
+
+    /// 记录回复结束时文件的状态
+    pub fn cowork_set_file_snapshot_after(&self, id: i64, existed: bool, sha256: Option<&str>) -> Result<bool> {
+        let conn = self.conn.write().unwrap();
+        let count = conn
+            .execute(
+                "UPDATE cowork_file_snapshots SET after_existed = ?, after_sha256 = ? WHERE id = ?",
+                rusqlite::params![existed, sha256, id],
+            )
+            .map_err(|e| {
+                println!("[Database] Error updating cowork file snapshot {}: {}", id, e);
+                e
+            })?;
+        Ok(count > 0)
+    }
+
+    pub fn cowork_mark_turn_reverted(&self, session_id: &str, turn_id: &str) -> Result<usize> {
+        let conn = self.conn.write().unwrap();
+        let count = conn
+            .execute(
+                "UPDATE cowork_file_snapshots SET reverted_at = ? WHERE session_id = ? AND turn_id = ?",
+                rusqlite::params![Local::now().timestamp_millis(), session_id, turn_id],
+            )
+            .map_err(|e| {
+                println!("[Database] Error marking turn {} reverted: {}", turn_id, e);
+                e
+            })?;
+        Ok(count)
+    }
+
+    /// 只保留会话最近 `keep_turns` 轮的快照，返回删除条数
+    pub fn cowork_prune_file_snapshots(&self, session_id: &str, keep_turns: usize) -> Result<usize> {
+        let conn = self.conn.write().unwrap();
+        let count = conn
+            .execute(
+                "DELETE FROM cowork_file_snapshots
+                 WHERE session_id = ?1 AND turn_id NOT IN (
+                     SELECT turn_id FROM cowork_file_snapshots WHERE session_id = ?1
+                     GROUP BY turn_id ORDER BY MAX(created_at) DESC, MAX(id) DESC LIMIT ?2
+                 )",
+                rusqlite::params![session_id, keep_turns as i64],
+            )
+            .map_err(|e| {
+                println!("[Database] Error pruning cowork file snapshots: {}", e);
+                e
+            })?;
+        if count > 0 {
+            println!(
+                "[Database] Pruned {} file snapshots of session {}",
+                count, session_id
+            );
+        }
+        Ok(count)
+    }
+
+    /// 仍被快照记录引用的内容哈希
+    pub fn cowork_snapshot_hashes(&self) -> Result<Vec<String>> {
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT sha256 FROM cowork_file_snapshots WHERE sha256 IS NOT NULL",
+        )?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut hashes = Vec::new();
+        for row in rows {
+            hashes.push(row?);
+        }
+        Ok(hashes)
+    }
+
+    // 会话模板操作
+    pub fn cowork_create_template(
+        &self,
+        id: &str,
+        name: &str,
+        cwd: Option<&str>,
+        system_prompt: Option<&str>,
+        execution_mode: Option<&str>,
+        active_skill_ids: Option<&str>,
+        context_budget: Option<i64>,
+    ) -> Result<()> {
+        println!("[Database] Creating cowork session template: {}, name: {}", id, name);
+        let conn = self.conn.write().unwrap();
+        let now = Local::now().timestamp_millis();
+        conn.execute(
+            "INSERT INTO cowork_session_templates
+                (id, name, cwd, system_prompt, execution_mode, active_skill_ids, context_budget, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            rusqlite::params![id, name, cwd, system_prompt, execution_mode, active_skill_ids, context_budget, now, now],
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork session template: {}", e);
+            e
+        })?;
+        Ok(())
+    }
+
+    /// 更新模板中传入的字段，名称以外的文本字段传入空字符串表示清除，返回模板是否存在
+    pub fn cowork_update_template(
+        &self,
+        id: &str,
+        name: Option<&str>,
+        cwd: Option<&str>,
+        system_prompt: Option<&str>,
+        execution_mode: Option<&str>,
+        active_skill_ids: Option<&str>,
+        context_budget: Option<i64>,
+    ) -> Result<bool> {
+        println!("[Database] Updating cowork session template: {}", id);
+        let conn = self.conn.write().unwrap();
+        let mut set_clauses = vec!["updated_at = ?"];
+        let mut params: Vec<rusqlite::types::Value> = vec![Local::now().timestamp_millis().into()];
+        if let Some(name) = name {
+            set_clauses.push("name = ?");
+            params.push(name.to_string().into());
+        }
+        for (column, value) in [
+            ("cwd = ?", cwd),
+            ("system_prompt = ?", system_prompt),
+            ("execution_mode = ?", execution_mode),
+            ("active_skill_ids = ?", active_skill_ids),
+        ] {
+            if let Some(value) = value {
+                set_clauses.push(column);
+                params.push(if value.is_empty() {
+                    rusqlite::types::Value::Null
+                } else {
+                    value.to_string().into()
+                });
+            }
+        }
+        if let Some(context_budget) = context_budget {
+            set_clauses.push("context_budget = ?");
+            // 小于等于 0 表示清除
+            params.push(if context_budget > 0 {
+                context_budget.into()
+            } else {
+                rusqlite::types::Value::Null
+            });
+        }
+        params.push(id.to_string().into());
+        let count = conn
+            .execute(
+                &format!(
+                    "UPDATE cowork_session_templates SET {} WHERE id = ?",
+                    set_clauses.join(", ")
+                ),
+                rusqlite::params_from_iter(params),
+            )
+            .map_err(|e| {
+                println!("[Database] Error updating cowork session template: {}", e);
+                e
+            })?;
+        Ok(count > 0)
+    }
+
+    pub fn cowork_delete_template(&self, id: &str) -> Result<()> {
+        println!("[Database] Deleting cowork session template: {}", id);
+        let conn = self.conn.write().unwrap();
+        conn.execute("DELETE FROM cowork_session_templates WHERE id = ?", [id])
+            .map_err(|e| {
+                println!("[Database] Error deleting cowork session template: {}", e);
+                e
+            })?;
+        Ok(())
+    }
+
+    /// 列出会话模板，按名称排序；指定 `id` 时只返回该模板
+    pub fn cowork_list_templates(&self, id: Option<&str>) -> Result<Vec<serde_json::Value>> {
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, name, cwd, system_prompt, execution_mode, active_skill_ids, context_budget, created_at, updated_at
+             FROM cowork_session_templates
+             WHERE ?1 IS NULL OR id = ?1
+             ORDER BY name COLLATE NOCASE",
+        )?;
+        let rows = stmt.query_map([id], |row| {
+            Ok(serde_json::json!({
+                "id": row.get::<_, String>(0)?,
+                "name": row.get::<_, String>(1)?,
+                "cwd": row.get::<_, Option<String>>(2)?,
+                "system_prompt": row.get::<_, Option<String>>(3)?,
+                "execution_mode": row.get::<_, Option<String>>(4)?,
+                "active_skill_ids": row.get::<_, Option<String>>(5)?,
+                "context_budget": row.get::<_, Option<i64>>(6)?,
+                "created_at": row.get::<_, i64>(7)?,
+                "updated_at": row.get::<_, i64>(8)?,
+            }))
+        })?;
+        let mut templates = Vec::new();
+        for row in rows {
+            templates.push(row?);
+        }
+        Ok(templates)
+    }
+
+    // 文件夹操作
+    pub fn cowork_create_folder(&self, id: &str, name: &str) -> Result<()> {
+        println!("[Database] Creating cowork folder: {}, name: {}", id, name);
+        let conn = self.conn.write().unwrap();
+        let now = Local::now().timestamp_millis();
+        conn.execute(
+            "INSERT INTO cowork_folders (id, name, created_at, updated_at) VALUES (?, ?, ?, ?)",
+            rusqlite::params![id, name, now, now],
+        )
+        .map_err(|e| {
+            println!("[Database] Error creating cowork folder: {}", e);
+            e
+        })?;
+        Ok(())
+    }
+
+    pub fn cowork_rename_folder(&self, id: &str, name: &str) -> Result<bool> {
+        println!("[Database] Renaming cowork folder: {}, name: {}", id, name);
+        let conn = self.conn.write().unwrap();
+        let now = Local::now().timestamp_millis();
+        let count = conn
+            .execute(
+                "UPDATE cowork_folders SET name = ?, updated_at = ? WHERE id = ?",
+                rusqlite::params![name, now, id],
+            )
+            .map_err(|e| {
+                println!("[Database] Error renaming cowork folder: {}", e);
+                e
+            })?;
+        Ok(count > 0)
+    }
+
+    /// 删除文件夹，其中的会话移出文件夹而不会被删除
+    pub fn cowork_delete_folder(&self, id: &str) -> Result<()> {
+        println!("[Database] Deleting cowork folder: {}", id);
+        let mut conn = self.conn.write().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute("UPDATE cowork_sessions SET folder_id = NULL WHERE folder_id = ?", [id])?;
+        tx.execute("DELETE FROM cowork_folders WHERE id = ?", [id])
             .map_err(|e| {
-                println!("[Database] Error removing KV: {}", e);
+                println!("[Database] Error deleting cowork folder: {}", e);
                 e
             })?;
-        println!("[Database] KV removed: {}, rows affected: {}", key, count);
+        tx.commit()?;
         Ok(())
     }
 
-    // 会话操作
-    pub fn cowork_list_sessions(&self) -> Result<Vec<serde_json::Value>> {
-        println!("[Database] Listing cowork sessions...");
+    /// 列出文件夹及其中的会话数，按名称排序
+    pub fn cowork_list_folders(&self) -> Result<Vec<serde_json::Value>> {
+        println!("[Database] Listing cowork folders...");
         let conn = self.conn.read().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, title, status, pinned, cwd, system_prompt, execution_mode, active_skill_ids, created_at, updated_at 
-             FROM cowork_sessions 
-             ORDER BY pinned DESC, updated_at DESC"
-        ).map_err(|e| {
-            println!("[Database] Error preparing cowork_list_sessions statement: {}", e);
+            "SELECT f.id, f.name, f.created_at, f.updated_at,
+                    (SELECT COUNT(*) FROM cowork_sessions s WHERE s.folder_id = f.id)
+             FROM cowork_folders f ORDER BY f.name COLLATE NOCASE, f.created_at",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(serde_json::json!({
+                "id": row.get::<_, String>(0)?,
+                "name": row.get::<_, String>(1)?,
+                "created_at": row.get::<_, i64>(2)?,
+                "updated_at": row.get::<_, i64>(3)?,
+                "session_count": row.get::<_, i64>(4)?,
+            }))
+        })?;
+        let mut folders = Vec::new();
+        for row in rows {
+            folders.push(row?);
+        }
+        Ok(folders)
+    }
+
+    pub fn cowork_set_session_context_budget(&self, id: &str, budget: Option<i64>) -> Result<()> {
+        println!("[Database] Setting context budget for session: {}, budget: {:?}", id, budget);
+        let conn = self.conn.write().unwrap();
+        conn.execute(
+            "UPDATE cowork_sessions SET context_budget = ? WHERE id = ?",
+            rusqlite::params![budget, id],
+        )
+        .map_err(|e| {
+            println!("[Database] Error setting session context budget: {}", e);
             e
         })?;
-        let rows = stmt
-            .query_map([], |row| {
-                Ok(serde_json::json! ({
-                    "id": row.get::<_, String>(0)?,
-                    "title": row.get::<_, String>(1)?,
-                    "status": row.get::<_, String>(2)?,
-                    "pinned": row.get::<_, bool>(3)?,
-                    "cwd": row.get::<_, Option<String>>(4)?,
-                    "system_prompt": row.get::<_, Option<String>>(5)?,
-                    "execution_mode": row.get::<_, Option<String>>(6)?,
-                    "active_skill_ids": row.get::<_, Option<String>>(7)?,
-                    "created_at": row.get::<_, i64>(8)?,
-                    "updated_at": row.get::<_, i64>(9)?,
-                }))
-            })
+        Ok(())
+    }
+
+    /// 写入自动生成的标题，仅当标题仍是创建时的默认标题时生效，返回是否写入
+    pub fn cowork_set_auto_title(&self, id: &str, title: &str) -> Result<bool> {
+        println!("[Database] Setting auto title for session: {}, title: {}", id, title);
+        let conn = self.conn.write().unwrap();
+        let now = Local::now().timestamp_millis();
+        let count = conn
+            .execute(
+                "UPDATE cowork_sessions SET title = ?, title_source = 'auto', updated_at = ? WHERE id = ? AND title_source IS NULL",
+                rusqlite::params![title, now, id],
+            )
             .map_err(|e| {
-                println!("[Database] Error querying cowork sessions: {}", e);
+                println!("[Database] Error setting auto title: {}", e);
                 e
             })?;
-
-        let mut sessions = Vec::new();
-        for row in rows {
-            sessions.push(row?);
-        }
-        println!("[Database] Found {} cowork sessions", sessions.len());
-        Ok(sessions)
+        Ok(count > 0)
     }
 
     pub fn cowork_create_session(
@@ -400,15 +1688,151 @@ impl Database {
         Ok(())
     }
 
-    pub fn cowork_delete_session(&self, id: &str) -> Result<()> {
-        println!("[Database] Deleting cowork session: {}", id);
+    /// 用模板中的设置创建会话，`title`、`cwd` 为 None 时使用模板的名称和工作目录。
+    /// 模板不存在时返回 false
+    pub fn cowork_create_session_from_template(
+        &self,
+        id: &str,
+        template_id: &str,
+        title: Option<&str>,
+        cwd: Option<&str>,
+    ) -> Result<bool> {
+        println!(
+            "[Database] Creating cowork session {} from template {}",
+            id, template_id
+        );
         let conn = self.conn.write().unwrap();
+        let now = Local::now().timestamp_millis();
         let count = conn
-            .execute("DELETE FROM cowork_sessions WHERE id = ?", [id])
+            .execute(
+                "INSERT INTO cowork_sessions
+                    (id, title, status, cwd, system_prompt, execution_mode, active_skill_ids, context_budget, created_at, updated_at)
+                 SELECT ?, COALESCE(?, name), 'idle', COALESCE(?, cwd, ''), COALESCE(system_prompt, ''),
+                        COALESCE(execution_mode, 'local'), active_skill_ids, context_budget, ?, ?
+                 FROM cowork_session_templates WHERE id = ?",
+                rusqlite::params![id, title, cwd, now, now, template_id],
+            )
+            .map_err(|e| {
+                println!("[Database] Error creating cowork session from template: {}", e);
+                e
+            })?;
+        Ok(count > 0)
+    }
+
+    /// 在一个事务中写入完整会话及其消息，保留传入的 id、时间戳、sequence 和 metadata，
+    /// 用于导入等需要原样还原历史的场景
+    pub fn cowork_insert_session_with_messages(
+        &self,
+        session: &serde_json::Value,
+        messages: &[serde_json::Value],
+        attachments: &[(String, String, u64)],
+    ) -> Result<()> {
+        let session_id = session["id"].as_str().unwrap_or_default();
+        println!(
+            "[Database] Inserting cowork session {} with {} messages",
+            session_id,
+            messages.len()
+        );
+        let mut conn = self.conn.write().unwrap();
+        let now = Local::now().timestamp_millis();
+        let tx = conn.transaction()?;
+
+        // 按 SESSION_COLUMNS 逐列取值，新增列无需修改这里
+        let columns: Vec<&str> = SESSION_COLUMNS.split(',').map(str::trim).collect();
+        let values: Vec<rusqlite::types::Value> = columns
+            .iter()
+            .map(|column| match (*column, &session[*column]) {
+                // 来自其他环境的文件夹不存在时不保留
+                ("folder_id", serde_json::Value::String(folder_id)) => tx
+                    .query_row("SELECT id FROM cowork_folders WHERE id = ?", [folder_id], |row| {
+                        row.get::<_, String>(0)
+                    })
+                    .ok()
+                    .map(rusqlite::types::Value::Text)
+                    .unwrap_or(rusqlite::types::Value::Null),
+                ("status", serde_json::Value::Null) => "idle".to_string().into(),
+                ("pinned", serde_json::Value::Null) => 0i64.into(),
+                ("created_at" | "updated_at", serde_json::Value::Null) => now.into(),
+                (_, value) => json_to_sql(value),
+            })
+            .collect();
+        tx.execute(
+            &format!(
+                "INSERT INTO cowork_sessions ({}) VALUES ({})",
+                SESSION_COLUMNS,
+                vec!["?"; columns.len()].join(", ")
+            ),
+            rusqlite::params_from_iter(values),
+        )
+        .map_err(|e| {
+            println!("[Database] Error inserting cowork session: {}", e);
+            e
+        })?;
+
+        let tags: Vec<String> = session["tags"]
+            .as_array()
+            .map(|tags| tags.iter().filter_map(|t| t.as_str().map(str::to_string)).collect())
+            .unwrap_or_default();
+        for tag in normalize_tags(&tags) {
+            tx.execute(
+                "INSERT INTO cowork_session_tags (session_id, tag) VALUES (?, ?)",
+                [session_id, tag.as_str()],
+            )?;
+        }
+
+        for message in messages {
+            tx.execute(
+                "INSERT INTO cowork_messages (id, session_id, type, content, timestamp, metadata, sequence)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                rusqlite::params![
+                    message["id"].as_str().unwrap_or_default(),
+                    session_id,
+                    message["type"].as_str().unwrap_or("user"),
+                    message["content"].as_str().unwrap_or_default(),
+                    message["timestamp"].as_i64().unwrap_or(now),
+                    message["metadata"].as_str(),
+                    message["sequence"].as_i64(),
+                ],
+            )
+            .map_err(|e| {
+                println!("[Database] Error inserting cowork message: {}", e);
+                e
+            })?;
+        }
+
+        // 随消息一起登记导入的附件，使其类型可查并参与回收
+        for (sha256, mime_type, size) in attachments {
+            tx.execute(
+                "INSERT INTO cowork_attachments (sha256, mime_type, size, stored_at) VALUES (?, ?, ?, ?)
+                 ON CONFLICT(sha256) DO UPDATE SET stored_at = excluded.stored_at",
+                rusqlite::params![sha256, mime_type, *size as i64, now],
+            )?;
+        }
+
+        tx.commit()?;
+        println!("[Database] Cowork session inserted successfully: {}", session_id);
+        Ok(())
+    }
+
+    pub fn cowork_delete_session(&self, id: &str) -> Result<()> {
+        println!("[Database] Deleting cowork session: {}", id);
+        let mut conn = self.conn.write().unwrap();
+        // 未开启外键约束，需要显式删除消息，同时触发全文索引清理；
+        // 所有删除在同一事务中完成，失败时不会留下缺少消息或标签的会话
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM cowork_messages WHERE session_id = ?", [id])
             .map_err(|e| {
-                println!("[Database] Error deleting cowork session: {}", e);
+                println!("[Database] Error deleting cowork session messages: {}", e);
                 e
             })?;
+        tx.execute("DELETE FROM cowork_session_tags WHERE session_id = ?", [id])?;
+        tx.execute("DELETE FROM cowork_permission_rules WHERE session_id = ?", [id])?;
+        tx.execute("DELETE FROM cowork_file_snapshots WHERE session_id = ?", [id])?;
+        let count = tx.execute("DELETE FROM cowork_sessions WHERE id = ?", [id])?;
+        tx.commit().map_err(|e| {
+            println!("[Database] Error deleting cowork session: {}", e);
+            e
+        })?;
         println!(
             "[Database] Cowork session deleted: {}, rows affected: {}",
             id, count
@@ -425,6 +1849,7 @@ impl Database {
         cwd: Option<&str>,
         system_prompt: Option<&str>,
         execution_mode: Option<&str>,
+        active_skill_ids: Option<&str>,
     ) -> Result<()> {
         println!("[Database] Updating cowork session: {}", id);
         let conn = self.conn.write().unwrap();
@@ -435,6 +1860,8 @@ impl Database {
         if let Some(title) = title {
             set_clauses.push("title = ?");
             params.push(title.to_string());
+            // 用户修改过的标题不再被自动标题覆盖
+            set_clauses.push("title_source = 'manual'");
         }
         if let Some(pinned) = pinned {
             set_clauses.push("pinned = ?");
@@ -456,6 +1883,10 @@ impl Database {
             set_clauses.push("execution_mode = ?");
             params.push(execution_mode.to_string());
         }
+        if let Some(active_skill_ids) = active_skill_ids {
+            set_clauses.push("active_skill_ids = ?");
+            params.push(active_skill_ids.to_string());
+        }
 
         if set_clauses.is_empty() {
             println!("[Database] No updates needed for session: {}", id);
@@ -482,15 +1913,12 @@ impl Database {
 
     // 消息操作
     pub fn cowork_list_messages(&self, session_id: &str) -> Result<Vec<serde_json::Value>> {
-        println!("[Database] Listing messages for session: {}", session_id);
         let conn = self.conn.read().unwrap();
         let mut stmt = conn
-            .prepare(
-                "SELECT id, type, content, timestamp, metadata 
-             FROM cowork_messages 
-             WHERE session_id = ? 
-             ORDER BY sequence ASC, timestamp ASC",
-            )
+            .prepare(&format!(
+                "SELECT {} FROM cowork_messages WHERE session_id = ? ORDER BY sequence ASC, timestamp ASC",
+                MESSAGE_COLUMNS
+            ))
             .map_err(|e| {
                 println!(
                     "[Database] Error preparing cowork_list_messages statement: {}",
@@ -499,15 +1927,7 @@ impl Database {
                 e
             })?;
         let rows = stmt
-            .query_map([session_id], |row| {
-                Ok(serde_json::json! ({
-                    "id": row.get::<_, String>(0)?,
-                    "type": row.get::<_, String>(1)?,
-                    "content": row.get::<_, String>(2)?,
-                    "timestamp": row.get::<_, i64>(3)?,
-                    "metadata": row.get::<_, Option<String>>(4)?,
-                }))
-            })
+            .query_map([session_id], message_from_row)
             .map_err(|e| {
                 println!("[Database] Error querying cowork messages: {}", e);
                 e
@@ -517,14 +1937,64 @@ impl Database {
         for row in rows {
             messages.push(row?);
         }
-        println!(
-            "[Database] Found {} messages for session: {}",
-            messages.len(),
-            session_id
-        );
         Ok(messages)
     }
 
+    /// 按 sequence 游标分页读取消息，结果总是按 sequence 升序排列。
+    /// 指定 `before` 时向前翻页（取紧邻其前的 `limit` 条），只指定 `after` 时向后翻页，
+    /// 都不指定时返回最新的 `limit` 条；第二个返回值表示翻页方向上是否还有更多消息
+    pub fn cowork_list_messages_page(
+        &self,
+        session_id: &str,
+        before: Option<i32>,
+        after: Option<i32>,
+        limit: u32,
+    ) -> Result<(Vec<serde_json::Value>, bool)> {
+        let mut conditions = vec!["session_id = ?"];
+        let mut params: Vec<rusqlite::types::Value> = vec![session_id.to_string().into()];
+        if let Some(before) = before {
+            conditions.push("sequence < ?");
+            params.push(i64::from(before).into());
+        }
+        if let Some(after) = after {
+            conditions.push("sequence > ?");
+            params.push(i64::from(after).into());
+        }
+        let backward = before.is_some() || after.is_none();
+        // 多取一条用于判断是否还有更多
+        params.push((i64::from(limit) + 1).into());
+
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM cowork_messages WHERE {} ORDER BY sequence {} LIMIT ?",
+                MESSAGE_COLUMNS,
+                conditions.join(" AND "),
+                if backward { "DESC" } else { "ASC" }
+            ))
+            .map_err(|e| {
+                println!("[Database] Error preparing cowork_list_messages_page statement: {}", e);
+                e
+            })?;
+        let rows = stmt
+            .query_map(rusqlite::params_from_iter(params), message_from_row)
+            .map_err(|e| {
+                println!("[Database] Error querying cowork messages page: {}", e);
+                e
+            })?;
+
+        let mut messages = Vec::new();
+        for row in rows {
+            messages.push(row?);
+        }
+        let has_more = messages.len() > limit as usize;
+        messages.truncate(limit as usize);
+        if backward {
+            messages.reverse();
+        }
+        Ok((messages, has_more))
+    }
+
     pub fn cowork_add_message(
         &self,
         id: &str,
@@ -590,6 +2060,179 @@ impl Database {
         Ok(())
     }
 
+    pub fn cowork_delete_message(&self, id: &str, session_id: &str) -> Result<bool> {
+        println!("[Database] Deleting message: {}, session: {}", id, session_id);
+        let conn = self.conn.write().unwrap();
+        let count = conn
+            .execute(
+                "DELETE FROM cowork_messages WHERE id = ? AND session_id = ?",
+                [id, session_id],
+            )
+            .map_err(|e| {
+                println!("[Database] Error deleting message: {}", e);
+                e
+            })?;
+        Ok(count > 0)
+    }
+
+    /// 重新生成回复：在同一事务中删除原回复及该轮的工具消息，并在末尾插入新的空回复
+    pub fn cowork_replace_reply(
+        &self,
+        session_id: &str,
+        delete_ids: &[&str],
+        new_id: &str,
+        metadata: &str,
+    ) -> Result<i32> {
+        println!(
+            "[Database] Replacing reply in session {}: deleting {} messages, new reply {}",
+            session_id,
+            delete_ids.len(),
+            new_id
+        );
+        let mut conn = self.conn.write().unwrap();
+        let now = Local::now().timestamp_millis();
+        let tx = conn.transaction()?;
+        for id in delete_ids {
+            tx.execute(
+                "DELETE FROM cowork_messages WHERE id = ? AND session_id = ?",
+                [id, &session_id],
+            )?;
+        }
+        let sequence: i32 = tx.query_row(
+            "SELECT COALESCE(MAX(sequence), 0) + 1 FROM cowork_messages WHERE session_id = ?",
+            [session_id],
+            |row| row.get(0),
+        )?;
+        tx.execute(
+            "INSERT INTO cowork_messages (id, session_id, type, content, timestamp, metadata, sequence)
+             VALUES (?, ?, 'assistant', '', ?, ?, ?)",
+            rusqlite::params![new_id, session_id, now, metadata, sequence],
+        )?;
+        tx.execute(
+            "UPDATE cowork_sessions SET updated_at = ? WHERE id = ?",
+            rusqlite::params![now, session_id],
+        )?;
+        tx.commit().map_err(|e| {
+            println!("[Database] Error replacing reply: {}", e);
+            e
+        })?;
+        Ok(sequence)
+    }
+
+    /// 编辑消息：在同一事务中删除其后的所有消息并更新该消息，返回删除条数
+    pub fn cowork_edit_message_truncating(
+        &self,
+        id: &str,
+        session_id: &str,
+        sequence: i32,
+        content: &str,
+        metadata: &str,
+    ) -> Result<usize> {
+        let mut conn = self.conn.write().unwrap();
+        let tx = conn.transaction()?;
+        let removed = tx.execute(
+            "DELETE FROM cowork_messages WHERE session_id = ? AND sequence > ?",
+            rusqlite::params![session_id, sequence],
+        )?;
+        tx.execute(
+            "UPDATE cowork_messages SET content = ?, metadata = ? WHERE id = ? AND session_id = ?",
+            [content, metadata, id, session_id],
+        )?;
+        tx.execute(
+            "UPDATE cowork_sessions SET updated_at = ? WHERE id = ?",
+            rusqlite::params![Local::now().timestamp_millis(), session_id],
+        )?;
+        tx.commit().map_err(|e| {
+            println!("[Database] Error editing message {}: {}", id, e);
+            e
+        })?;
+        Ok(removed)
+    }
+
+    /// 记录一个已写入存储的附件；重复上传时刷新 stored_at，使其重新获得回收宽限期
+    pub fn cowork_attachment_upsert(&self, sha256: &str, mime_type: &str, size: u64) -> Result<()> {
+        println!("[Database] Storing attachment: {}, size: {}", sha256, size);
+        let conn = self.conn.write().unwrap();
+        let now = Local::now().timestamp_millis();
+        conn.execute(
+            "INSERT INTO cowork_attachments (sha256, mime_type, size, stored_at) VALUES (?, ?, ?, ?)
+             ON CONFLICT(sha256) DO UPDATE SET stored_at = excluded.stored_at",
+            rusqlite::params![sha256, mime_type, size as i64, now],
+        )
+        .map_err(|e| {
+            println!("[Database] Error storing attachment: {}", e);
+            e
+        })?;
+        Ok(())
+    }
+
+    /// 列出在 `stored_before` 之前存入、且没有任何消息引用的附件（含图片缩略图）
+    pub fn cowork_attachments_unreferenced(&self, stored_before: i64) -> Result<Vec<String>> {
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT sha256 FROM cowork_attachments
+             WHERE stored_at < ?
+               AND sha256 NOT IN (
+                   SELECT r.sha256 FROM (
+                       SELECT json_extract(a.value, '$.sha256') AS sha256
+                       FROM cowork_messages m,
+                            json_each(CASE WHEN json_valid(m.metadata) THEN m.metadata ELSE '{}' END, '$.attachments') a
+                       WHERE m.metadata LIKE '%attachments%'
+                       UNION
+                       SELECT json_extract(a.value, '$.thumbnail_sha256') AS sha256
+                       FROM cowork_messages m,
+                            json_each(CASE WHEN json_valid(m.metadata) THEN m.metadata ELSE '{}' END, '$.attachments') a
+                       WHERE m.metadata LIKE '%thumbnail_sha256%'
+                   ) r
+                   WHERE r.sha256 IS NOT NULL
+               )",
+        )?;
+        let rows = stmt.query_map([stored_before], |row| row.get::<_, String>(0))?;
+        rows.collect()
+    }
+
+    pub fn cowork_attachment_mime_type(&self, sha256: &str) -> Result<Option<String>> {
+        let conn = self.conn.read().unwrap();
+        let mime_type = conn
+            .query_row(
+                "SELECT mime_type FROM cowork_attachments WHERE sha256 = ?",
+                [sha256],
+                |row| row.get::<_, Option<String>>(0),
+            )
+            .optional()?
+            .flatten();
+        Ok(mime_type)
+    }
+
+    /// 每个会话最近一张图片的缩略图哈希，用于会话列表展示
+    pub fn cowork_session_thumbnails(&self) -> Result<Vec<(String, String)>> {
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT m.session_id, json_extract(a.value, '$.thumbnail_sha256')
+             FROM cowork_messages m,
+                  json_each(CASE WHEN json_valid(m.metadata) THEN m.metadata ELSE '{}' END, '$.attachments') a
+             WHERE m.metadata LIKE '%thumbnail_sha256%'
+               AND json_extract(a.value, '$.thumbnail_sha256') IS NOT NULL
+             ORDER BY m.session_id, m.sequence DESC, a.key DESC",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        let mut thumbnails: Vec<(String, String)> = Vec::new();
+        for row in rows {
+            let (session_id, sha256) = row?;
+            if thumbnails.last().map(|(last, _)| last != &session_id).unwrap_or(true) {
+                thumbnails.push((session_id, sha256));
+            }
+        }
+        Ok(thumbnails)
+    }
+
+    pub fn cowork_attachment_delete(&self, sha256: &str) -> Result<bool> {
+        println!("[Database] Deleting attachment: {}", sha256);
+        let conn = self.conn.write().unwrap();
+        let count = conn.execute("DELETE FROM cowork_attachments WHERE sha256 = ?", [sha256])?;
+        Ok(count > 0)
+    }
+
     pub fn cowork_update_message(
         &self,
         id: &str,
@@ -647,6 +2290,122 @@ impl Database {
         Ok(())
     }
 
+    /// 全文检索消息，结果按相关度排序并带高亮片段
+    pub fn cowork_search_messages(
+        &self,
+        query: &str,
+        filter: &MessageSearchFilter,
+    ) -> Result<Vec<serde_json::Value>> {
+        println!("[Database] Searching cowork messages: {}, filter: {:?}", query, filter);
+        let terms: Vec<&str> = query.split_whitespace().collect();
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // trigram 无法匹配少于 3 个字符的词，此时退化为子串匹配
+        let use_fts = terms.iter().all(|t| t.chars().count() >= 3);
+
+        let mut conditions = Vec::new();
+        let mut params: Vec<rusqlite::types::Value> = Vec::new();
+        if use_fts {
+            conditions.push("cowork_messages_fts MATCH ?".to_string());
+            params.push(
+                terms
+                    .iter()
+                    .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .into(),
+            );
+        } else {
+            for term in &terms {
+                conditions.push("instr(lower(m.content), lower(?)) > 0".to_string());
+                params.push(term.to_string().into());
+            }
+        }
+        if let Some(session_id) = filter.session_id {
+            conditions.push("m.session_id = ?".to_string());
+            params.push(session_id.to_string().into());
+        }
+        if !filter.message_types.is_empty() {
+            conditions.push(format!(
+                "m.type IN ({})",
+                vec!["?"; filter.message_types.len()].join(", ")
+            ));
+            for msg_type in filter.message_types {
+                params.push(msg_type.clone().into());
+            }
+        }
+        if let Some(start_time) = filter.start_time {
+            conditions.push("m.timestamp >= ?".to_string());
+            params.push(start_time.into());
+        }
+        if let Some(end_time) = filter.end_time {
+            conditions.push("m.timestamp <= ?".to_string());
+            params.push(end_time.into());
+        }
+        params.push(i64::from(filter.limit).into());
+        params.push(i64::from(filter.offset).into());
+
+        let (select, from, order) = if use_fts {
+            (
+                "snippet(cowork_messages_fts, 2, '<mark>', '</mark>', '…', 16), bm25(cowork_messages_fts)",
+                "cowork_messages_fts JOIN cowork_messages m ON m.id = cowork_messages_fts.message_id",
+                "ORDER BY bm25(cowork_messages_fts) ASC, m.timestamp DESC",
+            )
+        } else {
+            ("m.content, 0.0", "cowork_messages m", "ORDER BY m.timestamp DESC")
+        };
+        let sql = format!(
+            "SELECT m.id, m.session_id, s.title, m.type, m.sequence, m.timestamp, {} 
+             FROM {} 
+             JOIN cowork_sessions s ON s.id = m.session_id
+             WHERE {} 
+             {} 
+             LIMIT ? OFFSET ?",
+            select,
+            from,
+            conditions.join(" AND "),
+            order
+        );
+
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(&sql).map_err(|e| {
+            println!("[Database] Error preparing cowork_search_messages statement: {}", e);
+            e
+        })?;
+        let rows = stmt
+            .query_map(rusqlite::params_from_iter(params), |row| {
+                let text = row.get::<_, String>(6)?;
+                let snippet = if use_fts {
+                    text
+                } else {
+                    highlight_snippet(&text, &terms)
+                };
+                Ok(serde_json::json!({
+                    "message_id": row.get::<_, String>(0)?,
+                    "session_id": row.get::<_, String>(1)?,
+                    "session_title": row.get::<_, String>(2)?,
+                    "type": row.get::<_, String>(3)?,
+                    "sequence": row.get::<_, Option<i32>>(4)?,
+                    "timestamp": row.get::<_, i64>(5)?,
+                    "snippet": snippet,
+                    "rank": row.get::<_, f64>(7)?,
+                }))
+            })
+            .map_err(|e| {
+                println!("[Database] Error searching cowork messages: {}", e);
+                e
+            })?;
+
+        let mut hits = Vec::new();
+        for row in rows {
+            hits.push(row?);
+        }
+        println!("[Database] Found {} matching cowork messages", hits.len());
+        Ok(hits)
+    }
+
     // Cowork Config 操作
     pub fn cowork_config_get(&self, key: &str) -> Result<Option<String>> {
         println!("[Database] Getting cowork config: {}", key);
@@ -709,7 +2468,7 @@ impl Database {
         println!("[Database] Listing user memories...");
         let conn = self.conn.read().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, text, confidence, is_explicit, status, created_at, updated_at, last_used_at 
+            "SELECT id, text, confidence, is_explicit, status, created_at, updated_at, last_used_at, kind, scope, scope_value, expires_at 
              FROM user_memories 
              ORDER BY updated_at DESC"
         ).map_err(|e| {
@@ -727,6 +2486,10 @@ impl Database {
                     "created_at": row.get::<_, i64>(5)?,
                     "updated_at": row.get::<_, i64>(6)?,
                     "last_used_at": row.get::<_, Option<i64>>(7)?,
+                    "kind": row.get::<_, Option<String>>(8)?,
+                    "scope": row.get::<_, String>(9)?,
+                    "scope_value": row.get::<_, Option<String>>(10)?,
+                    "expires_at": row.get::<_, Option<i64>>(11)?,
                 }))
             })
             .map_err(|e| {
@@ -742,32 +2505,148 @@ impl Database {
         Ok(memories)
     }
 
-    pub fn user_memory_create(
+    pub fn user_memory_create(
+        &self,
+        id: &str,
+        text: &str,
+        confidence: f64,
+        is_explicit: bool,
+    ) -> Result<()> {
+        self.user_memory_create_with_scope(id, text, confidence, is_explicit, "created", &MemoryScope::GLOBAL)
+    }
+
+    /// 写入一条记忆，状态、适用范围和过期时间随同一条记录插入
+    pub fn user_memory_create_with_scope(
+        &self,
+        id: &str,
+        text: &str,
+        confidence: f64,
+        is_explicit: bool,
+        status: &str,
+        scope: &MemoryScope,
+    ) -> Result<()> {
+        println!(
+            "[Database] Creating user memory: {}, confidence: {}, is_explicit: {}, scope: {}",
+            id, confidence, is_explicit, scope.scope
+        );
+        let conn = self.conn.write().unwrap();
+        let now = Local::now().timestamp_millis();
+        conn.execute(
+            "INSERT INTO user_memories (id, text, confidence, is_explicit, status, created_at, updated_at, scope, scope_value, expires_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            rusqlite::params![
+                id,
+                text,
+                confidence,
+                is_explicit,
+                status,
+                now,
+                now,
+                scope.scope,
+                scope.scope_value,
+                scope.expires_at
+            ],
+        ).map_err(|e| {
+            println!("[Database] Error creating user memory: {}", e);
+            e
+        })?;
+        println!("[Database] User memory created successfully: {}", id);
+        Ok(())
+    }
+
+    /// 写入一条自动提取的记忆，并在同一事务中记录来源会话和消息
+    pub fn user_memory_create_with_sources(
         &self,
         id: &str,
         text: &str,
+        kind: &str,
         confidence: f64,
-        is_explicit: bool,
+        status: &str,
+        scope: &MemoryScope,
+        session_id: &str,
+        sources: &[(&str, &str)],
     ) -> Result<()> {
         println!(
-            "[Database] Creating user memory: {}, confidence: {}, is_explicit: {}",
-            id, confidence, is_explicit
+            "[Database] Creating extracted user memory: {}, confidence: {}, status: {}",
+            id, confidence, status
         );
-        let conn = self.conn.write().unwrap();
+        let mut conn = self.conn.write().unwrap();
         let now = Local::now().timestamp_millis();
-        let is_explicit_int = if is_explicit { 1 } else { 0 };
-        conn.execute(
-            "INSERT INTO user_memories (id, text, confidence, is_explicit, status, created_at, updated_at) 
-             VALUES (?, ?, ?, ?, 'created', ?, ?)",
-            [id, text, &confidence.to_string(), &is_explicit_int.to_string(), &now.to_string(), &now.to_string()],
-        ).map_err(|e| {
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO user_memories (id, text, confidence, is_explicit, status, created_at, updated_at, kind, scope, scope_value, expires_at)
+             VALUES (?, ?, ?, 0, ?, ?, ?, ?, ?, ?, ?)",
+            rusqlite::params![
+                id,
+                text,
+                confidence,
+                status,
+                now,
+                now,
+                kind,
+                scope.scope,
+                scope.scope_value,
+                scope.expires_at
+            ],
+        )
+        .map_err(|e| {
             println!("[Database] Error creating user memory: {}", e);
             e
         })?;
-        println!("[Database] User memory created successfully: {}", id);
+
+        for (message_id, role) in sources {
+            tx.execute(
+                "INSERT INTO user_memory_sources (id, memory_id, session_id, message_id, role, is_active, created_at)
+                 VALUES (?, ?, ?, ?, ?, 1, ?)",
+                rusqlite::params![
+                    format!("source_{}", uuid::Uuid::new_v4()),
+                    id,
+                    session_id,
+                    message_id,
+                    role,
+                    now
+                ],
+            )
+            .map_err(|e| {
+                println!("[Database] Error creating user memory source: {}", e);
+                e
+            })?;
+        }
+
+        tx.commit()?;
+        println!("[Database] Extracted user memory created successfully: {}", id);
         Ok(())
     }
 
+    pub fn user_memory_list_sources(&self, memory_id: &str) -> Result<Vec<serde_json::Value>> {
+        println!("[Database] Listing sources of user memory: {}", memory_id);
+        let conn = self.conn.read().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, memory_id, session_id, message_id, role, is_active, created_at
+             FROM user_memory_sources
+             WHERE memory_id = ?
+             ORDER BY created_at ASC",
+        )?;
+        let rows = stmt.query_map([memory_id], |row| {
+            Ok(serde_json::json!({
+                "id": row.get::<_, String>(0)?,
+                "memory_id": row.get::<_, String>(1)?,
+                "session_id": row.get::<_, Option<String>>(2)?,
+                "message_id": row.get::<_, Option<String>>(3)?,
+                "role": row.get::<_, String>(4)?,
+                "is_active": row.get::<_, bool>(5)?,
+                "created_at": row.get::<_, i64>(6)?,
+            }))
+        })?;
+
+        let mut sources = Vec::new();
+        for row in rows {
+            sources.push(row?);
+        }
+        Ok(sources)
+    }
+
     pub fn user_memory_update(
         &self,
         id: &str,
@@ -775,28 +2654,49 @@ impl Database {
         confidence: Option<f64>,
         status: Option<&str>,
         is_explicit: Option<bool>,
+    ) -> Result<()> {
+        self.user_memory_update_with_scope(id, text, confidence, status, is_explicit, None)
+    }
+
+    /// 更新记忆内容，`scope` 不为 None 时在同一事务中一并修改适用范围和过期时间
+    pub fn user_memory_update_with_scope(
+        &self,
+        id: &str,
+        text: Option<&str>,
+        confidence: Option<f64>,
+        status: Option<&str>,
+        is_explicit: Option<bool>,
+        scope: Option<&MemoryScope>,
     ) -> Result<()> {
         println!("[Database] Updating user memory: {}", id);
         let conn = self.conn.write().unwrap();
         let now = Local::now().timestamp_millis();
         let mut set_clauses = Vec::new();
-        let mut params = Vec::new();
+        let mut params: Vec<rusqlite::types::Value> = Vec::new();
 
         if let Some(text) = text {
             set_clauses.push("text = ?");
-            params.push(text.to_string());
+            params.push(text.to_string().into());
         }
         if let Some(confidence) = confidence {
             set_clauses.push("confidence = ?");
-            params.push(confidence.to_string());
+            params.push(confidence.into());
         }
         if let Some(status) = status {
             set_clauses.push("status = ?");
-            params.push(status.to_string());
+            params.push(status.to_string().into());
         }
         if let Some(is_explicit) = is_explicit {
             set_clauses.push("is_explicit = ?");
-            params.push(if is_explicit { "1" } else { "0" }.to_string());
+            params.push(i64::from(is_explicit).into());
+        }
+        if let Some(scope) = scope {
+            set_clauses.push("scope = ?");
+            params.push(scope.scope.to_string().into());
+            set_clauses.push("scope_value = ?");
+            params.push(scope.scope_value.map(str::to_string).into());
+            set_clauses.push("expires_at = ?");
+            params.push(scope.expires_at.into());
         }
 
         if set_clauses.is_empty() {
@@ -805,8 +2705,8 @@ impl Database {
         }
 
         set_clauses.push("updated_at = ?");
-        params.push(now.to_string());
-        params.push(id.to_string());
+        params.push(now.into());
+        params.push(id.to_string().into());
 
         let sql = format!(
             "UPDATE user_memories SET {} WHERE id = ?",
@@ -822,6 +2722,97 @@ impl Database {
         Ok(())
     }
 
+    /// 把重复记忆合并到 `survivor_id`：来源记录改挂到保留记忆，被合并的记忆删除并记录到
+    /// user_memory_merges。`merged` 为被合并记忆 id 及其与保留记忆的相似度，返回迁移的来源条数
+    pub fn user_memory_merge(
+        &self,
+        survivor_id: &str,
+        merged: &[(String, f64)],
+        confidence: f64,
+        status: &str,
+        is_explicit: bool,
+    ) -> Result<usize> {
+        println!(
+            "[Database] Merging {} user memories into {}",
+            merged.len(),
+            survivor_id
+        );
+        let mut conn = self.conn.write().unwrap();
+        let now = Local::now().timestamp_millis();
+        let tx = conn.transaction()?;
+
+        let mut moved = 0;
+        let mut last_used_at: Option<i64> = tx.query_row(
+            "SELECT last_used_at FROM user_memories WHERE id = ?",
+            [survivor_id],
+            |row| row.get(0),
+        )?;
+        for (merged_id, similarity) in merged {
+            let (text, merged_last_used): (String, Option<i64>) = tx.query_row(
+                "SELECT text, last_used_at FROM user_memories WHERE id = ?",
+                [merged_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            last_used_at = last_used_at.max(merged_last_used);
+
+            moved += tx.execute(
+                "UPDATE user_memory_sources SET memory_id = ? WHERE memory_id = ?",
+                [survivor_id, merged_id.as_str()],
+            )?;
+            tx.execute(
+                "INSERT INTO user_memory_merges (id, survivor_id, merged_id, merged_text, similarity, created_at)
+                 VALUES (?, ?, ?, ?, ?, ?)",
+                rusqlite::params![
+                    format!("merge_{}", uuid::Uuid::new_v4()),
+                    survivor_id,
+                    merged_id,
+                    text,
+                    similarity,
+                    now
+                ],
+            )?;
+            tx.execute("DELETE FROM user_memories WHERE id = ?", [merged_id])?;
+        }
+
+        tx.execute(
+            "UPDATE user_memories SET confidence = ?, status = ?, is_explicit = ?, last_used_at = ?, updated_at = ? WHERE id = ?",
+            rusqlite::params![confidence, status, is_explicit, last_used_at, now, survivor_id],
+        )?;
+        tx.commit().map_err(|e| {
+            println!("[Database] Error merging user memories: {}", e);
+            e
+        })?;
+
+        println!(
+            "[Database] Merged user memories into {}, {} sources moved",
+            survivor_id, moved
+        );
+        Ok(moved)
+    }
+
+    /// 记录记忆被注入提示词的时间，不改变 updated_at
+    pub fn user_memories_mark_used(&self, ids: &[&str]) -> Result<usize> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let conn = self.conn.write().unwrap();
+        let now = Local::now().timestamp_millis();
+        let sql = format!(
+            "UPDATE user_memories SET last_used_at = ? WHERE id IN ({})",
+            vec!["?"; ids.len()].join(", ")
+        );
+        let mut params: Vec<rusqlite::types::Value> = vec![now.into()];
+        params.extend(ids.iter().map(|id| rusqlite::types::Value::from(id.to_string())));
+        let count = conn
+            .execute(&sql, rusqlite::params_from_iter(params))
+            .map_err(|e| {
+                println!("[Database] Error marking user memories as used: {}", e);
+                e
+            })?;
+        println!("[Database] Marked {} user memories as used", count);
+        Ok(count)
+    }
+
     pub fn user_memory_delete(&self, id: &str) -> Result<bool> {
         println!("[Database] Deleting user memory: {}", id);
         let conn = self.conn.write().unwrap();
@@ -840,6 +2831,16 @@ impl Database {
         Ok(count > 0)
     }
 
+    /// 记忆表的指纹（条数 + 最近更新时间），用于轮询时判断记忆是否有变化
+    pub fn user_memories_fingerprint(&self) -> Result<String> {
+        let conn = self.conn.read().unwrap();
+        conn.query_row(
+            "SELECT COUNT(*), COALESCE(MAX(updated_at), 0) FROM user_memories",
+            [],
+            |row| Ok(format!("{}:{}", row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+        )
+    }
+
     pub fn user_memory_get_stats(&self) -> Result<serde_json::Value> {
         println!("[Database] Getting user memory stats...");
         let conn = self.conn.read().unwrap();
@@ -852,6 +2853,8 @@ impl Database {
 
         let mut total = 0;
         let mut created = 0;
+        let mut pending = 0;
+        let mut accepted = 0;
         let mut stale = 0;
         let mut deleted = 0;
         let mut explicit = 0;
@@ -875,6 +2878,8 @@ impl Database {
             }
             match status.as_str() {
                 "created" => created += count,
+                "pending" => pending += count,
+                "accepted" => accepted += count,
                 "stale" => stale += count,
                 "deleted" => deleted += count,
                 _ => {}
@@ -884,13 +2889,41 @@ impl Database {
         println!("[Database] User memory stats: total={}, created={}, stale={}, deleted={}, explicit={}, implicit={}", 
             total, created, stale, deleted, explicit, implicit);
 
+        let merged: i64 =
+            conn.query_row("SELECT COUNT(*) FROM user_memory_merges", [], |row| row.get(0))?;
+        let mut stmt = conn.prepare(
+            "SELECT mm.survivor_id, m.text, mm.merged_id, mm.merged_text, mm.similarity, mm.created_at
+             FROM user_memory_merges mm
+             LEFT JOIN user_memories m ON m.id = mm.survivor_id
+             ORDER BY mm.created_at DESC
+             LIMIT 20",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(serde_json::json!({
+                "survivor_id": row.get::<_, String>(0)?,
+                "survivor_text": row.get::<_, Option<String>>(1)?,
+                "merged_id": row.get::<_, String>(2)?,
+                "merged_text": row.get::<_, String>(3)?,
+                "similarity": row.get::<_, f64>(4)?,
+                "merged_at": row.get::<_, i64>(5)?,
+            }))
+        })?;
+        let mut recent_merges = Vec::new();
+        for row in rows {
+            recent_merges.push(row?);
+        }
+
         Ok(serde_json::json!({
             "total": total,
             "created": created,
+            "pending": pending,
+            "accepted": accepted,
             "stale": stale,
             "deleted": deleted,
             "explicit": explicit,
             "implicit": implicit,
+            "merged": merged,
+            "recent_merges": recent_merges,
         }))
     }
 
@@ -1448,6 +3481,443 @@ mod tests {
         // 测试列出消息
         let messages = db.cowork_list_messages(session_id).unwrap();
         assert!(!messages.is_empty(), "Should have at least one message");
+        assert_eq!(messages[0]["session_id"], session_id);
+        assert_eq!(messages[0]["sequence"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_cowork_messages_page() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+
+        let session_id = "test_session_1";
+        db.cowork_create_session(session_id, "Test Session", None, None, None)
+            .unwrap();
+        for i in 1..=7 {
+            db.cowork_add_message(&format!("msg_{}", i), session_id, "user", &format!("m{}", i))
+                .unwrap();
+        }
+        let sequences = |messages: &[serde_json::Value]| -> Vec<i64> {
+            messages.iter().map(|m| m["sequence"].as_i64().unwrap()).collect()
+        };
+
+        // 默认取最新的一页，再用 before 向前翻页
+        let (messages, has_more) = db.cowork_list_messages_page(session_id, None, None, 3).unwrap();
+        assert_eq!((sequences(&messages), has_more), (vec![5, 6, 7], true));
+        let (messages, has_more) = db.cowork_list_messages_page(session_id, Some(5), None, 3).unwrap();
+        assert_eq!((sequences(&messages), has_more), (vec![2, 3, 4], true));
+        let (messages, has_more) = db.cowork_list_messages_page(session_id, Some(2), None, 3).unwrap();
+        assert_eq!((sequences(&messages), has_more), (vec![1], false));
+
+        let (messages, has_more) = db.cowork_list_messages_page(session_id, None, Some(4), 3).unwrap();
+        assert_eq!((sequences(&messages), has_more), (vec![5, 6, 7], false));
+        let (messages, has_more) = db.cowork_list_messages_page(session_id, Some(6), Some(2), 10).unwrap();
+        assert_eq!((sequences(&messages), has_more), (vec![3, 4, 5], false));
+        assert!(db.cowork_list_messages_page("missing", None, None, 3).unwrap().0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cowork_session_settings() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+
+        let session_id = "test_session_1";
+        db.cowork_create_session(session_id, "Test Session", Some("/tmp/project"), None, None)
+            .unwrap();
+        db.cowork_update_session(
+            session_id,
+            None,
+            None,
+            None,
+            None,
+            Some("Be concise"),
+            None,
+            Some(r#"["docx"]"#),
+        )
+        .unwrap();
+
+        let session = db.cowork_get_session(session_id).unwrap().unwrap();
+        assert_eq!(session["cwd"], "/tmp/project");
+        assert_eq!(session["system_prompt"], "Be concise");
+        assert_eq!(session["active_skill_ids"], r#"["docx"]"#);
+        assert!(session["context_budget"].is_null());
+        assert!(db.cowork_get_session("missing").unwrap().is_none());
+
+        db.cowork_set_session_context_budget(session_id, Some(8000))
+            .unwrap();
+        let session = db.cowork_get_session(session_id).unwrap().unwrap();
+        assert_eq!(session["context_budget"], 8000);
+
+        // 自动标题只覆盖默认标题，用户改过的标题不会被覆盖
+        assert!(session["title_source"].is_null());
+        assert!(db.cowork_set_auto_title(session_id, "Auto Title").unwrap());
+        let session = db.cowork_get_session(session_id).unwrap().unwrap();
+        assert_eq!(session["title"], "Auto Title");
+        assert_eq!(session["title_source"], "auto");
+        assert!(!db.cowork_set_auto_title(session_id, "Another Title").unwrap());
+
+        db.cowork_update_session(session_id, Some("Renamed"), None, None, None, None, None, None)
+            .unwrap();
+        let session = db.cowork_get_session(session_id).unwrap().unwrap();
+        assert_eq!(session["title"], "Renamed");
+        assert_eq!(session["title_source"], "manual");
+    }
+
+    #[tokio::test]
+    async fn test_cowork_insert_session_with_messages() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+
+        let session = serde_json::json!({
+            "id": "session_imported",
+            "title": "Imported",
+            "status": "idle",
+            "pinned": false,
+            "cwd": "/tmp/project",
+            "system_prompt": "Be brief.",
+            "execution_mode": "local",
+            "active_skill_ids": null,
+            "created_at": 1000,
+            "updated_at": 2000,
+            "parent_session_id": "session_parent",
+            "fork_sequence": 2,
+            "folder_id": "folder_elsewhere",
+            "tags": ["work", "  ", "research", "work"],
+        });
+        let messages = vec![
+            serde_json::json!({"id": "msg_1", "type": "user", "content": "hello", "timestamp": 1000, "metadata": null, "sequence": 1}),
+            serde_json::json!({"id": "msg_2", "type": "assistant", "content": "hi", "timestamp": 1500, "metadata": "{\"status\":\"done\"}", "sequence": 2}),
+        ];
+        let sha = "b".repeat(64);
+        let attachments = vec![(sha.clone(), "text/plain".to_string(), 5)];
+        db.cowork_insert_session_with_messages(&session, &messages, &attachments).unwrap();
+        assert_eq!(db.cowork_attachment_mime_type(&sha).unwrap().as_deref(), Some("text/plain"));
+
+        let stored = db.cowork_get_session("session_imported").unwrap().unwrap();
+        assert_eq!(stored["created_at"], 1000);
+        assert_eq!(stored["system_prompt"], "Be brief.");
+        assert_eq!(stored["parent_session_id"], "session_parent");
+        assert_eq!(stored["fork_sequence"], 2);
+        assert!(stored["context_budget"].is_null());
+        assert!(stored["folder_id"].is_null());
+        assert_eq!(stored["tags"], serde_json::json!(["research", "work"]));
+        let stored_messages = db.cowork_list_messages("session_imported").unwrap();
+        assert_eq!(stored_messages.len(), 2);
+        assert_eq!(stored_messages[1]["metadata"], "{\"status\":\"done\"}");
+        assert_eq!(stored_messages[1]["sequence"], 2);
+
+        // 重复 id 时整体回滚
+        assert!(db.cowork_insert_session_with_messages(&session, &messages, &[]).is_err());
+        assert_eq!(db.cowork_list_messages("session_imported").unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cowork_session_organization() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+
+        for i in 1..=5 {
+            let cwd = if i % 2 == 0 { "/tmp/even" } else { "/tmp/odd" };
+            db.cowork_create_session(&format!("session_{}", i), &format!("Session {}", i), Some(cwd), None, None)
+                .unwrap();
+        }
+        db.cowork_create_folder("folder_1", "Work").unwrap();
+        db.cowork_set_session_folder("session_1", Some("folder_1")).unwrap();
+        db.cowork_set_session_folder("session_2", Some("folder_1")).unwrap();
+        db.cowork_set_session_tags("session_1", &["rust".to_string(), "ui".to_string()])
+            .unwrap();
+        db.cowork_set_session_tags("session_3", &["rust".to_string()]).unwrap();
+        db.cowork_set_session_archived("session_4", true).unwrap();
+
+        let page = |filter: SessionListFilter| {
+            let (sessions, total) = db.cowork_list_sessions_page(&filter).unwrap();
+            let ids: Vec<String> = sessions.iter().map(|s| s["id"].as_str().unwrap().to_string()).collect();
+            (ids, total)
+        };
+        let (ids, total) = page(SessionListFilter { archived: Some(false), limit: 2, ..Default::default() });
+        assert_eq!(total, 4);
+        assert_eq!(ids.len(), 2);
+        let (rest, _) = page(SessionListFilter { archived: Some(false), limit: 10, offset: 2, ..Default::default() });
+        assert_eq!(rest.len(), 2);
+        assert!(ids.iter().all(|id| !rest.contains(id)));
+
+        let (mut ids, total) = page(SessionListFilter { tag: Some("rust"), limit: 10, ..Default::default() });
+        ids.sort();
+        assert_eq!((ids, total), (vec!["session_1".to_string(), "session_3".to_string()], 2));
+        let (ids, _) = page(SessionListFilter { tag: Some("rust"), folder_id: Some("folder_1"), limit: 10, ..Default::default() });
+        assert_eq!(ids, vec!["session_1".to_string()]);
+        let (ids, _) = page(SessionListFilter { archived: Some(true), limit: 10, ..Default::default() });
+        assert_eq!(ids, vec!["session_4".to_string()]);
+        let (_, total) = page(SessionListFilter { cwd: Some("/tmp/odd"), limit: 10, ..Default::default() });
+        assert_eq!(total, 3);
+        // 工作区按去掉末尾分隔符后的 cwd 匹配
+        db.cowork_create_session("session_6", "Session 6", Some("/tmp/odd/"), None, None).unwrap();
+        let (_, total) = page(SessionListFilter { workspace: Some("/tmp/odd"), limit: 10, ..Default::default() });
+        assert_eq!(total, 4);
+        let workspaces = db.cowork_list_workspaces().unwrap();
+        assert_eq!(workspaces.len(), 2);
+        assert_eq!(workspaces[0]["path"], "/tmp/odd");
+        assert_eq!(workspaces[0]["session_count"], 4);
+        assert_eq!((workspaces[1]["session_count"].as_i64(), workspaces[1]["active_session_count"].as_i64()), (Some(2), Some(1)));
+        db.cowork_delete_session("session_6").unwrap();
+        let (_, total) = page(SessionListFilter { start_time: Some(Local::now().timestamp_millis() + 1000), limit: 10, ..Default::default() });
+        assert_eq!(total, 0);
+
+        let session = db.cowork_get_session("session_1").unwrap().unwrap();
+        assert_eq!(session["tags"], serde_json::json!(["rust", "ui"]));
+        assert_eq!(session["folder_id"], "folder_1");
+        assert_eq!(db.cowork_list_tags().unwrap(), vec![("rust".to_string(), 2), ("ui".to_string(), 1)]);
+        assert_eq!(db.cowork_list_folders().unwrap()[0]["session_count"], 2);
+
+        // 删除文件夹只会把会话移出文件夹
+        assert!(db.cowork_rename_folder("folder_1", "Projects").unwrap());
+        db.cowork_delete_folder("folder_1").unwrap();
+        assert!(db.cowork_list_folders().unwrap().is_empty());
+        assert!(db.cowork_get_session("session_1").unwrap().unwrap()["folder_id"].is_null());
+        db.cowork_delete_session("session_1").unwrap();
+        assert_eq!(db.cowork_list_tags().unwrap(), vec![("rust".to_string(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_cowork_usage() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+        db.cowork_create_session("session_1", "First", None, None, None).unwrap();
+        db.cowork_create_session("session_2", "Second", None, None, None).unwrap();
+
+        db.cowork_usage_record("msg_1", "session_1", Some("gpt-4o"), 100, 10, false).unwrap();
+        db.cowork_usage_record("msg_2", "session_1", Some("gpt-4o"), 200, 20, true).unwrap();
+        db.cowork_usage_record("msg_3", "session_1", Some("local"), 5, 5, false).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        db.cowork_usage_record("msg_4", "session_2", Some("gpt-4o"), 1, 1, false).unwrap();
+        // 同一条消息重复写入时保留最后一次
+        db.cowork_usage_record("msg_4", "session_2", Some("gpt-4o"), 50, 50, false).unwrap();
+
+        let groups = db.cowork_usage_groups(false, None, None, None).unwrap();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0]["key"], "session_2");
+        assert_eq!(groups[0]["prompt_tokens"], 50);
+        assert_eq!(groups[1]["key"], "session_1");
+        assert_eq!(groups[1]["title"], "First");
+        assert_eq!((groups[1]["model"].as_str(), groups[1]["prompt_tokens"].as_i64()), (Some("gpt-4o"), Some(300)));
+        assert_eq!(groups[1]["estimated_replies"], 1);
+
+        // 会话删除后用量仍然保留
+        db.cowork_delete_session("session_1").unwrap();
+        let groups = db.cowork_usage_groups(true, Some("session_1"), None, None).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0]["key"], Local::now().format("%Y-%m-%d").to_string());
+        assert!(groups[0]["title"].is_null());
+        let future = Local::now().timestamp_millis() + 1000;
+        assert!(db.cowork_usage_groups(true, None, Some(future), None).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cowork_approvals() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+        db.cowork_create_session("session_1", "First", None, None, None).unwrap();
+
+        db.cowork_add_permission_rule("rule_1", "shell", Some("git *"), None, None, "allow").unwrap();
+        db.cowork_add_permission_rule("rule_2", "write_file", None, Some("/tmp"), Some("session_1"), "deny")
+            .unwrap();
+        db.cowork_add_permission_rule("rule_3", "shell", None, None, Some("session_2"), "allow").unwrap();
+        let rules = db.cowork_list_permission_rules(Some("session_1")).unwrap();
+        let ids: Vec<&str> = rules.iter().map(|r| r["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec!["rule_1", "rule_2"]);
+        assert_eq!(rules[1]["path_prefix"], "/tmp");
+        assert_eq!(db.cowork_list_permission_rules(None).unwrap().len(), 3);
+        assert!(db.cowork_delete_permission_rule("rule_3").unwrap());
+        assert!(!db.cowork_delete_permission_rule("rule_3").unwrap());
+
+        db.cowork_record_approval("a1", "session_1", "shell", Some("git status"), None, r#"{"command":"git status"}"#, "allow", "rule", Some("rule_1"), 1)
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        db.cowork_record_approval("a2", "session_1", "shell", Some("rm -rf build"), None, "null", "deny", "user", None, 2)
+            .unwrap();
+        let audit = db.cowork_list_approval_audit(Some("session_1"), 10, 0).unwrap();
+        assert_eq!(audit.len(), 2);
+        assert_eq!(audit[0]["approval_id"], "a2");
+        assert_eq!(audit[1]["arguments"]["command"], "git status");
+        assert!(db.cowork_list_approval_audit(Some("session_2"), 10, 0).unwrap().is_empty());
+
+        // 会话删除后会话规则随之删除，审计记录保留
+        db.cowork_delete_session("session_1").unwrap();
+        assert_eq!(db.cowork_list_permission_rules(None).unwrap().len(), 1);
+        assert_eq!(db.cowork_list_approval_audit(None, 1, 1).unwrap()[0]["approval_id"], "a1");
+    }
+
+    #[tokio::test]
+    async fn test_cowork_file_snapshots() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+        db.cowork_create_session("session_1", "First", None, None, None).unwrap();
+
+        let sha = "a".repeat(64);
+        assert!(db.cowork_add_file_snapshot("session_1", "msg_1", "/work/a.txt", true, Some(&sha), 3).unwrap());
+        // 同一轮中只保留第一次修改前的状态
+        assert!(!db.cowork_add_file_snapshot("session_1", "msg_1", "/work/a.txt", true, None, 0).unwrap());
+        assert!(db.cowork_has_file_snapshot("msg_1", "/work/a.txt").unwrap());
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        db.cowork_add_file_snapshot("session_1", "msg_2", "/work/a.txt", true, Some(&sha), 3).unwrap();
+        db.cowork_add_file_snapshot("session_1", "msg_2", "/work/b.txt", false, None, 0).unwrap();
+
+        let snapshots = db.cowork_list_file_snapshots("session_1", Some("msg_2")).unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[1]["existed"], false);
+        assert_eq!(snapshots[1]["missing"], false);
+        assert!(snapshots[1]["after_existed"].is_null());
+        let id = snapshots[1]["id"].as_i64().unwrap();
+        assert!(db.cowork_set_file_snapshot_after(id, true, Some(&sha)).unwrap());
+        assert!(!db.cowork_set_file_snapshot_after(-1, true, None).unwrap());
+        let snapshots = db.cowork_list_file_snapshots("session_1", Some("msg_2")).unwrap();
+        assert_eq!(snapshots[1]["after_existed"], true);
+        assert_eq!(snapshots[1]["after_sha256"].as_str(), Some(sha.as_str()));
+        assert_eq!(db.cowork_mark_turn_reverted("session_1", "msg_2").unwrap(), 2);
+        assert!(db.cowork_list_file_snapshots("session_1", None).unwrap()[2]["reverted_at"].is_i64());
+        // 已保存快照的文件不会被标记为缺失
+        assert!(!db.cowork_add_missing_file_snapshot("session_1", "msg_2", "/work/b.txt").unwrap());
+        assert!(db.cowork_add_missing_file_snapshot("session_1", "msg_2", "/work/c.txt").unwrap());
+        let missing = db.cowork_list_file_snapshots("session_1", Some("msg_2")).unwrap();
+        assert_eq!(missing[2]["missing"], true);
+        assert!(missing[2]["sha256"].is_null());
+
+        assert_eq!(db.cowork_prune_file_snapshots("session_1", 1).unwrap(), 1);
+        assert_eq!(db.cowork_list_file_snapshots("session_1", None).unwrap().len(), 3);
+        assert_eq!(db.cowork_snapshot_hashes().unwrap(), vec![sha]);
+        db.cowork_delete_session("session_1").unwrap();
+        assert!(db.cowork_snapshot_hashes().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cowork_session_templates() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+
+        db.cowork_create_template("template_1", "Review", Some("/tmp/project"), Some("Review code."), None, None, Some(16000))
+            .unwrap();
+        assert!(db
+            .cowork_create_template("template_2", "Review", None, None, None, None, None)
+            .is_err());
+        assert!(db
+            .cowork_update_template("template_1", Some("Code review"), None, None, Some("sandbox"), Some(r#"["git"]"#), Some(0))
+            .unwrap());
+        assert!(!db
+            .cowork_update_template("missing", Some("x"), None, None, None, None, None)
+            .unwrap());
+
+        let templates = db.cowork_list_templates(None).unwrap();
+        assert_eq!(templates.len(), 1);
+        assert_eq!(templates[0]["name"], "Code review");
+        assert_eq!(templates[0]["system_prompt"], "Review code.");
+        assert_eq!(templates[0]["execution_mode"], "sandbox");
+        assert!(templates[0]["context_budget"].is_null());
+        assert_eq!(db.cowork_list_templates(Some("template_1")).unwrap().len(), 1);
+        assert!(db.cowork_list_templates(Some("missing")).unwrap().is_empty());
+
+        db.cowork_delete_template("template_1").unwrap();
+        assert!(db.cowork_list_templates(None).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cowork_attachments_unreferenced() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+        let (shared, orphan) = ("a".repeat(64), "b".repeat(64));
+        db.cowork_attachment_upsert(&shared, "image/png", 10).unwrap();
+        db.cowork_attachment_upsert(&orphan, "text/plain", 5).unwrap();
+
+        let metadata = serde_json::json!({
+            "attachments": [{ "sha256": shared, "name": "a.png", "mime_type": "image/png", "size": 10 }]
+        })
+        .to_string();
+        for session_id in ["session_1", "session_2"] {
+            db.cowork_create_session(session_id, "Test", None, None, None).unwrap();
+            let msg_id = format!("{}_msg", session_id);
+            db.cowork_add_message(&msg_id, session_id, "user", "see attached").unwrap();
+            db.cowork_update_message(&msg_id, session_id, None, Some(&metadata)).unwrap();
+        }
+        db.cowork_add_message("msg_bad", "session_1", "user", "bad metadata").unwrap();
+        db.cowork_update_message("msg_bad", "session_1", None, Some("not json, attachments")).unwrap();
+
+        let future = Local::now().timestamp_millis() + 1000;
+        assert!(db.cowork_attachments_unreferenced(0).unwrap().is_empty());
+        assert_eq!(db.cowork_attachments_unreferenced(future).unwrap(), vec![orphan.clone()]);
+
+        // 只有最后一个引用它的会话被删除后才可回收
+        db.cowork_delete_session("session_1").unwrap();
+        assert_eq!(db.cowork_attachments_unreferenced(future).unwrap(), vec![orphan.clone()]);
+        db.cowork_delete_session("session_2").unwrap();
+        let mut unreferenced = db.cowork_attachments_unreferenced(future).unwrap();
+        unreferenced.sort();
+        assert_eq!(unreferenced, vec![shared.clone(), orphan.clone()]);
+
+        assert!(db.cowork_attachment_delete(&orphan).unwrap());
+        assert_eq!(db.cowork_attachments_unreferenced(future).unwrap(), vec![shared]);
+    }
+
+    #[tokio::test]
+    async fn test_cowork_search_messages() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+
+        db.cowork_create_session("session_1", "Rust questions", None, None, None)
+            .unwrap();
+        db.cowork_create_session("session_2", "中文会话", None, None, None)
+            .unwrap();
+        db.cowork_add_message("msg_1", "session_1", "user", "How do I use tokio channels?")
+            .unwrap();
+        db.cowork_add_message("msg_2", "session_1", "assistant", "Use tokio::sync::mpsc for channels.")
+            .unwrap();
+        db.cowork_add_message("msg_3", "session_2", "user", "请帮我整理会议纪要")
+            .unwrap();
+
+        let filter = MessageSearchFilter {
+            limit: 10,
+            ..Default::default()
+        };
+        let hits = db.cowork_search_messages("tokio channels", &filter).unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0]["session_title"], "Rust questions");
+        assert!(hits[0]["snippet"].as_str().unwrap().contains("<mark>"));
+
+        let types = vec!["assistant".to_string()];
+        let filter = MessageSearchFilter {
+            message_types: &types,
+            limit: 10,
+            ..Default::default()
+        };
+        let hits = db.cowork_search_messages("tokio", &filter).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0]["message_id"], "msg_2");
+
+        // 短词走子串匹配
+        let filter = MessageSearchFilter {
+            limit: 10,
+            ..Default::default()
+        };
+        let hits = db.cowork_search_messages("纪要", &filter).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0]["snippet"].as_str().unwrap().contains("<mark>纪要</mark>"));
+
+        // 更新和删除同步到索引
+        db.cowork_update_message("msg_1", "session_1", Some("Something else entirely"), None)
+            .unwrap();
+        let hits = db.cowork_search_messages("tokio", &filter).unwrap();
+        assert_eq!(hits.len(), 1);
+        db.cowork_delete_session("session_1").unwrap();
+        let hits = db.cowork_search_messages("tokio", &filter).unwrap();
+        assert!(hits.is_empty());
     }
 
     #[tokio::test]
@@ -1469,6 +3939,165 @@ mod tests {
         assert!(!memories.is_empty(), "Should have at least one memory");
     }
 
+    #[tokio::test]
+    async fn test_user_memory_scope() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+
+        // 未指定范围时为全局记忆
+        db.user_memory_create("test_memory_1", "Test memory content", 0.9, false)
+            .unwrap();
+        let session = MemoryScope {
+            scope: "session",
+            scope_value: Some("session_1"),
+            expires_at: Some(3000),
+        };
+        db.user_memory_create_with_scope("test_memory_2", "Draft in English", 0.8, true, "accepted", &session)
+            .unwrap();
+        let memories = db.user_memories_list().unwrap();
+        let global = memories.iter().find(|m| m["id"] == "test_memory_1").unwrap();
+        assert_eq!(global["scope"], "global");
+        assert!(global["scope_value"].is_null() && global["expires_at"].is_null());
+        let created = memories.iter().find(|m| m["id"] == "test_memory_2").unwrap();
+        assert_eq!((created["scope"].as_str(), created["status"].as_str()), (Some("session"), Some("accepted")));
+        assert_eq!(created["scope_value"], "session_1");
+        assert_eq!(created["expires_at"], 3000);
+
+        // 修改范围
+        let workspace = MemoryScope {
+            scope: "workspace",
+            scope_value: Some("/tmp/project"),
+            expires_at: Some(5000),
+        };
+        db.user_memory_update_with_scope("test_memory_1", None, None, None, None, Some(&workspace))
+            .unwrap();
+        let memories = db.user_memories_list().unwrap();
+        let scoped = memories.iter().find(|m| m["id"] == "test_memory_1").unwrap();
+        assert_eq!(scoped["scope"], "workspace");
+        assert_eq!(scoped["scope_value"], "/tmp/project");
+        assert_eq!(scoped["expires_at"], 5000);
+    }
+
+    #[tokio::test]
+    async fn test_user_memory_sources() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+        db.user_memory_create("test_memory_1", "Test memory content", 0.9, true)
+            .unwrap();
+
+        // 自动提取的记忆与来源消息在同一事务中写入
+        db.user_memory_create_with_sources(
+            "test_memory_2",
+            "Prefers Chinese replies",
+            "preference",
+            0.6,
+            "pending",
+            &MemoryScope::GLOBAL,
+            "session_1",
+            &[("msg_1", "user"), ("msg_2", "assistant")],
+        )
+        .unwrap();
+        let memories = db.user_memories_list().unwrap();
+        let extracted = memories.iter().find(|m| m["id"] == "test_memory_2").unwrap();
+        assert_eq!(extracted["is_explicit"], false);
+        assert_eq!(extracted["kind"], "preference");
+        let sources = db.user_memory_list_sources("test_memory_2").unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0]["session_id"], "session_1");
+        assert!(db.user_memory_list_sources("test_memory_1").unwrap().is_empty());
+        let stats = db.user_memory_get_stats().unwrap();
+        assert_eq!(stats["pending"], 1);
+        assert_eq!(stats["implicit"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_user_memory_merge() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+        let sources = [("msg_1", "user"), ("msg_2", "assistant")];
+        for (id, text, confidence, source) in [
+            ("test_memory_2", "Prefers Chinese replies", 0.6, &sources[..]),
+            ("test_memory_3", "prefers chinese replies.", 0.5, &sources[..1]),
+            ("test_memory_4", "Prefers Chinese replies!", 0.9, &sources[1..]),
+        ] {
+            db.user_memory_create_with_sources(
+                id,
+                text,
+                "preference",
+                confidence,
+                "pending",
+                &MemoryScope::GLOBAL,
+                "session_1",
+                source,
+            )
+            .unwrap();
+        }
+        // 已删除的记忆即使文本相同也不进入合并分组
+        assert!(db.user_memory_delete("test_memory_4").unwrap());
+
+        let memories: Vec<crate::cowork::UserMemory> = db
+            .user_memories_list()
+            .unwrap()
+            .into_iter()
+            .filter_map(|m| serde_json::from_value(m).ok())
+            .collect();
+        let threshold = crate::cowork_memory::DUPLICATE_SIMILARITY_THRESHOLD;
+        let plans = crate::cowork_memory::plan_merges(&memories, threshold);
+        assert_eq!(plans.len(), 1);
+        assert_eq!(plans[0].survivor_id, "test_memory_2");
+        assert_eq!(plans[0].merged_ids, vec!["test_memory_3".to_string()]);
+
+        let moved = db
+            .user_memory_merge(
+                "test_memory_2",
+                &[("test_memory_3".to_string(), 0.95)],
+                0.8,
+                "accepted",
+                false,
+            )
+            .unwrap();
+        assert_eq!(moved, 1);
+        assert_eq!(db.user_memory_list_sources("test_memory_2").unwrap().len(), 3);
+        let memories = db.user_memories_list().unwrap();
+        assert!(memories.iter().all(|m| m["id"] != "test_memory_3"));
+        let survivor = memories.iter().find(|m| m["id"] == "test_memory_2").unwrap();
+        assert_eq!(survivor["status"], "accepted");
+        let deleted = memories.iter().find(|m| m["id"] == "test_memory_4").unwrap();
+        assert_eq!(deleted["status"], "deleted");
+        assert_eq!(db.user_memory_list_sources("test_memory_4").unwrap().len(), 1);
+        let stats = db.user_memory_get_stats().unwrap();
+        assert_eq!(stats["merged"], 1);
+        assert_eq!(stats["recent_merges"][0]["merged_text"], "prefers chinese replies.");
+    }
+
+    #[tokio::test]
+    async fn test_user_memories_mark_used() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(db_path).unwrap();
+        db.user_memory_create("test_memory_1", "Test memory content", 0.9, false)
+            .unwrap();
+        db.user_memory_create("test_memory_2", "Other memory", 0.9, false)
+            .unwrap();
+        let before = db.user_memories_list().unwrap();
+
+        // 记录使用时间不改变 updated_at，列表顺序保持不变
+        assert_eq!(db.user_memories_mark_used(&[]).unwrap(), 0);
+        assert_eq!(db.user_memories_mark_used(&["test_memory_1", "missing"]).unwrap(), 1);
+        let memories = db.user_memories_list().unwrap();
+        let used = memories.iter().find(|m| m["id"] == "test_memory_1").unwrap();
+        assert!(used["last_used_at"].is_i64());
+        let unused = memories.iter().find(|m| m["id"] == "test_memory_2").unwrap();
+        assert!(unused["last_used_at"].is_null());
+        let updated_at = |list: &[serde_json::Value]| {
+            list.iter().map(|m| (m["id"].clone(), m["updated_at"].clone())).collect::<Vec<_>>()
+        };
+        assert_eq!(updated_at(&memories), updated_at(&before));
+    }
+
     #[tokio::test]
     async fn test_scheduled_tasks() {
         // 创建临时目录
@@ -27,6 +27,13 @@ pub struct GoClawConfig {
     pub auto_reconnect: bool,
     pub start_timeout_ms: u64,
     pub ws_connect_timeout_ms: u64,
+    /// 普通请求等待响应的总时长；流式回复为两次推送之间允许的最长间隔
+    #[serde(default = "default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+}
+
+fn default_request_timeout_ms() -> u64 {
+    30000
 }
 
 impl Default for GoClawConfig {
@@ -40,6 +47,7 @@ impl Default for GoClawConfig {
             auto_reconnect: true,
             start_timeout_ms: 5000,
             ws_connect_timeout_ms: 10000,
+            request_timeout_ms: default_request_timeout_ms(),
         }
     }
 }
@@ -88,6 +96,82 @@ type PendingRequests =
     Arc<Mutex<HashMap<String, oneshot::Sender<Result<serde_json::Value, String>>>>>;
 type NotificationCallback =
     Arc<Mutex<Option<Box<dyn Fn(String, serde_json::Value) + Send + Sync>>>>;
+type SharedConnection = Arc<AsyncMutex<Option<WebSocketConnection>>>;
+
+/// JSON-RPC 取消通知的方法名
+const CANCEL_REQUEST_METHOD: &str = "$/cancelRequest";
+
+/// 已发出、尚未收到响应的请求。等待响应不需要持有 GoClawManager 的锁，
+/// 因此等待期间仍可以通过 `cancel_request` 取消
+pub struct PendingResponse {
+    id: String,
+    rx: oneshot::Receiver<Result<serde_json::Value, String>>,
+    timeout: Duration,
+    pending_requests: PendingRequests,
+    ws_connection: SharedConnection,
+}
+
+impl PendingResponse {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// 配置的请求超时
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// 在总超时内等待响应，超时后放弃请求
+    pub async fn wait(mut self) -> anyhow::Result<serde_json::Value> {
+        let result = tokio::time::timeout(self.timeout, self.response()).await;
+        match result {
+            Ok(result) => result,
+            Err(_) => {
+                self.abandon().await;
+                Err(anyhow::anyhow!("Request timeout"))
+            }
+        }
+    }
+
+    /// 不计超时地等待响应，由调用方决定何时放弃（如流式回复按空闲时间计算超时）
+    pub async fn response(&mut self) -> anyhow::Result<serde_json::Value> {
+        let result = (&mut self.rx)
+            .await
+            .map_err(|e| anyhow::anyhow!("Channel error: {}", e))?;
+        result.map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// 放弃等待：移除请求并通知 GoClaw 停止处理
+    pub async fn abandon(self) {
+        if self.pending_requests.lock().unwrap().remove(&self.id).is_none() {
+            return;
+        }
+        if let Err(e) = send_cancel(&self.ws_connection, &self.id).await {
+            println!("[GoClaw] Failed to send cancel notification for {}: {}", self.id, e);
+        }
+        println!("[GoClaw] Request abandoned: {}", self.id);
+    }
+}
+
+async fn send_text(ws_connection: &SharedConnection, text: String) -> anyhow::Result<()> {
+    let ws_conn = ws_connection.lock().await;
+    if let Some(conn) = &*ws_conn {
+        let mut write = conn.write.lock().await;
+        write.send(Message::Text(text)).await?;
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("WebSocket not connected"))
+    }
+}
+
+async fn send_cancel(ws_connection: &SharedConnection, id: &str) -> anyhow::Result<()> {
+    let notification = JsonRpcNotification {
+        jsonrpc: "2.0".to_string(),
+        method: CANCEL_REQUEST_METHOD.to_string(),
+        params: serde_json::json!({ "id": id }),
+    };
+    send_text(ws_connection, serde_json::to_string(&notification)?).await
+}
 
 struct WebSocketConnection {
     write: Arc<
@@ -112,7 +196,7 @@ pub struct GoClawManager {
     process_running: Arc<Mutex<bool>>,
     pending_requests: PendingRequests,
     request_id: Arc<Mutex<u64>>,
-    ws_connection: Arc<AsyncMutex<Option<WebSocketConnection>>>,
+    ws_connection: SharedConnection,
     notification_callback: NotificationCallback,
     last_error: Arc<Mutex<Option<String>>>,
     logger: Arc<Mutex<Logger>>,
@@ -750,6 +834,15 @@ impl GoClawManager {
         method: String,
         params: serde_json::Value,
     ) -> anyhow::Result<serde_json::Value> {
+        self.start_request(method, params).await?.wait().await
+    }
+
+    /// 发送请求但不等待响应，返回的 PendingResponse 可在释放锁之后再等待
+    pub async fn start_request(
+        &self,
+        method: String,
+        params: serde_json::Value,
+    ) -> anyhow::Result<PendingResponse> {
         self.connect_websocket().await?;
 
         let id = self.next_request_id();
@@ -764,26 +857,52 @@ impl GoClawManager {
         let (tx, rx) = oneshot::channel();
         {
             let mut pending = self.pending_requests.lock().unwrap();
-            pending.insert(id, tx);
+            pending.insert(id.clone(), tx);
         }
 
-        let ws_conn = self.ws_connection.lock().await;
-        if let Some(conn) = &*ws_conn {
-            let mut write = conn.write.lock().await;
-            write
-                .send(Message::Text(serde_json::to_string(&request)?))
-                .await?;
-        } else {
-            return Err(anyhow::anyhow!("WebSocket not connected"));
+        if let Err(e) = send_text(&self.ws_connection, serde_json::to_string(&request)?).await {
+            self.pending_requests.lock().unwrap().remove(&id);
+            return Err(e);
         }
-        drop(ws_conn);
 
-        let result = tokio::time::timeout(Duration::from_secs(30), rx)
-            .await
-            .map_err(|_| anyhow::anyhow!("Request timeout"))?
-            .map_err(|e| anyhow::anyhow!("Channel error: {}", e))?;
+        let timeout_ms = self.config.lock().unwrap().request_timeout_ms;
+        Ok(PendingResponse {
+            id,
+            rx,
+            timeout: Duration::from_millis(timeout_ms),
+            pending_requests: self.pending_requests.clone(),
+            ws_connection: self.ws_connection.clone(),
+        })
+    }
 
-        result.map_err(|e| anyhow::anyhow!(e))
+    /// 取消进行中的请求：立即让等待方返回错误，并通知 GoClaw 停止处理。
+    /// 请求已完成或不存在时返回 false
+    pub async fn cancel_request(&self, id: &str) -> anyhow::Result<bool> {
+        let tx = match self.pending_requests.lock().unwrap().remove(id) {
+            Some(tx) => tx,
+            None => return Ok(false),
+        };
+        let _ = tx.send(Err("Request cancelled".to_string()));
+
+        if let Err(e) = send_cancel(&self.ws_connection, id).await {
+            println!("[GoClaw] Failed to send cancel notification for {}: {}", id, e);
+        }
+        println!("[GoClaw] Request cancelled: {}", id);
+        Ok(true)
+    }
+
+    /// 发送不需要响应的通知，如取消请求和工具审批结果
+    pub async fn send_notification(
+        &self,
+        method: String,
+        params: serde_json::Value,
+    ) -> anyhow::Result<()> {
+        let notification = JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method,
+            params,
+        };
+        send_text(&self.ws_connection, serde_json::to_string(&notification)?).await
     }
 
     pub async fn send_message(&self, content: String) -> anyhow::Result<serde_json::Value> {
@@ -6,6 +6,19 @@ extern crate tauri_plugin_dialog;
 extern crate open;
 
 mod cowork;
+mod cowork_approvals;
+mod cowork_attachments;
+mod cowork_context;
+mod cowork_documents;
+mod cowork_export;
+mod cowork_images;
+mod cowork_memory;
+mod cowork_memory_sync;
+mod cowork_prompt;
+mod cowork_snapshots;
+mod cowork_tools;
+mod cowork_usage;
+mod cowork_workspaces;
 mod crypto;
 mod database;
 #[cfg(not(target_os = "android"))]
@@ -35,6 +48,12 @@ mod update_manager;
 #[path = "update_manager_android.rs"]
 mod update_manager;
 
+use cowork_approvals::{ApprovalRequest, PermissionRule};
+use cowork_attachments::Attachment;
+use cowork_images::ImageInput;
+use cowork_snapshots::{RevertedFile, SnapshotTurn};
+use cowork_usage::{PriceTable, UsageSummary};
+use cowork_workspaces::WorkspaceInstructions;
 use std::sync::Arc;
 use tauri::{AppHandle, Manager, State};
 use tokio::sync::Mutex as TokioMutex;
@@ -304,6 +323,200 @@ async fn cowork_list_sessions(state: State<'_, AppState>) -> Result<Vec<CoworkSe
     manager.list_sessions().await.map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn cowork_list_sessions_page(
+    tag: Option<String>,
+    folder_id: Option<String>,
+    status: Option<String>,
+    archived: Option<bool>,
+    cwd: Option<String>,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+    state: State<'_, AppState>,
+) -> Result<CoworkSessionPage, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .list_sessions_page(tag, folder_id, status, archived, cwd, start_time, end_time, limit, offset)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_list_workspaces(state: State<'_, AppState>) -> Result<Vec<CoworkWorkspace>, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.list_workspaces().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_list_workspace_sessions(
+    workspace: String,
+    archived: Option<bool>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+    state: State<'_, AppState>,
+) -> Result<CoworkSessionPage, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .list_workspace_sessions(workspace, archived, limit, offset)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_get_workspace_instructions(
+    workspace: String,
+    state: State<'_, AppState>,
+) -> Result<Option<WorkspaceInstructions>, String> {
+    let manager = state.cowork_manager.lock().await;
+    Ok(manager.workspace_instructions(workspace).await)
+}
+
+#[tauri::command]
+async fn cowork_set_session_tags(
+    id: String,
+    tags: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<CoworkSession, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.set_session_tags(id, tags).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_list_tags(state: State<'_, AppState>) -> Result<Vec<CoworkTag>, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.list_tags().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_set_session_archived(
+    id: String,
+    archived: bool,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .set_session_archived(id, archived)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_set_session_folder(
+    id: String,
+    folder_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .set_session_folder(id, folder_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_list_folders(state: State<'_, AppState>) -> Result<Vec<CoworkFolder>, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.list_folders().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_create_folder(name: String, state: State<'_, AppState>) -> Result<CoworkFolder, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.create_folder(name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_rename_folder(
+    id: String,
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.rename_folder(id, name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_delete_folder(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.delete_folder(id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_list_templates(state: State<'_, AppState>) -> Result<Vec<CoworkTemplate>, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.list_templates().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_create_template(
+    name: String,
+    cwd: Option<String>,
+    system_prompt: Option<String>,
+    execution_mode: Option<String>,
+    active_skill_ids: Option<String>,
+    context_budget: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<CoworkTemplate, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .create_template(name, cwd, system_prompt, execution_mode, active_skill_ids, context_budget)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_update_template(
+    id: String,
+    name: Option<String>,
+    cwd: Option<String>,
+    system_prompt: Option<String>,
+    execution_mode: Option<String>,
+    active_skill_ids: Option<String>,
+    context_budget: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<CoworkTemplate, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .update_template(id, name, cwd, system_prompt, execution_mode, active_skill_ids, context_budget)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_delete_template(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.delete_template(id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_save_session_as_template(
+    session_id: String,
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<CoworkTemplate, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .save_session_as_template(session_id, name)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_create_session_from_template(
+    template_id: String,
+    title: Option<String>,
+    cwd: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<CoworkSession, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .create_session_from_template(template_id, title, cwd)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn cowork_create_session(
     title: String,
@@ -334,6 +547,7 @@ async fn cowork_update_session(
     cwd: Option<String>,
     system_prompt: Option<String>,
     execution_mode: Option<String>,
+    active_skill_ids: Option<String>,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
     let manager = state.cowork_manager.lock().await;
@@ -346,11 +560,25 @@ async fn cowork_update_session(
             cwd,
             system_prompt,
             execution_mode,
+            active_skill_ids,
         )
         .await
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn cowork_set_context_budget(
+    session_id: String,
+    budget: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .set_context_budget(session_id, budget)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn cowork_update_message(
     session_id: String,
@@ -389,11 +617,23 @@ async fn cowork_update_user_memory(
     confidence: Option<f64>,
     status: Option<String>,
     is_explicit: Option<bool>,
+    scope: Option<String>,
+    scope_value: Option<String>,
+    expires_at: Option<i64>,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
     let manager = state.cowork_manager.lock().await;
     manager
-        .update_user_memory(id, text, confidence, status, is_explicit)
+        .update_user_memory(
+            id,
+            text,
+            confidence,
+            status,
+            is_explicit,
+            scope,
+            scope_value,
+            expires_at,
+        )
         .await
         .map_err(|e| e.to_string())
 }
@@ -421,6 +661,178 @@ async fn cowork_list_messages(
     manager.list_messages(session_id).await.map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn cowork_list_messages_page(
+    session_id: String,
+    before: Option<i32>,
+    after: Option<i32>,
+    limit: Option<u32>,
+    state: State<'_, AppState>,
+) -> Result<CoworkMessagePage, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .list_messages_page(session_id, before, after, limit)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_get_usage_summary(
+    group_by: Option<String>,
+    session_id: Option<String>,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<UsageSummary, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .usage_summary(
+            group_by.unwrap_or_else(|| "session".to_string()),
+            session_id,
+            start_time,
+            end_time,
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_get_price_table(state: State<'_, AppState>) -> Result<PriceTable, String> {
+    let manager = state.cowork_manager.lock().await;
+    Ok(manager.price_table().await)
+}
+
+#[tauri::command]
+async fn cowork_set_price_table(prices: PriceTable, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.set_price_table(prices).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_search(
+    query: String,
+    session_id: Option<String>,
+    message_types: Option<Vec<String>>,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+    state: State<'_, AppState>,
+) -> Result<Vec<CoworkSearchHit>, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .search(
+            query,
+            session_id,
+            message_types.unwrap_or_default(),
+            start_time,
+            end_time,
+            limit,
+            offset,
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_fork_session(
+    session_id: String,
+    sequence: i32,
+    title: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<CoworkSession, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .fork_session(session_id, sequence, title)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_export_session(
+    session_id: String,
+    format: String,
+    path: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let manager = state.cowork_manager.lock().await;
+    let content = manager
+        .export_session(session_id, format)
+        .await
+        .map_err(|e| e.to_string())?;
+    // 指定路径时直接写入文件
+    if let Some(path) = path {
+        std::fs::write(&path, &content).map_err(|e| e.to_string())?;
+    }
+    Ok(content)
+}
+
+#[tauri::command]
+async fn cowork_import_session(
+    data: Option<String>,
+    path: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<CoworkSession, String> {
+    let data = match (data, path) {
+        (Some(data), _) => data,
+        (None, Some(path)) => std::fs::read_to_string(&path).map_err(|e| e.to_string())?,
+        (None, None) => return Err("Either data or path is required".to_string()),
+    };
+    let manager = state.cowork_manager.lock().await;
+    manager.import_session(data).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_store_attachment(
+    data: Option<String>,
+    path: Option<String>,
+    name: Option<String>,
+    mime_type: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Attachment, String> {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+    let (bytes, default_name) = match (data, path) {
+        (Some(data), _) => (BASE64.decode(data.trim()).map_err(|e| e.to_string())?, None),
+        (None, Some(path)) => {
+            let bytes = std::fs::read(&path).map_err(|e| e.to_string())?;
+            let file_name = std::path::Path::new(&path)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string());
+            (bytes, file_name)
+        }
+        (None, None) => return Err("Either data or path is required".to_string()),
+    };
+    let name = name
+        .or(default_name)
+        .unwrap_or_else(|| "attachment".to_string());
+    let manager = state.cowork_manager.lock().await.clone();
+    manager
+        .store_attachment(name, bytes, mime_type)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_read_attachment(sha256: String, state: State<'_, AppState>) -> Result<String, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.read_attachment(sha256).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_list_session_thumbnails(
+    state: State<'_, AppState>,
+) -> Result<std::collections::HashMap<String, String>, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.session_thumbnails().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_gc_attachments(state: State<'_, AppState>) -> Result<usize, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.gc_attachments().await.map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn cowork_add_message(
     session_id: String,
@@ -436,9 +848,55 @@ async fn cowork_add_message(
 }
 
 #[tauri::command]
-async fn cowork_list_user_memories(state: State<'_, AppState>) -> Result<Vec<UserMemory>, String> {
+async fn cowork_list_user_memories(
+    scope: Option<String>,
+    session_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<UserMemory>, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .list_user_memories_filtered(scope, session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_extract_user_memories(
+    session_id: String,
+    message_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<UserMemory>, String> {
+    let manager = state.cowork_manager.lock().await.clone();
+    manager
+        .extract_memories(session_id, message_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_consolidate_user_memories(
+    state: State<'_, AppState>,
+) -> Result<serde_json::Value, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.consolidate_memories().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_sync_memory_file(state: State<'_, AppState>) -> Result<MemorySyncReport, String> {
+    let manager = state.cowork_manager.lock().await.clone();
+    manager.sync_memory_file().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_list_user_memory_sources(
+    memory_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<serde_json::Value>, String> {
     let manager = state.cowork_manager.lock().await;
-    manager.list_user_memories().await.map_err(|e| e.to_string())
+    manager
+        .list_user_memory_sources(memory_id)
+        .await
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -446,11 +904,14 @@ async fn cowork_create_user_memory(
     text: String,
     confidence: f64,
     is_explicit: bool,
+    scope: Option<String>,
+    scope_value: Option<String>,
+    expires_at: Option<i64>,
     state: State<'_, AppState>,
 ) -> Result<UserMemory, String> {
     let manager = state.cowork_manager.lock().await;
     manager
-        .create_user_memory(text, confidence, is_explicit)
+        .create_user_memory(text, confidence, is_explicit, scope, scope_value, expires_at)
         .await
         .map_err(|e| e.to_string())
 }
@@ -459,11 +920,154 @@ async fn cowork_create_user_memory(
 async fn cowork_send_message(
     session_id: String,
     content: String,
+    attachments: Option<Vec<Attachment>>,
+    images: Option<Vec<ImageInput>>,
+    state: State<'_, AppState>,
+) -> Result<CoworkMessage, String> {
+    // 流式回复耗时较长，克隆后释放锁，避免阻塞其他 cowork 命令
+    let manager = state.cowork_manager.lock().await.clone();
+    let mut attachments = attachments.unwrap_or_default();
+    if let Some(images) = images.filter(|images| !images.is_empty()) {
+        attachments.extend(manager.store_images(images).await.map_err(|e| e.to_string())?);
+    }
+    manager
+        .send_message(session_id, content, attachments)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_cancel(session_id: String, state: State<'_, AppState>) -> Result<bool, String> {
+    let manager = state.cowork_manager.lock().await.clone();
+    manager.cancel(session_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_list_snapshot_turns(
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<SnapshotTurn>, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.list_snapshot_turns(session_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_revert_turn(
+    session_id: String,
+    turn_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<RevertedFile>, String> {
+    let manager = state.cowork_manager.lock().await.clone();
+    manager.revert_turn(session_id, turn_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_list_pending_approvals(
+    session_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<ApprovalRequest>, String> {
+    let manager = state.cowork_manager.lock().await.clone();
+    Ok(manager.list_pending_approvals(session_id))
+}
+
+#[tauri::command]
+async fn cowork_respond_approval(
+    approval_id: String,
+    allow: bool,
+    scope: Option<String>,
+    command_pattern: Option<String>,
+    path_prefix: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Option<PermissionRule>, String> {
+    let manager = state.cowork_manager.lock().await.clone();
+    manager
+        .respond_approval(approval_id, allow, scope, command_pattern, path_prefix)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_list_permission_rules(
+    session_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<PermissionRule>, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.list_permission_rules(session_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_add_permission_rule(
+    tool: String,
+    command_pattern: Option<String>,
+    path_prefix: Option<String>,
+    session_id: Option<String>,
+    allow: bool,
+    state: State<'_, AppState>,
+) -> Result<PermissionRule, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .add_permission_rule(tool, command_pattern, path_prefix, session_id, allow)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_delete_permission_rule(id: String, state: State<'_, AppState>) -> Result<bool, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager.delete_permission_rule(id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_list_approval_audit(
+    session_id: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<Vec<serde_json::Value>, String> {
+    let manager = state.cowork_manager.lock().await;
+    manager
+        .list_approval_audit(session_id, limit, offset)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_edit_and_resend(
+    session_id: String,
+    message_id: String,
+    content: String,
+    archive: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<CoworkMessage, String> {
+    let manager = state.cowork_manager.lock().await.clone();
+    manager
+        .edit_and_resend(session_id, message_id, content, archive.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_regenerate(
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<CoworkMessage, String> {
+    let manager = state.cowork_manager.lock().await.clone();
+    manager
+        .regenerate_reply(session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cowork_select_alternate(
+    session_id: String,
+    message_id: String,
+    index: usize,
     state: State<'_, AppState>,
 ) -> Result<CoworkMessage, String> {
     let manager = state.cowork_manager.lock().await;
     manager
-        .send_message(session_id, content)
+        .select_alternate(session_id, message_id, index)
         .await
         .map_err(|e| e.to_string())
 }
@@ -1032,8 +1636,10 @@ pub fn run() {
             println!("[skills] User skills dir: {:?}", skills_dir);
             println!("[skills] Bundled skills dir: {:?}", bundled_skills_dir);
 
-            let skills_manager = SkillsManager::new(skills_dir, skills_config_path)
-                .with_bundled_skills(bundled_skills_dir);
+            let skills_manager = Arc::new(TokioMutex::new(
+                SkillsManager::new(skills_dir, skills_config_path)
+                    .with_bundled_skills(bundled_skills_dir),
+            ));
 
             let app_data_dir = app
                 .path()
@@ -1055,9 +1661,16 @@ pub fn run() {
                 eprintln!("[System] Failed to setup system tray: {}", e);
             }
 
-            let goclaw_manager = Arc::new(TokioMutex::new(GoClawManager::new(kv_store.clone(), logger.clone())));
+            let goclaw = GoClawManager::new(kv_store.clone(), logger.clone());
             let mut cowork_manager = CoworkManager::new(database_arc.clone());
+            goclaw.set_notification_callback(cowork_manager.notification_handler());
+            let goclaw_manager = Arc::new(TokioMutex::new(goclaw));
             cowork_manager.set_goclaw_manager(goclaw_manager.clone());
+            cowork_manager.set_skills_manager(skills_manager.clone());
+            cowork_manager.set_app_handle(app.handle().clone());
+            cowork_manager.set_data_dir(app_data_dir.clone());
+            tauri::async_runtime::spawn(cowork_manager.clone().run_memory_file_watcher());
+            tauri::async_runtime::spawn(cowork_manager.clone().run_workspace_instructions_watcher());
 
             let scheduler = Scheduler::new(database_arc.clone());
             let tuptup_service = Arc::new(TokioMutex::new(TuptupService::new()));
@@ -1067,7 +1680,7 @@ pub fn run() {
             app.manage(AppState {
                 storage,
                 kv_store,
-                skills_manager: Arc::new(TokioMutex::new(skills_manager)),
+                skills_manager,
                 database: database_arc,
                 system_manager: Arc::new(TokioMutex::new(system_manager)),
                 goclaw_manager,
@@ -1127,10 +1740,37 @@ pub fn run() {
             goclaw_send_message,
             goclaw_list_sessions,
             cowork_list_sessions,
+            cowork_list_sessions_page,
+            cowork_list_workspaces,
+            cowork_list_workspace_sessions,
+            cowork_get_workspace_instructions,
+            cowork_set_session_tags,
+            cowork_list_tags,
+            cowork_set_session_archived,
+            cowork_set_session_folder,
+            cowork_list_folders,
+            cowork_create_folder,
+            cowork_rename_folder,
+            cowork_delete_folder,
             cowork_create_session,
+            cowork_list_templates,
+            cowork_create_template,
+            cowork_update_template,
+            cowork_delete_template,
+            cowork_save_session_as_template,
+            cowork_create_session_from_template,
             cowork_delete_session,
             cowork_update_session,
+            cowork_set_context_budget,
             cowork_list_messages,
+            cowork_list_messages_page,
+            cowork_search,
+            cowork_get_usage_summary,
+            cowork_get_price_table,
+            cowork_set_price_table,
+            cowork_fork_session,
+            cowork_export_session,
+            cowork_import_session,
             cowork_add_message,
             cowork_update_message,
             cowork_list_user_memories,
@@ -1138,9 +1778,29 @@ pub fn run() {
             cowork_update_user_memory,
             cowork_delete_user_memory,
             cowork_get_user_memory_stats,
+            cowork_extract_user_memories,
+            cowork_consolidate_user_memories,
+            cowork_sync_memory_file,
+            cowork_list_user_memory_sources,
             cowork_get_config,
             cowork_set_config,
             cowork_send_message,
+            cowork_store_attachment,
+            cowork_read_attachment,
+            cowork_list_session_thumbnails,
+            cowork_gc_attachments,
+            cowork_cancel,
+            cowork_list_pending_approvals,
+            cowork_respond_approval,
+            cowork_list_permission_rules,
+            cowork_add_permission_rule,
+            cowork_delete_permission_rule,
+            cowork_list_approval_audit,
+            cowork_list_snapshot_turns,
+            cowork_revert_turn,
+            cowork_edit_and_resend,
+            cowork_regenerate,
+            cowork_select_alternate,
             logger_log,
             logger_debug,
             logger_info,
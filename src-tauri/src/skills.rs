@@ -584,8 +584,23 @@ impl SkillsManager {
     }
 
     pub async fn build_auto_routing_prompt(&self) -> anyhow::Result<String> {
+        self.build_auto_routing_prompt_for(None).await
+    }
+
+    /// 构建技能路由提示词，`skill_ids` 为 Some 时只包含其中已启用的技能
+    pub async fn build_auto_routing_prompt_for(
+        &self,
+        skill_ids: Option<&[String]>,
+    ) -> anyhow::Result<String> {
         let skills = self.load_skills().await?;
-        let enabled_skills: Vec<_> = skills.into_iter().filter(|s| s.enabled).collect();
+        let enabled_skills: Vec<_> = skills
+            .into_iter()
+            .filter(|s| s.enabled)
+            .filter(|s| match skill_ids {
+                Some(ids) => ids.contains(&s.id),
+                None => true,
+            })
+            .collect();
 
         if enabled_skills.is_empty() {
             return Ok(String::new());